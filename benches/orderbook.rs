@@ -0,0 +1,101 @@
+//! Order book write and read costs. Run with `cargo bench --bench orderbook`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use websocket_agg_orders::api::{Exchange, Side};
+use websocket_agg_orders::{ExchangePrice, OrderBook};
+
+/// Distinct prices per venue and side the messages walk over, so the books end up deep.
+const PRICE_RANGE: u64 = 5_000;
+
+/// A 40-level depth message for `exchange`: 20 bids below 100 000 and 20 asks above it,
+/// moved along by `i`. Every third level is a removal.
+fn message(i: u64, exchange: Exchange) -> Vec<ExchangePrice> {
+    (0..40)
+        .map(|level| {
+            let offset = (i * 7 + level * 13) % PRICE_RANGE;
+            let (side, price) = if level < 20 {
+                (Side::Buy, 100_000 - 1 - offset)
+            } else {
+                (Side::Sell, 100_000 + offset)
+            };
+            ExchangePrice::new(exchange, price, (i + level) % 3, side)
+        })
+        .collect()
+}
+
+/// A book with two venues whose sides hold a few thousand levels each.
+fn deep_book() -> OrderBook {
+    let ob = OrderBook::new("BTC-USDT".to_string());
+    for i in 0..PRICE_RANGE {
+        ob.update_price_levels(&message(i, Exchange::Binance));
+        ob.update_price_levels(&message(i, Exchange::Okx));
+    }
+    ob
+}
+
+/// One 40-level message applied in one call against one level at a time.
+fn updates(c: &mut Criterion) {
+    let ob = deep_book();
+    let mut i = 0;
+    let mut next_message = || {
+        i += 1;
+        message(i, Exchange::Binance)
+    };
+    let mut group = c.benchmark_group("update_price_levels");
+    group.throughput(Throughput::Elements(40));
+    group.bench_function("batched", |b| {
+        b.iter_batched(
+            &mut next_message,
+            |levels| ob.update_price_levels(&levels),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("per_level", |b| {
+        b.iter_batched(
+            &mut next_message,
+            |levels| {
+                for level in levels {
+                    ob.update_price_level(level);
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+/// `snapshot(10)` on deep books, alone and while two threads write as fast as they can.
+fn snapshot_reads(c: &mut Criterion) {
+    let ob = Arc::new(deep_book());
+    let mut group = c.benchmark_group("snapshot");
+    group.bench_function("idle", |b| b.iter(|| black_box(ob.snapshot(10))));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let writers: Vec<_> = [Exchange::Binance, Exchange::Okx]
+        .into_iter()
+        .map(|exchange| {
+            let (ob, stop) = (ob.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut i = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    i += 1;
+                    ob.update_price_levels(&message(i, exchange));
+                }
+            })
+        })
+        .collect();
+    group.bench_function("under_writes", |b| b.iter(|| black_box(ob.snapshot(10))));
+    stop.store(true, Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, updates, snapshot_reads);
+criterion_main!(benches);
@@ -1,8 +1,14 @@
+// With the `grpc` feature, generates the `OrderbookAggregator` server from
+// proto/orderbook.proto (needs `protoc`; set PROTOC if it is not on PATH).
+// The proto path is relative to the crate root, so keep the file there when moving things.
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::configure()
-        .build_server(true)
-        .build_client(false)
-        .compile_protos(&["proto/orderbook.proto"], &["proto"])?;
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/orderbook.proto");
+        tonic_build::configure()
+            .build_server(true)
+            .build_client(false)
+            .compile_protos(&["proto/orderbook.proto"], &["proto"])?;
+    }
     Ok(())
 }
-
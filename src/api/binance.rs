@@ -1,180 +1,1293 @@
-use futures_util::StreamExt;
-use tokio::sync::mpsc;
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::tungstenite::Message;
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::Value;
+use tokio::sync::oneshot;
 use tracing::instrument;
 
-use crate::api::{ExchangePrice, Side, TradingPair};
-use crate::util::{parse_price_cents, parse_quantity_smallest_unit};
+use crate::api::{
+    ClientError, Exchange, ExchangeClient, ExchangePrice, PairContext, ReconnectConfig, Side,
+    SideModes, TradingPair, UpdateMode, UpdateSender, rest,
+};
+use crate::metrics::METRICS;
+use crate::util::{parse_price_scaled, parse_quantity_smallest_unit};
+
+const BINANCE_WS_URL: &str = "wss://stream.binance.com:9443/ws";
+const BINANCE_REST_URL: &str = "https://api.binance.com";
+
+/// Levels per side requested for the REST snapshot a diff stream starts from (the maximum).
+const SNAPSHOT_LIMIT: u32 = 5000;
+
+/// Diff updates held while the snapshot is fetched; more means the fetch is stuck.
+const MAX_BUFFERED_UPDATES: usize = 1000;
+
+/// Which Binance depth stream to subscribe to, named like the stream suffix: `depth20@100ms`
+/// is a partial book of the top 20 levels every 100ms, `depth@100ms` the diff stream.
+/// Spot streams push every 1000ms (no `@...ms` suffix) or every 100ms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinanceDepthStream {
+    /// Levels per side of a partial book (5, 10 or 20), or `None` for the diff stream.
+    levels: Option<u32>,
+    /// Push interval in milliseconds (1000 or 100).
+    speed_ms: u32,
+}
+
+impl BinanceDepthStream {
+    pub fn new(levels: Option<u32>, speed_ms: u32) -> Result<Self, String> {
+        if let Some(levels) = levels
+            && ![5, 10, 20].contains(&levels)
+        {
+            return Err(format!(
+                "Binance partial books have 5, 10 or 20 levels, not {levels}"
+            ));
+        }
+        if ![100, 1000].contains(&speed_ms) {
+            return Err(format!(
+                "Binance spot depth streams update every 100ms or 1000ms, not {speed_ms}ms"
+            ));
+        }
+        Ok(BinanceDepthStream { levels, speed_ms })
+    }
+
+    /// Whether this is the diff stream, which only carries changes.
+    pub fn is_diff(&self) -> bool {
+        self.levels.is_none()
+    }
+}
+
+impl Default for BinanceDepthStream {
+    fn default() -> Self {
+        BinanceDepthStream {
+            levels: Some(20),
+            speed_ms: 100,
+        }
+    }
+}
+
+impl std::fmt::Display for BinanceDepthStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "depth")?;
+        if let Some(levels) = self.levels {
+            write!(f, "{levels}")?;
+        }
+        if self.speed_ms != 1000 {
+            write!(f, "@{}ms", self.speed_ms)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for BinanceDepthStream {
+    type Err = String;
+
+    /// Parse a stream suffix such as `depth20@100ms`, `depth5` or `depth@100ms`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("unknown Binance depth stream '{s}'");
+        let (depth, speed) = s.split_once('@').unwrap_or((s, "1000ms"));
+        let levels = depth.strip_prefix("depth").ok_or_else(invalid)?;
+        let levels = match levels {
+            "" => None,
+            levels => Some(levels.parse().map_err(|_| invalid())?),
+        };
+        let speed_ms = speed
+            .strip_suffix("ms")
+            .and_then(|ms| ms.parse().ok())
+            .ok_or_else(invalid)?;
+        BinanceDepthStream::new(levels, speed_ms)
+    }
+}
+
+/// Which Binance stream the client reads a pair's book from: a depth stream, or
+/// `bookTicker`, which pushes only the best bid and ask (with sizes) whenever either changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinanceStream {
+    Depth(BinanceDepthStream),
+    BookTicker,
+}
+
+impl BinanceStream {
+    /// Whether this is the diff stream, which only carries changes.
+    pub fn is_diff(&self) -> bool {
+        matches!(self, BinanceStream::Depth(depth) if depth.is_diff())
+    }
+}
+
+impl Default for BinanceStream {
+    fn default() -> Self {
+        BinanceStream::Depth(BinanceDepthStream::default())
+    }
+}
+
+impl std::fmt::Display for BinanceStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinanceStream::Depth(depth) => depth.fmt(f),
+            BinanceStream::BookTicker => write!(f, "bookTicker"),
+        }
+    }
+}
+
+impl std::str::FromStr for BinanceStream {
+    type Err = String;
+
+    /// Parse `bookTicker` or a depth stream suffix such as `depth20@100ms`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bookTicker" => Ok(BinanceStream::BookTicker),
+            depth => depth.parse().map(BinanceStream::Depth),
+        }
+    }
+}
+
+/// Update ids seen on one depth stream session, used to catch dropped or reordered events.
+#[derive(Debug, Default)]
+pub struct DepthSequence {
+    /// `lastUpdateId` of the last snapshot or `u` of the last applied update.
+    last_update_id: Option<u64>,
+    /// Whether `last_update_id` came from a snapshot, which the next update may overlap.
+    after_snapshot: bool,
+}
 
-const BINANCE_WS_BASE_URL: &str = "wss://stream.binance.com:9443/ws";
+/// What to do with a message given its update ids.
+#[derive(Debug, PartialEq, Eq)]
+enum SequenceCheck {
+    Apply,
+    /// Already covered by an earlier snapshot/update.
+    Stale,
+}
+
+/// A depth update skipped ids, so the local view of the venue can no longer be trusted.
+#[derive(Debug)]
+struct SequenceGap {
+    expected: u64,
+    first_update_id: u64,
+}
+
+impl std::fmt::Display for SequenceGap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "depth update gap: expected U={}, got U={}",
+            self.expected, self.first_update_id
+        )
+    }
+}
+
+impl std::error::Error for SequenceGap {}
+
+/// Where a diff stream is in Binance's local order book procedure: buffer updates, fetch the
+/// REST snapshot, then replay the buffered updates on top of it.
+#[derive(Debug, Default)]
+enum SnapshotSync {
+    /// No diff update seen yet on this connection.
+    #[default]
+    Idle,
+    /// The snapshot is on its way; updates (with their `received_at`) wait in `buffered`.
+    Fetching {
+        snapshot: oneshot::Receiver<Result<String, ClientError>>,
+        buffered: Vec<(u64, Value)>,
+    },
+    /// The snapshot has been applied; updates apply directly.
+    Synced,
+}
+
+/// Per-connection state of one depth stream.
+#[derive(Debug, Default)]
+pub struct DepthSession {
+    sequence: DepthSequence,
+    sync: SnapshotSync,
+}
+
+impl DepthSession {
+    /// Handle one diff-stream update. Nothing is emitted until the REST snapshot (fetched on
+    /// the first update, for its `s` symbol) has arrived and been applied.
+    fn on_diff(
+        &mut self,
+        rest_url: &str,
+        depth: Value,
+        received_at: u64,
+        pair: &PairContext,
+        out: &mut Vec<ExchangePrice>,
+    ) -> Result<(), ClientError> {
+        let (snapshot, buffered) = match &mut self.sync {
+            SnapshotSync::Synced => {
+                return parse_depth(&depth, received_at, pair, &mut self.sequence, out);
+            }
+            SnapshotSync::Idle => {
+                let Some(symbol) = depth.get("s").and_then(|s| s.as_str()) else {
+                    return Ok(());
+                };
+                self.sync = SnapshotSync::Fetching {
+                    snapshot: spawn_snapshot_fetch(rest_url, symbol),
+                    buffered: vec![(received_at, depth)],
+                };
+                return Ok(());
+            }
+            SnapshotSync::Fetching { snapshot, buffered } => (snapshot, buffered),
+        };
+
+        buffered.push((received_at, depth));
+        if buffered.len() > MAX_BUFFERED_UPDATES {
+            return Err(snapshot_error(format!(
+                "{} updates buffered before the snapshot arrived",
+                buffered.len()
+            )));
+        }
+        let text = match snapshot.try_recv() {
+            Err(oneshot::error::TryRecvError::Empty) => return Ok(()),
+            Err(oneshot::error::TryRecvError::Closed) => {
+                return Err(snapshot_error("snapshot fetch stopped"));
+            }
+            Ok(result) => result?,
+        };
+        let snapshot: Value = serde_json::from_str(&text).map_err(snapshot_error)?;
+
+        let buffered = std::mem::take(buffered);
+        if !self.apply_snapshot(&snapshot, &buffered, received_at, pair, out)? {
+            // Older than the first buffered update, so it cannot be bridged: fetch again.
+            let symbol = buffered[0]
+                .1
+                .get("s")
+                .and_then(|s| s.as_str())
+                .unwrap_or_default();
+            tracing::debug!(
+                symbol,
+                "binance snapshot predates buffered updates; refetching"
+            );
+            self.sync = SnapshotSync::Fetching {
+                snapshot: spawn_snapshot_fetch(rest_url, symbol),
+                buffered,
+            };
+        }
+        Ok(())
+    }
+
+    /// Apply `snapshot` (replacing Binance's levels) and then the updates buffered while it
+    /// was fetched; updates it already covers are skipped. Returns `false`, applying nothing,
+    /// when the snapshot is older than the first buffered update. Otherwise the session is
+    /// synced even if some levels fail to parse: those are reported, and only a sequence gap
+    /// asks for a resync.
+    fn apply_snapshot(
+        &mut self,
+        snapshot: &Value,
+        buffered: &[(u64, Value)],
+        received_at: u64,
+        pair: &PairContext,
+        out: &mut Vec<ExchangePrice>,
+    ) -> Result<bool, ClientError> {
+        let last_update_id = snapshot
+            .get("lastUpdateId")
+            .and_then(|id| id.as_u64())
+            .ok_or_else(|| snapshot_error("no lastUpdateId"))?;
+        let first_buffered = buffered
+            .first()
+            .and_then(|(_, update)| update.get("U")?.as_u64());
+        if first_buffered.is_some_and(|first| last_update_id < first) {
+            return Ok(false);
+        }
+        self.sync = SnapshotSync::Synced;
+
+        let mut level_error =
+            parse_depth(snapshot, received_at, pair, &mut self.sequence, out).err();
+        for (received_at, update) in buffered {
+            match parse_depth(update, *received_at, pair, &mut self.sequence, out) {
+                Err(error @ ClientError::Protocol { .. }) => return Err(error),
+                Err(error) => {
+                    level_error.get_or_insert(error);
+                }
+                Ok(()) => {}
+            }
+        }
+        level_error.map_or(Ok(true), Err)
+    }
+}
+
+fn snapshot_error(reason: impl std::fmt::Display) -> ClientError {
+    ClientError::Snapshot {
+        exchange: Exchange::Binance,
+        reason: reason.to_string(),
+    }
+}
+
+/// Fetch the REST depth snapshot for `symbol` (e.g. `BTCUSDT`) from `rest_url`, as the JSON
+/// text Binance returns: `{"lastUpdateId":..,"bids":[..],"asks":[..]}`.
+pub async fn fetch_snapshot(rest_url: &str, symbol: &str) -> Result<String, ClientError> {
+    let url = format!("{rest_url}/api/v3/depth?symbol={symbol}&limit={SNAPSHOT_LIMIT}");
+    tracing::info!(%url, "fetching binance depth snapshot");
+    rest::get(&url).await.map_err(snapshot_error)
+}
+
+/// Fetch the snapshot on its own task so the stream keeps being read (and buffered) meanwhile.
+fn spawn_snapshot_fetch(
+    rest_url: &str,
+    symbol: &str,
+) -> oneshot::Receiver<Result<String, ClientError>> {
+    let (tx, rx) = oneshot::channel();
+    let (rest_url, symbol) = (rest_url.to_string(), symbol.to_string());
+    tokio::spawn(async move {
+        let _ = tx.send(fetch_snapshot(&rest_url, &symbol).await);
+    });
+    rx
+}
+
+impl DepthSequence {
+    /// Record a snapshot's `lastUpdateId`; every snapshot resets the sequence.
+    fn on_snapshot(&mut self, last_update_id: u64) {
+        self.last_update_id = Some(last_update_id);
+        self.after_snapshot = true;
+    }
+
+    /// Validate an update spanning ids `first..=last` (`U`/`u`).
+    /// After a snapshot the first update may overlap it (`U <= lastUpdateId + 1 <= u`);
+    /// after that each update must start at the previous `u + 1`.
+    fn on_update(&mut self, first: u64, last: u64) -> Result<SequenceCheck, SequenceGap> {
+        let Some(prev) = self.last_update_id else {
+            // Nothing to validate against yet; start tracking from here.
+            self.last_update_id = Some(last);
+            return Ok(SequenceCheck::Apply);
+        };
+
+        if last <= prev {
+            return Ok(SequenceCheck::Stale);
+        }
+        let in_sequence = if self.after_snapshot {
+            first <= prev + 1
+        } else {
+            first == prev + 1
+        };
+        if !in_sequence {
+            return Err(SequenceGap {
+                expected: prev + 1,
+                first_update_id: first,
+            });
+        }
+
+        self.last_update_id = Some(last);
+        self.after_snapshot = false;
+        Ok(SequenceCheck::Apply)
+    }
+}
 
 pub struct BinanceClient {
-    tx: mpsc::Sender<ExchangePrice>,
+    tx: UpdateSender,
+    /// Base URL the stream path is appended to; `BINANCE_WS_URL` overrides it in `main`.
+    pub ws_url: String,
+    /// Stream to subscribe to; `BINANCE_STREAM` picks it in `main`.
+    pub stream: BinanceStream,
+    /// REST base URL the diff stream's snapshot is fetched from; `BINANCE_REST_URL` overrides it.
+    pub rest_url: String,
+    /// Backoff used to reconnect after the stream drops.
+    pub reconnect: ReconnectConfig,
 }
 
 impl BinanceClient {
-    pub fn new(tx: mpsc::Sender<ExchangePrice>) -> Self {
-        BinanceClient { tx }
-    }
-
-    /// Listen to a specific trading pair's depth stream on Binance.
-    pub async fn listen_pair(&self, pair: TradingPair) {
-        let symbol = pair.binance_symbol();
-        let stream_name = format!("{}@depth20@100ms", symbol);
-        let url = format!("{}/{}", BINANCE_WS_BASE_URL, stream_name);
-
-        match connect_async(&url).await {
-            Ok((ws_stream, _)) => {
-                let (_write, mut read) = ws_stream.split();
-
-                let mut received_any = false;
-
-                while let Some(msg) = read.next().await {
-                    match msg {
-                        Ok(Message::Text(text)) => {
-                            received_any = true;
-                            // Capture timestamp immediately when message received
-                            let received_at = Self::current_timestamp_ms();
-                            if let Err(_e) = self.handle_message(&text, received_at).await {
-                                // Handle or log parsing / channel errors if needed
-                            }
-                        }
-                        Ok(Message::Ping(_data)) => {
-                            // Pings can be ignored here; tungstenite handles pongs on the write side.
-                        }
-                        Ok(Message::Close(_)) => {
-                            break;
-                        }
-                        Err(_e) => {
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
+    pub fn new(tx: impl Into<UpdateSender>) -> Self {
+        BinanceClient {
+            tx: tx.into(),
+            ws_url: BINANCE_WS_URL.to_string(),
+            stream: BinanceStream::default(),
+            rest_url: BINANCE_REST_URL.to_string(),
+            reconnect: ReconnectConfig::default(),
+        }
+    }
+
+    /// Listen to a specific trading pair's stream on Binance, reconnecting on drops.
+    pub async fn listen_pair(&self, pair: TradingPair) -> Result<(), ClientError> {
+        crate::api::run(self, &self.tx, pair).await
+    }
+
+    /// Listen to several pairs over one combined-stream connection, routing each message to
+    /// its pair by the `stream` name. A single pair uses its raw stream, as `listen_pair` does.
+    /// A sequence gap on any stream reconnects (and resyncs) all of them.
+    pub async fn listen_pairs(&self, pairs: Vec<TradingPair>) -> Result<(), ClientError> {
+        let Some(first) = pairs.first().cloned() else {
+            return Ok(());
+        };
+        if pairs.len() == 1 {
+            return self.listen_pair(first).await;
+        }
+        let multiplexed = Multiplexed {
+            client: self,
+            streams: pairs
+                .iter()
+                .map(|pair| (self.stream_name(pair), pair.context()))
+                .collect(),
+        };
+        // `run` wants a pair for the URL and parser context; `Multiplexed` uses its own.
+        crate::api::run(&multiplexed, &self.tx, first).await
+    }
 
-                let _ = received_any;
+    /// Partial books and tickers stand on their own; diff updates go through the snapshot sync.
+    fn handle_depth(
+        &self,
+        depth: Value,
+        received_at: u64,
+        pair: &PairContext,
+        session: &mut DepthSession,
+        out: &mut Vec<ExchangePrice>,
+    ) -> Result<(), ClientError> {
+        match self.stream {
+            BinanceStream::BookTicker => parse_book_ticker(&depth, received_at, pair, out),
+            stream if stream.is_diff() => {
+                session.on_diff(&self.rest_url, depth, received_at, pair, out)
+            }
+            BinanceStream::Depth(_) => {
+                parse_depth(&depth, received_at, pair, &mut session.sequence, out)
             }
-            Err(_e) => {}
         }
     }
 
-    /// Get the current time as milliseconds since Unix epoch.
-    fn current_timestamp_ms() -> u64 {
-        let now = std::time::SystemTime::now();
-        now.duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64
+    /// Stream name for `pair`, e.g. `ethusdt@depth20@100ms` or `ethusdt@bookTicker`.
+    fn stream_name(&self, pair: &TradingPair) -> String {
+        format!("{}@{}", pair.binance_symbol(), self.stream)
+    }
+
+    /// Stream URL for `pair`, e.g. `wss://stream.binance.com:9443/ws/ethusdt@depth20@100ms`.
+    fn stream_url(&self, pair: &TradingPair) -> String {
+        format!("{}/{}", self.ws_url, self.stream_name(pair))
+    }
+
+    /// Combined-stream URL for `streams`, e.g.
+    /// `wss://stream.binance.com:9443/stream?streams=btcusdt@depth20@100ms/ethusdt@depth20@100ms`.
+    /// The `/ws` raw-stream path of `ws_url` is swapped for `/stream`.
+    fn combined_stream_url<'a>(&self, streams: impl IntoIterator<Item = &'a String>) -> String {
+        let root = self.ws_url.strip_suffix("/ws").unwrap_or(&self.ws_url);
+        let streams: Vec<&str> = streams.into_iter().map(String::as_str).collect();
+        format!("{root}/stream?streams={}", streams.join("/"))
+    }
+}
+
+impl ExchangeClient for BinanceClient {
+    const EXCHANGE: Exchange = Exchange::Binance;
+    type Session = DepthSession;
+
+    fn ws_url(&self, pair: &TradingPair) -> String {
+        self.stream_url(pair)
+    }
+
+    /// Binance's raw streams have no subscribe step to reject: an unknown symbol's stream
+    /// just stays silent, which the reconnect loop's retry limit covers.
+    fn subscribe_msg(&self, _pair: &TradingPair) -> Option<String> {
+        None
+    }
+
+    fn reconnect(&self) -> &ReconnectConfig {
+        &self.reconnect
+    }
+
+    /// A gap in update ids, or a snapshot that could not be synced: reconnecting starts a
+    /// fresh stream, sequence and snapshot.
+    fn resyncs_on(&self, error: &ClientError) -> bool {
+        match error {
+            ClientError::Protocol { source, .. } => source.is::<SequenceGap>(),
+            ClientError::Snapshot { .. } => true,
+            _ => false,
+        }
     }
 
-    /// Parse one depth message and send price levels to the aggregator.
-    #[instrument(skip(self, text), fields(exchange = "binance"))]
-    async fn handle_message(
+    fn parse_message(
         &self,
         text: &str,
         received_at: u64,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Basic validation - avoid extremely large messages
-        if text.len() > 100_000 {
-            return Err("Message too large".into());
+        pair: &PairContext,
+        session: &mut DepthSession,
+        out: &mut Vec<ExchangePrice>,
+    ) -> Result<(), ClientError> {
+        if self.stream == BinanceStream::BookTicker || self.stream.is_diff() {
+            let (_, depth) = split_combined(parse_json(text)?);
+            return self.handle_depth(depth, received_at, pair, session, out);
         }
+        parse_binance(text, received_at, pair, &mut session.sequence, out)
+    }
+}
 
-        let depth = {
-            let _span = tracing::info_span!("parse_json").entered();
-            serde_json::from_str::<serde_json::Value>(text)?
-        };
+/// Several pairs on one combined-stream connection, keyed by stream name.
+struct Multiplexed<'a> {
+    client: &'a BinanceClient,
+    streams: BTreeMap<String, PairContext>,
+}
+
+impl ExchangeClient for Multiplexed<'_> {
+    const EXCHANGE: Exchange = Exchange::Binance;
+    /// Update ids (and snapshot sync) per stream.
+    type Session = HashMap<String, DepthSession>;
+
+    fn ws_url(&self, _pair: &TradingPair) -> String {
+        self.client.combined_stream_url(self.streams.keys())
+    }
+
+    fn subscribe_msg(&self, _pair: &TradingPair) -> Option<String> {
+        None
+    }
+
+    fn reconnect(&self) -> &ReconnectConfig {
+        &self.client.reconnect
+    }
 
-        // Binance depth stream format:
-        // - Snapshot (REST): { "lastUpdateId": ..., "bids": [[price, qty], ...], "asks": [[price, qty], ...] }
-        // - WS updates (like btcusdt@depth20@100ms):
-        //   { "e": "depthUpdate", "E": ..., "b": [[price, qty], ...], "a": [[price, qty], ...], ... }
-        let event_type = depth.get("e").and_then(|e| e.as_str());
-        let is_snapshot = depth.get("lastUpdateId").is_some();
-        let is_update = event_type == Some("depthUpdate");
+    fn resyncs_on(&self, error: &ClientError) -> bool {
+        self.client.resyncs_on(error)
+    }
 
-        // Only process depth snapshots and updates
-        if !is_snapshot && !is_update {
+    /// Route the message to its stream's pair; frames without a known stream are ignored.
+    fn parse_message(
+        &self,
+        text: &str,
+        received_at: u64,
+        _pair: &PairContext,
+        sessions: &mut HashMap<String, DepthSession>,
+        out: &mut Vec<ExchangePrice>,
+    ) -> Result<(), ClientError> {
+        let (stream, depth) = split_combined(parse_json(text)?);
+        let Some((stream, pair)) = stream.and_then(|stream| self.streams.get_key_value(&stream))
+        else {
             return Ok(());
-        }
+        };
+        let session = sessions.entry(stream.clone()).or_default();
+        self.client
+            .handle_depth(depth, received_at, pair, session, out)
+    }
+}
 
-        let exchange_timestamp = depth.get("E").and_then(|e| e.as_u64()).unwrap_or(0);
+/// Combined streams (`/stream?streams=...`) wrap each payload as
+/// `{"stream":"btcusdt@depth20@100ms","data":{...}}`; split off the stream name. Raw streams
+/// send the payload itself, with no name.
+fn split_combined(mut message: Value) -> (Option<String>, Value) {
+    if let Some(Value::String(stream)) = message.get_mut("stream").map(Value::take)
+        && let Some(data) = message.get_mut("data")
+    {
+        return (Some(stream), data.take());
+    }
+    (None, message)
+}
 
-        // Process bids (buy side). Prefer WS keys "b", fall back to "bids".
-        if let Some(bids) = depth
-            .get("b")
-            .or_else(|| depth.get("bids"))
-            .and_then(|b| b.as_array())
-        {
-            for bid in bids {
-                if let Some(bid_array) = bid.as_array() {
-                    if bid_array.len() >= 2 {
-                        if let (Some(price_str), Some(qty_str)) =
-                            (bid_array[0].as_str(), bid_array[1].as_str())
-                        {
-                            let (price_opt, quantity_opt) = {
-                                let _span = tracing::info_span!("process_bids").entered();
-                                (
-                                    parse_price_cents(price_str),
-                                    parse_quantity_smallest_unit(qty_str, 8), // BTC has 8 decimals
-                                )
-                            };
-
-                            if let (Some(price), Some(quantity)) = (price_opt, quantity_opt) {
-                                let _ = self
-                                    .tx
-                                    .send(ExchangePrice::Binance {
-                                        price,
-                                        quantity,
-                                        exchange_timestamp,
-                                        received_at,
-                                        side: Side::Buy,
-                                    })
-                                    .await;
-                            }
-                        }
+fn parse_json(text: &str) -> Result<Value, ClientError> {
+    tracing::trace!(text, "raw message");
+    // Basic validation - avoid extremely large messages
+    if text.len() > 100_000 {
+        return Err(ClientError::protocol(
+            Exchange::Binance,
+            "message too large",
+        ));
+    }
+
+    let _span = tracing::info_span!("parse_json").entered();
+    serde_json::from_str::<Value>(text).map_err(|e| ClientError::protocol(Exchange::Binance, e))
+}
+
+/// Parse one depth message into `out`, checking update ids against `sequence`.
+pub fn parse_binance(
+    text: &str,
+    received_at: u64,
+    pair: &PairContext,
+    sequence: &mut DepthSequence,
+    out: &mut Vec<ExchangePrice>,
+) -> Result<(), ClientError> {
+    let (_, depth) = split_combined(parse_json(text)?);
+    parse_depth(&depth, received_at, pair, sequence, out)
+}
+
+#[instrument(skip(depth, sequence, out), fields(exchange = "binance", levels = tracing::field::Empty))]
+fn parse_depth(
+    depth: &Value,
+    received_at: u64,
+    pair: &PairContext,
+    sequence: &mut DepthSequence,
+    out: &mut Vec<ExchangePrice>,
+) -> Result<(), ClientError> {
+    // Binance depth stream format:
+    // - Snapshot (REST): { "lastUpdateId": ..., "bids": [[price, qty], ...], "asks": [[price, qty], ...] }
+    // - WS updates (like btcusdt@depth20@100ms):
+    //   { "e": "depthUpdate", "E": ..., "b": [[price, qty], ...], "a": [[price, qty], ...], ... }
+    let event_type = depth.get("e").and_then(|e| e.as_str());
+    let is_snapshot = depth.get("lastUpdateId").is_some();
+    let is_update = event_type == Some("depthUpdate");
+
+    // Only process depth snapshots and updates
+    if !is_snapshot && !is_update {
+        return Ok(());
+    }
+
+    // Snapshots carry "lastUpdateId"; diff updates carry first/last ids "U"/"u".
+    if let Some(last_update_id) = depth.get("lastUpdateId").and_then(|id| id.as_u64()) {
+        sequence.on_snapshot(last_update_id);
+    } else if let (Some(first), Some(last)) = (
+        depth.get("U").and_then(|id| id.as_u64()),
+        depth.get("u").and_then(|id| id.as_u64()),
+    ) && sequence
+        .on_update(first, last)
+        .map_err(|gap| ClientError::protocol(Exchange::Binance, gap))?
+        == SequenceCheck::Stale
+    {
+        return Ok(());
+    }
+
+    let exchange_timestamp = depth.get("E").and_then(|e| e.as_u64()).unwrap_or(0);
+    let side_len = |short, long| {
+        depth
+            .get(short)
+            .or_else(|| depth.get(long))
+            .and_then(|l| l.as_array())
+            .map_or(0, Vec::len)
+    };
+    tracing::Span::current().record("levels", side_len("b", "bids") + side_len("a", "asks"));
+    let mut level_error = None;
+
+    // A partial book (`lastUpdateId`, e.g. @depth20) lists the whole top of each side, so
+    // it replaces Binance's levels; diff updates only change the levels they name.
+    let mut bid_modes = SideModes::new(is_snapshot);
+    let mut ask_modes = SideModes::new(is_snapshot);
+
+    // Process bids (buy side). Prefer WS keys "b", fall back to "bids".
+    if let Some(bids) = depth
+        .get("b")
+        .or_else(|| depth.get("bids"))
+        .and_then(|b| b.as_array())
+    {
+        for bid in bids {
+            if let Some(bid_array) = bid.as_array()
+                && bid_array.len() >= 2
+                && let (Some(price_str), Some(qty_str)) =
+                    (bid_array[0].as_str(), bid_array[1].as_str())
+            {
+                let (price_res, quantity_res) = {
+                    let _span = tracing::info_span!("process_bids").entered();
+                    (
+                        parse_price_scaled(price_str, pair.price_decimals),
+                        parse_quantity_smallest_unit(qty_str, pair.base_decimals),
+                    )
+                };
+
+                match (price_res, quantity_res) {
+                    (Ok(price), Ok(quantity)) => {
+                        METRICS.record_level_parsed(Exchange::Binance.as_str());
+                        out.push(
+                            ExchangePrice::new(Exchange::Binance, price, quantity, Side::Buy)
+                                .with_mode(bid_modes.next_mode())
+                                .with_timestamps(exchange_timestamp, received_at)
+                                .with_pair(pair.symbol.clone()),
+                        );
+                    }
+                    (Err(error), _) | (_, Err(error)) => {
+                        METRICS.record_parse_error(Exchange::Binance.as_str(), error);
+                        level_error.get_or_insert(error);
                     }
                 }
             }
         }
+    }
 
-        // Process asks (sell side). Prefer WS keys "a", fall back to "asks".
-        if let Some(asks) = depth
-            .get("a")
-            .or_else(|| depth.get("asks"))
-            .and_then(|a| a.as_array())
-        {
-            for ask in asks {
-                if let Some(ask_array) = ask.as_array() {
-                    if ask_array.len() >= 2 {
-                        if let (Some(price_str), Some(qty_str)) =
-                            (ask_array[0].as_str(), ask_array[1].as_str())
-                        {
-                            let (price_opt, quantity_opt) = {
-                                let _span = tracing::info_span!("process_asks").entered();
-                                (
-                                    parse_price_cents(price_str),
-                                    parse_quantity_smallest_unit(qty_str, 8),
-                                )
-                            };
-
-                            if let (Some(price), Some(quantity)) = (price_opt, quantity_opt) {
-                                let _ = self
-                                    .tx
-                                    .send(ExchangePrice::Binance {
-                                        price,
-                                        quantity,
-                                        exchange_timestamp,
-                                        received_at,
-                                        side: Side::Sell,
-                                    })
-                                    .await;
-                            }
-                        }
+    // Process asks (sell side). Prefer WS keys "a", fall back to "asks".
+    if let Some(asks) = depth
+        .get("a")
+        .or_else(|| depth.get("asks"))
+        .and_then(|a| a.as_array())
+    {
+        for ask in asks {
+            if let Some(ask_array) = ask.as_array()
+                && ask_array.len() >= 2
+                && let (Some(price_str), Some(qty_str)) =
+                    (ask_array[0].as_str(), ask_array[1].as_str())
+            {
+                let (price_res, quantity_res) = {
+                    let _span = tracing::info_span!("process_asks").entered();
+                    (
+                        parse_price_scaled(price_str, pair.price_decimals),
+                        parse_quantity_smallest_unit(qty_str, pair.base_decimals),
+                    )
+                };
+
+                match (price_res, quantity_res) {
+                    (Ok(price), Ok(quantity)) => {
+                        METRICS.record_level_parsed(Exchange::Binance.as_str());
+                        out.push(
+                            ExchangePrice::new(Exchange::Binance, price, quantity, Side::Sell)
+                                .with_mode(ask_modes.next_mode())
+                                .with_timestamps(exchange_timestamp, received_at)
+                                .with_pair(pair.symbol.clone()),
+                        );
+                    }
+                    (Err(error), _) | (_, Err(error)) => {
+                        METRICS.record_parse_error(Exchange::Binance.as_str(), error);
+                        level_error.get_or_insert(error);
                     }
                 }
             }
         }
+    }
 
-        Ok(())
+    for (modes, side) in [(bid_modes, Side::Buy), (ask_modes, Side::Sell)] {
+        out.extend(modes.clear_if_empty(Exchange::Binance, side).map(|clear| {
+            clear
+                .with_timestamps(exchange_timestamp, received_at)
+                .with_pair(pair.symbol.clone())
+        }));
+    }
+
+    match level_error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// Parse one `bookTicker` payload, e.g.
+/// `{"u":400900217,"s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}`:
+/// best bid `b` for `B`, best ask `a` for `A`. Each is all this stream knows of its side, so it
+/// replaces Binance's side. Spot tickers carry no event time, so `E` is read only if present.
+#[instrument(skip(ticker, out), fields(exchange = "binance", levels = tracing::field::Empty))]
+fn parse_book_ticker(
+    ticker: &Value,
+    received_at: u64,
+    pair: &PairContext,
+    out: &mut Vec<ExchangePrice>,
+) -> Result<(), ClientError> {
+    let field = |key| ticker.get(key).and_then(|f| f.as_str());
+    let exchange_timestamp = ticker.get("E").and_then(|e| e.as_u64()).unwrap_or(0);
+    let mut levels = 0;
+    let mut level_error = None;
+    for (price_key, qty_key, side) in [("b", "B", Side::Buy), ("a", "A", Side::Sell)] {
+        let (Some(price_str), Some(qty_str)) = (field(price_key), field(qty_key)) else {
+            continue;
+        };
+        levels += 1;
+        match (
+            parse_price_scaled(price_str, pair.price_decimals),
+            parse_quantity_smallest_unit(qty_str, pair.base_decimals),
+        ) {
+            (Ok(price), Ok(quantity)) => {
+                METRICS.record_level_parsed(Exchange::Binance.as_str());
+                out.push(
+                    ExchangePrice::new(Exchange::Binance, price, quantity, side)
+                        .with_mode(UpdateMode::Replace)
+                        .with_timestamps(exchange_timestamp, received_at)
+                        .with_pair(pair.symbol.clone()),
+                );
+            }
+            (Err(error), _) | (_, Err(error)) => {
+                METRICS.record_parse_error(Exchange::Binance.as_str(), error);
+                level_error.get_or_insert(error);
+            }
+        }
+    }
+    tracing::Span::current().record("levels", levels);
+
+    match level_error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{UpdateMode, golden};
+    use futures_util::{SinkExt, StreamExt};
+    use std::str::FromStr;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::accept_async;
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[tokio::test]
+    async fn answers_pings_with_pongs() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(tcp).await.unwrap();
+            ws.send(Message::Ping(b"hb".to_vec())).await.unwrap();
+            let reply = ws.next().await.unwrap().unwrap();
+            ws.close(None).await.unwrap();
+            reply
+        });
+
+        let (tx, _rx) = mpsc::channel(8);
+        let client = BinanceClient::new(tx);
+        let pair = TradingPair::default_pair().context();
+        crate::api::run_session(&client, &client.tx, &url, None, &pair).await;
+        assert_eq!(server.await.unwrap(), Message::Pong(b"hb".to_vec()));
+    }
+
+    #[test]
+    fn stream_url_follows_the_configured_pair() {
+        let (tx, _rx) = mpsc::channel(1);
+        let mut client = BinanceClient::new(tx);
+        let pair = TradingPair::from_str("ETH-USDT").unwrap();
+        assert_eq!(
+            client.stream_url(&pair),
+            "wss://stream.binance.com:9443/ws/ethusdt@depth20@100ms"
+        );
+
+        client.ws_url = "wss://testnet.binance.vision/ws".to_string();
+        assert_eq!(
+            client.ws_url(&pair),
+            "wss://testnet.binance.vision/ws/ethusdt@depth20@100ms"
+        );
+    }
+
+    #[test]
+    fn sequence_accepts_contiguous_updates_after_snapshot() {
+        let mut seq = DepthSequence::default();
+        seq.on_snapshot(100);
+        // Fully covered by the snapshot.
+        assert_eq!(seq.on_update(90, 100).unwrap(), SequenceCheck::Stale);
+        // First update may straddle the snapshot id.
+        assert_eq!(seq.on_update(95, 105).unwrap(), SequenceCheck::Apply);
+        assert_eq!(seq.on_update(106, 110).unwrap(), SequenceCheck::Apply);
+        assert_eq!(seq.last_update_id, Some(110));
+    }
+
+    #[test]
+    fn sequence_reports_gap() {
+        let mut seq = DepthSequence::default();
+        assert_eq!(seq.on_update(1, 5).unwrap(), SequenceCheck::Apply);
+        let gap = seq.on_update(7, 9).unwrap_err();
+        assert_eq!(gap.expected, 6);
+        assert_eq!(gap.first_update_id, 7);
+    }
+
+    #[test]
+    fn parse_message_surfaces_gap_and_skips_stale() {
+        let (tx, _rx) = mpsc::channel(16);
+        let client = BinanceClient::new(tx);
+        let mut levels = Vec::new();
+        let mut seq = DepthSession::default();
+        let ctx = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+
+        let update = |first: u64, last: u64| {
+            format!(
+                r#"{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{first},"u":{last},"b":[["100.00","1.0"]],"a":[]}}"#
+            )
+        };
+        client
+            .parse_message(&update(1, 2), 0, &ctx, &mut seq, &mut levels)
+            .unwrap();
+        client
+            .parse_message(&update(1, 2), 0, &ctx, &mut seq, &mut levels)
+            .unwrap();
+        let err = client
+            .parse_message(&update(5, 6), 0, &ctx, &mut seq, &mut levels)
+            .unwrap_err();
+        assert!(client.resyncs_on(&err));
+
+        // Only the first update's level was kept.
+        assert_eq!(levels.len(), 1);
+    }
+
+    fn golden(sequence: &mut DepthSequence, text: &str) -> Vec<ExchangePrice> {
+        let pair = golden::context("BNB-BTC", 8, 8);
+        golden::parse(|out| parse_binance(text, 42, &pair, sequence, out))
+    }
+
+    #[test]
+    fn golden_snapshot_replaces_each_side() {
+        let snapshot = golden(
+            &mut DepthSequence::default(),
+            include_str!("testdata/binance/snapshot.json"),
+        );
+        assert_eq!(
+            golden::levels(&snapshot),
+            [
+                (
+                    (400_000_000, 43_100_000_000, Side::Buy),
+                    UpdateMode::Replace
+                ),
+                (
+                    (400_000_200, 1_200_000_000, Side::Sell),
+                    UpdateMode::Replace
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn golden_updates_follow_their_update_ids() {
+        let mut sequence = DepthSequence::default();
+        let update = golden(&mut sequence, include_str!("testdata/binance/update.json"));
+        assert_eq!(
+            golden::levels(&update),
+            [
+                ((240_000, 1_000_000_000, Side::Buy), UpdateMode::Delta),
+                ((260_000, 10_000_000_000, Side::Sell), UpdateMode::Delta),
+            ]
+        );
+        assert_eq!(update[0].exchange_timestamp(), 1_672_515_782_136);
+
+        // U=161 follows u=160; a quantity of "0" removes the level.
+        let removal = golden(&mut sequence, include_str!("testdata/binance/removal.json"));
+        assert_eq!(
+            golden::levels(&removal),
+            [((260_000, 0, Side::Sell), UpdateMode::Delta)]
+        );
+        let book = golden::book_after(&[&update, &removal]);
+        assert!(golden::top(&book, Side::Sell, 5).is_empty());
+
+        // Replayed ids are stale; skipped ids are a gap.
+        let replayed = golden(&mut sequence, include_str!("testdata/binance/removal.json"));
+        assert!(replayed.is_empty());
+        let skipped = include_str!("testdata/binance/removal.json")
+            .replace(r#""U":161,"u":161"#, r#""U":163,"u":163"#);
+        let err = parse_binance(
+            &skipped,
+            42,
+            &golden::context("BNB-BTC", 8, 8),
+            &mut sequence,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("expected U=162, got U=163"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn golden_update_older_than_the_snapshot_is_skipped() {
+        // The example update (u=160) predates the example snapshot (lastUpdateId=1027024).
+        let mut sequence = DepthSequence::default();
+        golden(
+            &mut sequence,
+            include_str!("testdata/binance/snapshot.json"),
+        );
+        assert!(golden(&mut sequence, include_str!("testdata/binance/update.json")).is_empty());
+    }
+
+    #[test]
+    fn combined_stream_envelope_is_unwrapped() {
+        let raw = include_str!("testdata/binance/snapshot.json").trim();
+        let wrapped = format!(r#"{{"stream":"bnbbtc@depth20@100ms","data":{raw}}}"#);
+        assert_eq!(
+            golden::levels(&golden(&mut DepthSequence::default(), &wrapped)),
+            golden::levels(&golden(&mut DepthSequence::default(), raw))
+        );
+    }
+
+    #[test]
+    fn combined_stream_url_lists_every_pair() {
+        let (tx, _rx) = mpsc::channel(1);
+        let client = BinanceClient::new(tx);
+        let streams = [
+            "btcusdt@depth20@100ms".to_string(),
+            "ethusdt@depth20@100ms".to_string(),
+        ];
+        assert_eq!(
+            client.combined_stream_url(&streams),
+            "wss://stream.binance.com:9443/stream?streams=btcusdt@depth20@100ms/ethusdt@depth20@100ms"
+        );
+    }
+
+    #[test]
+    fn multiplexed_messages_are_routed_by_stream() {
+        let (tx, _rx) = mpsc::channel(1);
+        let client = BinanceClient::new(tx);
+        let pairs = ["BTC-USDT", "ETH-USDT"].map(|pair| {
+            pair.parse::<TradingPair>()
+                .unwrap()
+                .with_price_decimals(Some(2))
+        });
+        let multiplexed = Multiplexed {
+            client: &client,
+            streams: pairs
+                .iter()
+                .map(|pair| (client.stream_name(pair), pair.context()))
+                .collect(),
+        };
+        let mut sequences = HashMap::new();
+        let mut out = Vec::new();
+        let ignored = pairs[0].context();
+        for text in [
+            r#"{"stream":"ethusdt@depth20@100ms","data":{"lastUpdateId":7,"bids":[["2000.00","1.0"]],"asks":[]}}"#,
+            r#"{"stream":"btcusdt@depth20@100ms","data":{"lastUpdateId":9,"bids":[],"asks":[["43000.00","0.5"]]}}"#,
+            r#"{"stream":"solusdt@depth20@100ms","data":{"lastUpdateId":1,"bids":[["100.00","1.0"]],"asks":[]}}"#,
+        ] {
+            multiplexed
+                .parse_message(text, 42, &ignored, &mut sequences, &mut out)
+                .unwrap();
+        }
+
+        let levels: Vec<_> = out
+            .iter()
+            .filter(|price| price.level().1 > 0)
+            .map(|price| (price.pair().to_string(), price.level()))
+            .collect();
+        assert_eq!(
+            levels,
+            [
+                ("ETH-USDT".to_string(), (200_000, 100_000_000, Side::Buy)),
+                ("BTC-USDT".to_string(), (4_300_000, 50_000_000, Side::Sell)),
+            ]
+        );
+        // Each stream keeps its own update ids.
+        assert_eq!(sequences.len(), 2);
+    }
+
+    #[test]
+    fn depth_stream_names_round_trip() {
+        for name in [
+            "depth20@100ms",
+            "depth5",
+            "depth10@100ms",
+            "depth",
+            "depth@100ms",
+        ] {
+            let stream: BinanceDepthStream = name.parse().unwrap();
+            assert_eq!(stream.to_string(), name);
+        }
+        assert_eq!(
+            "depth20@1000ms".parse::<BinanceDepthStream>().unwrap(),
+            BinanceDepthStream::new(Some(20), 1000).unwrap()
+        );
+        assert!(
+            "depth@100ms"
+                .parse::<BinanceDepthStream>()
+                .unwrap()
+                .is_diff()
+        );
+    }
+
+    #[test]
+    fn depth_stream_rejects_combinations_binance_does_not_offer() {
+        for name in [
+            "depth15@100ms",
+            "depth20@10ms",
+            "depth@250ms",
+            "book20",
+            "depth20@fast",
+        ] {
+            assert!(name.parse::<BinanceDepthStream>().is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn stream_name_follows_the_configured_depth_stream() {
+        let (tx, _rx) = mpsc::channel(1);
+        let mut client = BinanceClient::new(tx);
+        client.stream = "depth5".parse().unwrap();
+        let pair = "ETH-USDT".parse::<TradingPair>().unwrap();
+        assert_eq!(
+            client.ws_url(&pair),
+            "wss://stream.binance.com:9443/ws/ethusdt@depth5"
+        );
+    }
+
+    #[test]
+    fn book_ticker_is_a_stream_choice() {
+        assert_eq!(
+            "bookTicker".parse::<BinanceStream>().unwrap(),
+            BinanceStream::BookTicker
+        );
+        assert_eq!(
+            "depth5".parse::<BinanceStream>().unwrap(),
+            BinanceStream::Depth(BinanceDepthStream::new(Some(5), 1000).unwrap())
+        );
+        assert!("bookticker".parse::<BinanceStream>().is_err());
+
+        let (tx, _rx) = mpsc::channel(1);
+        let mut client = BinanceClient::new(tx);
+        client.stream = BinanceStream::BookTicker;
+        let pair = "ETH-USDT".parse::<TradingPair>().unwrap();
+        assert_eq!(
+            client.ws_url(&pair),
+            "wss://stream.binance.com:9443/ws/ethusdt@bookTicker"
+        );
+    }
+
+    #[test]
+    fn parses_book_ticker_best_bid_and_ask() {
+        let (tx, _rx) = mpsc::channel(1);
+        let mut client = BinanceClient::new(tx);
+        client.stream = BinanceStream::BookTicker;
+        let pair = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        let text = r#"{"u":400900217,"s":"BTCUSDT","b":"43250.00","B":"0.75000000","a":"43250.10","A":"0.50000000"}"#;
+        let mut out = Vec::new();
+        client
+            .parse_message(text, 42, &pair, &mut DepthSession::default(), &mut out)
+            .unwrap();
+        let levels: Vec<_> = out
+            .iter()
+            .map(|price| (price.level(), price.mode(), price.received_at()))
+            .collect();
+        assert_eq!(
+            levels,
+            [
+                ((4_325_000, 75_000_000, Side::Buy), UpdateMode::Replace, 42),
+                ((4_325_010, 50_000_000, Side::Sell), UpdateMode::Replace, 42),
+            ]
+        );
+        assert_eq!(out[0].pair(), "BTC-USDT");
+
+        // On a combined stream the same payload arrives wrapped with its stream name.
+        let wrapped = format!(r#"{{"stream":"btcusdt@bookTicker","data":{text}}}"#);
+        let mut wrapped_out = Vec::new();
+        client
+            .parse_message(
+                &wrapped,
+                42,
+                &pair,
+                &mut DepthSession::default(),
+                &mut wrapped_out,
+            )
+            .unwrap();
+        assert_eq!(wrapped_out.len(), 2);
+    }
+
+    fn diff(first: u64, last: u64, bid: &str) -> Value {
+        serde_json::from_str(&format!(
+            r#"{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{first},"u":{last},"b":[["{bid}","1.0"]],"a":[]}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn diff_updates_wait_for_the_snapshot_then_replay_on_top_of_it() {
+        let ctx = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        let (snapshot_tx, snapshot_rx) = oneshot::channel();
+        let mut session = DepthSession {
+            sync: SnapshotSync::Fetching {
+                snapshot: snapshot_rx,
+                buffered: Vec::new(),
+            },
+            ..DepthSession::default()
+        };
+        let mut out = Vec::new();
+
+        // Covered by the snapshot (u <= 100), straddling it, then fetched too early to show.
+        for update in [diff(95, 98, "98.00"), diff(99, 102, "99.00")] {
+            session.on_diff("", update, 7, &ctx, &mut out).unwrap();
+        }
+        assert!(out.is_empty());
+
+        snapshot_tx
+            .send(Ok(
+                r#"{"lastUpdateId":100,"bids":[["97.00","2.0"]],"asks":[["101.00","1.0"]]}"#
+                    .to_string(),
+            ))
+            .unwrap();
+        session
+            .on_diff("", diff(103, 104, "96.00"), 8, &ctx, &mut out)
+            .unwrap();
+        assert!(matches!(session.sync, SnapshotSync::Synced));
+        let levels: Vec<_> = out
+            .iter()
+            .map(|price| (price.level(), price.mode()))
+            .collect();
+        assert_eq!(
+            levels,
+            [
+                ((9_700, 200_000_000, Side::Buy), UpdateMode::Replace),
+                ((10_100, 100_000_000, Side::Sell), UpdateMode::Replace),
+                ((9_900, 100_000_000, Side::Buy), UpdateMode::Delta),
+                ((9_600, 100_000_000, Side::Buy), UpdateMode::Delta),
+            ]
+        );
+
+        // Synced: updates apply directly, and a gap still asks for a resync.
+        let err = session
+            .on_diff("", diff(110, 111, "95.00"), 9, &ctx, &mut out)
+            .unwrap_err();
+        let (tx, _rx) = mpsc::channel(1);
+        assert!(BinanceClient::new(tx).resyncs_on(&err));
+    }
+
+    #[test]
+    fn bad_snapshot_level_still_syncs_the_session() {
+        let ctx = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        let (snapshot_tx, snapshot_rx) = oneshot::channel();
+        let mut session = DepthSession {
+            sync: SnapshotSync::Fetching {
+                snapshot: snapshot_rx,
+                buffered: Vec::new(),
+            },
+            ..DepthSession::default()
+        };
+        snapshot_tx
+            .send(Ok(
+                r#"{"lastUpdateId":100,"bids":[["97.00","2.0"],["oops","1.0"]],"asks":[]}"#
+                    .to_string(),
+            ))
+            .unwrap();
+        let mut out = Vec::new();
+        let err = session
+            .on_diff("", diff(100, 101, "96.00"), 7, &ctx, &mut out)
+            .unwrap_err();
+        let (tx, _rx) = mpsc::channel(1);
+        assert!(matches!(err, ClientError::Parse(_)));
+        assert!(!BinanceClient::new(tx).resyncs_on(&err));
+        assert!(matches!(session.sync, SnapshotSync::Synced));
+        // The valid bid, the empty ask side cleared, then the buffered update.
+        assert_eq!(out.len(), 3);
+
+        // The next update applies directly instead of waiting on the consumed snapshot.
+        out.clear();
+        session
+            .on_diff("", diff(102, 102, "95.00"), 8, &ctx, &mut out)
+            .unwrap();
+        assert_eq!(out[0].level(), (9_500, 100_000_000, Side::Buy));
+    }
+
+    #[test]
+    fn snapshot_older_than_the_buffered_updates_is_not_applied() {
+        let ctx = TradingPair::default_pair().context();
+        let mut session = DepthSession::default();
+        let snapshot = serde_json::json!({"lastUpdateId": 90, "bids": [], "asks": []});
+        let mut out = Vec::new();
+        let applied = session
+            .apply_snapshot(&snapshot, &[(1, diff(95, 98, "98.00"))], 1, &ctx, &mut out)
+            .unwrap();
+        assert!(!applied);
+        assert!(out.is_empty());
+    }
+
+    #[tokio::test]
+    async fn first_diff_update_fetches_the_snapshot_over_rest() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rest_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = tcp.read(&mut request).await.unwrap();
+            let body = r#"{"lastUpdateId":100,"bids":[["97.00","2.0"]],"asks":[]}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            tcp.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+
+        let ctx = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        let mut session = DepthSession::default();
+        let mut out = Vec::new();
+        session
+            .on_diff(&rest_url, diff(99, 101, "99.00"), 1, &ctx, &mut out)
+            .unwrap();
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("GET /api/v3/depth?symbol=BTCUSDT&limit=5000 HTTP/1.1"),
+            "{request}"
+        );
+
+        // The fetch lands on its own task; the next update picks it up.
+        let mut next = 102;
+        while !matches!(session.sync, SnapshotSync::Synced) {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            session
+                .on_diff(&rest_url, diff(next, next, "98.00"), 2, &ctx, &mut out)
+                .unwrap();
+            next += 1;
+        }
+        assert_eq!(out[0].level(), (9_700, 200_000_000, Side::Buy));
+        assert_eq!(out[1].level(), (0, 0, Side::Sell));
+        assert_eq!(out[2].level(), (9_900, 100_000_000, Side::Buy));
     }
 }
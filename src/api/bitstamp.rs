@@ -1,190 +1,264 @@
-use futures_util::{SinkExt, StreamExt};
-use tokio::sync::mpsc;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use std::time::Duration;
+
 use tracing::instrument;
 
-use crate::api::{ExchangePrice, Side, TradingPair};
-use crate::util::{parse_price_cents, parse_quantity_smallest_unit};
+use crate::api::{
+    ClientError, Exchange, ExchangeClient, ExchangePrice, HEARTBEAT_INTERVAL, PairContext,
+    ReconnectConfig, Side, SideModes, SubscriptionRejected, TradingPair, UpdateSender,
+};
+use crate::metrics::METRICS;
+use crate::util::{parse_price_scaled, parse_quantity_smallest_unit};
 
 const BITSTAMP_WS_URL: &str = "wss://ws.bitstamp.net";
 
+/// Application-level heartbeat; Bitstamp answers with a `bts:heartbeat` event.
+const BITSTAMP_HEARTBEAT: &str = r#"{"event":"bts:heartbeat"}"#;
+
 pub struct BitstampClient {
-    tx: mpsc::Sender<ExchangePrice>,
+    tx: UpdateSender,
+    /// WebSocket endpoint; `BITSTAMP_WS_URL` overrides it in `main`, e.g. for a sandbox or a mock server.
+    pub ws_url: String,
+    /// Backoff used to reconnect (and re-subscribe) after the stream drops.
+    pub reconnect: ReconnectConfig,
+    /// How often to send the heartbeat on an open connection.
+    pub heartbeat_interval: Duration,
 }
 
 impl BitstampClient {
-    pub fn new(tx: mpsc::Sender<ExchangePrice>) -> Self {
-        BitstampClient { tx }
+    pub fn new(tx: impl Into<UpdateSender>) -> Self {
+        BitstampClient {
+            tx: tx.into(),
+            ws_url: BITSTAMP_WS_URL.to_string(),
+            reconnect: ReconnectConfig::default(),
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+        }
     }
 
-    /// Listen to a specific trading pair's order book on Bitstamp.
-    pub async fn listen_pair(&self, pair: TradingPair) {
-        match connect_async(BITSTAMP_WS_URL).await {
-            Ok((mut ws_stream, _)) => {
-
-                let channel = format!("order_book_{}", pair.bitstamp_pair_code());
-
-                let subscribe_msg = serde_json::json!({
-                    "event": "bts:subscribe",
-                    "data": {
-                        "channel": channel
-                    }
-                });
+    /// Listen to a specific trading pair's order book on Bitstamp, reconnecting on drops.
+    pub async fn listen_pair(&self, pair: TradingPair) -> Result<(), ClientError> {
+        crate::api::run(self, &self.tx, pair).await
+    }
+}
 
-                if let Err(_e) = ws_stream
-                    .send(Message::Text(subscribe_msg.to_string()))
-                    .await
-                {
-                    return;
-                }
+impl ExchangeClient for BitstampClient {
+    const EXCHANGE: Exchange = Exchange::Bitstamp;
+    type Session = ();
 
-                let (_write, mut read) = ws_stream.split();
-
-                let mut received_any = false;
-
-                while let Some(msg) = read.next().await {
-                    match msg {
-                        Ok(Message::Text(text)) => {
-                            let received_at = Self::current_timestamp_ms();
-                            if self.handle_message(&text, received_at).await.is_ok() {
-                                received_any = true;
-                            }
-                        }
-                        Ok(Message::Ping(_data)) => {}
-                        Ok(Message::Close(_)) => {
-                            break;
-                        }
-                        Err(_e) => {
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
+    fn ws_url(&self, _pair: &TradingPair) -> String {
+        self.ws_url.clone()
+    }
 
-                let _ = received_any;
-            }
-            Err(_e) => {
+    fn subscribe_msg(&self, pair: &TradingPair) -> Option<String> {
+        let channel = format!("order_book_{}", pair.bitstamp_pair_code());
+        let subscribe_msg = serde_json::json!({
+            "event": "bts:subscribe",
+            "data": {
+                "channel": channel
             }
-        }
+        });
+        Some(subscribe_msg.to_string())
+    }
+
+    fn reconnect(&self) -> &ReconnectConfig {
+        &self.reconnect
     }
 
-    /// Get the current time as milliseconds since Unix epoch.
-    fn current_timestamp_ms() -> u64 {
-        let now = std::time::SystemTime::now();
-        now.duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64
+    fn heartbeat(&self) -> Option<(&'static str, Duration)> {
+        Some((BITSTAMP_HEARTBEAT, self.heartbeat_interval))
     }
 
-    /// Parse one order book data message and send price levels to the aggregator.
-    #[instrument(skip(self, text), fields(exchange = "bitstamp"))]
-    async fn handle_message(
+    fn parse_message(
         &self,
         text: &str,
         received_at: u64,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if text.len() > 100_000 {
-            return Err("Message too large".into());
-        }
+        pair: &PairContext,
+        _session: &mut (),
+        out: &mut Vec<ExchangePrice>,
+    ) -> Result<(), ClientError> {
+        parse_bitstamp(text, received_at, pair, out)
+    }
+}
 
-        let v = {
-            let _span = tracing::info_span!("parse_json").entered();
-            serde_json::from_str::<serde_json::Value>(text)?
-        };
+/// Parse one order book data message into `out`.
+#[instrument(skip(text, out), fields(exchange = "bitstamp", levels = tracing::field::Empty))]
+pub fn parse_bitstamp(
+    text: &str,
+    received_at: u64,
+    pair: &PairContext,
+    out: &mut Vec<ExchangePrice>,
+) -> Result<(), ClientError> {
+    tracing::trace!(text, "raw message");
+    if text.len() > 100_000 {
+        return Err(ClientError::protocol(
+            Exchange::Bitstamp,
+            "message too large",
+        ));
+    }
 
-        let event = match v.get("event").and_then(|e| e.as_str()) {
-            Some(e) => e,
-            None => return Ok(()),
-        };
+    let v = {
+        let _span = tracing::info_span!("parse_json").entered();
+        serde_json::from_str::<serde_json::Value>(text)
+            .map_err(|e| ClientError::protocol(Exchange::Bitstamp, e))?
+    };
 
-        // Ignore non-data events (subscription acks, reconnects, etc.)
-        if event != "data" {
-            return Ok(());
-        }
+    let event = match v.get("event").and_then(|e| e.as_str()) {
+        Some(e) => e,
+        None => return Ok(()),
+    };
 
-        let data = match v.get("data") {
-            Some(d) => d,
-            None => return Ok(()),
-        };
-
-        let exchange_timestamp = data
-            .get("microtimestamp")
-            .and_then(|t| t.as_str())
-            .and_then(|s| s.parse::<u64>().ok())
-            .unwrap_or(0);
-
-        // Bids: [["price", "amount"], ...]
-        if let Some(bids) = data.get("bids").and_then(|b| b.as_array()) {
-            for bid in bids {
-                if let Some(arr) = bid.as_array() {
-                    if arr.len() >= 2 {
-                        if let (Some(price_str), Some(size_str)) =
-                            (arr[0].as_str(), arr[1].as_str())
-                        {
-                            if size_str == "0" {
-                                continue;
-                            }
-                            let (price_opt, quantity_opt) = {
-                                let _span = tracing::info_span!("process_bids").entered();
-                                (
-                                    parse_price_cents(price_str),
-                                    parse_quantity_smallest_unit(size_str, 8),
-                                )
-                            };
-                            if let (Some(price), Some(quantity)) = (price_opt, quantity_opt) {
-                                let _ = self
-                                    .tx
-                                    .send(ExchangePrice::Bitstamp {
-                                        price,
-                                        quantity,
-                                        exchange_timestamp,
-                                        received_at,
-                                        side: Side::Buy,
-                                    })
-                                    .await;
-                            }
-                        }
+    // e.g. {"event":"bts:error","data":{"code":null,"message":"Bad subscription string."}}
+    if event == "bts:error" {
+        let reason = v
+            .pointer("/data/message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("unknown error");
+        return Err(SubscriptionRejected::new(Exchange::Bitstamp, reason).into());
+    }
+
+    // Ignore other non-data events (subscription acks, reconnects, etc.)
+    if event != "data" {
+        return Ok(());
+    }
+
+    let data = match v.get("data") {
+        Some(d) => d,
+        None => return Ok(()),
+    };
+
+    // "microtimestamp" is in microseconds; keep milliseconds like the other venues.
+    let exchange_timestamp = data
+        .get("microtimestamp")
+        .and_then(|t| t.as_str())
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(|micros| micros / 1_000)
+        .unwrap_or(0);
+    let side_len = |key| data.get(key).and_then(|l| l.as_array()).map_or(0, Vec::len);
+    tracing::Span::current().record("levels", side_len("bids") + side_len("asks"));
+    let mut level_error = None;
+    // `order_book_*` pushes the whole top 100 every time, so each message replaces the book.
+    let mut bid_modes = SideModes::new(true);
+    let mut ask_modes = SideModes::new(true);
+
+    // Bids: [["price", "amount"], ...]; a zero amount is forwarded so the book drops the level.
+    if let Some(bids) = data.get("bids").and_then(|b| b.as_array()) {
+        for bid in bids {
+            if let Some(arr) = bid.as_array()
+                && arr.len() >= 2
+                && let (Some(price_str), Some(size_str)) = (arr[0].as_str(), arr[1].as_str())
+            {
+                let (price_res, quantity_res) = {
+                    let _span = tracing::info_span!("process_bids").entered();
+                    (
+                        parse_price_scaled(price_str, pair.price_decimals),
+                        parse_quantity_smallest_unit(size_str, pair.base_decimals),
+                    )
+                };
+                match (price_res, quantity_res) {
+                    (Ok(price), Ok(quantity)) => {
+                        METRICS.record_level_parsed(Exchange::Bitstamp.as_str());
+                        out.push(
+                            ExchangePrice::new(Exchange::Bitstamp, price, quantity, Side::Buy)
+                                .with_mode(bid_modes.next_mode())
+                                .with_timestamps(exchange_timestamp, received_at)
+                                .with_pair(pair.symbol.clone()),
+                        );
+                    }
+                    (Err(error), _) | (_, Err(error)) => {
+                        METRICS.record_parse_error(Exchange::Bitstamp.as_str(), error);
+                        level_error.get_or_insert(error);
                     }
                 }
             }
         }
+    }
 
-        // Asks: [["price", "amount"], ...]
-        if let Some(asks) = data.get("asks").and_then(|a| a.as_array()) {
-            for ask in asks {
-                if let Some(arr) = ask.as_array() {
-                    if arr.len() >= 2 {
-                        if let (Some(price_str), Some(size_str)) =
-                            (arr[0].as_str(), arr[1].as_str())
-                        {
-                            if size_str == "0" {
-                                continue;
-                            }
-                            let (price_opt, quantity_opt) = {
-                                let _span = tracing::info_span!("process_asks").entered();
-                                (
-                                    parse_price_cents(price_str),
-                                    parse_quantity_smallest_unit(size_str, 8),
-                                )
-                            };
-                            if let (Some(price), Some(quantity)) = (price_opt, quantity_opt) {
-                                let _ = self
-                                    .tx
-                                    .send(ExchangePrice::Bitstamp {
-                                        price,
-                                        quantity,
-                                        exchange_timestamp,
-                                        received_at,
-                                        side: Side::Sell,
-                                    })
-                                    .await;
-                            }
-                        }
+    // Asks: [["price", "amount"], ...]
+    if let Some(asks) = data.get("asks").and_then(|a| a.as_array()) {
+        for ask in asks {
+            if let Some(arr) = ask.as_array()
+                && arr.len() >= 2
+                && let (Some(price_str), Some(size_str)) = (arr[0].as_str(), arr[1].as_str())
+            {
+                let (price_res, quantity_res) = {
+                    let _span = tracing::info_span!("process_asks").entered();
+                    (
+                        parse_price_scaled(price_str, pair.price_decimals),
+                        parse_quantity_smallest_unit(size_str, pair.base_decimals),
+                    )
+                };
+                match (price_res, quantity_res) {
+                    (Ok(price), Ok(quantity)) => {
+                        METRICS.record_level_parsed(Exchange::Bitstamp.as_str());
+                        out.push(
+                            ExchangePrice::new(Exchange::Bitstamp, price, quantity, Side::Sell)
+                                .with_mode(ask_modes.next_mode())
+                                .with_timestamps(exchange_timestamp, received_at)
+                                .with_pair(pair.symbol.clone()),
+                        );
+                    }
+                    (Err(error), _) | (_, Err(error)) => {
+                        METRICS.record_parse_error(Exchange::Bitstamp.as_str(), error);
+                        level_error.get_or_insert(error);
                     }
                 }
             }
         }
+    }
+
+    for (modes, side) in [(bid_modes, Side::Buy), (ask_modes, Side::Sell)] {
+        out.extend(modes.clear_if_empty(Exchange::Bitstamp, side).map(|clear| {
+            clear
+                .with_timestamps(exchange_timestamp, received_at)
+                .with_pair(pair.symbol.clone())
+        }));
+    }
+
+    match level_error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{UpdateMode, golden};
+
+    fn golden(text: &str) -> Vec<ExchangePrice> {
+        let pair = golden::context("BTC-USDT", 8, 2);
+        golden::parse(|out| parse_bitstamp(text, 42, &pair, out))
+    }
+
+    #[test]
+    fn golden_book_replaces_each_side() {
+        let book = golden(include_str!("testdata/bitstamp/book.json"));
+        assert_eq!(
+            golden::levels(&book),
+            [
+                ((4_325_000, 75_000_000, Side::Buy), UpdateMode::Replace),
+                ((4_324_950, 200_000_000, Side::Buy), UpdateMode::Delta),
+                ((4_325_010, 50_000_000, Side::Sell), UpdateMode::Replace),
+                ((4_325_100, 120_000_000, Side::Sell), UpdateMode::Delta),
+            ]
+        );
+        // "microtimestamp" 1700000000123456 in milliseconds.
+        assert_eq!(book[0].exchange_timestamp(), 1_700_000_000_123);
+    }
 
-        Ok(())
+    #[test]
+    fn golden_next_book_drops_levels_it_no_longer_lists() {
+        // Bitstamp never sends zero sizes: a level is removed by leaving it out.
+        let book = golden::book_after(&[
+            &golden(include_str!("testdata/bitstamp/book.json")),
+            &golden(include_str!("testdata/bitstamp/next_book.json")),
+        ]);
+        assert_eq!(
+            golden::top(&book, Side::Sell, 5),
+            [(4_325_100, 120_000_000), (4_325_150, 80_000_000)]
+        );
+        assert_eq!(
+            golden::top(&book, Side::Buy, 5),
+            [(4_325_000, 100_000_000), (4_324_950, 200_000_000)]
+        );
     }
 }
@@ -0,0 +1,452 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::instrument;
+
+use crate::api::{
+    ClientError, Exchange, ExchangePrice, HEARTBEAT_INTERVAL, MessageStamp, PairContext,
+    ReconnectConfig, SessionEnd, Side, SideModes, SubscriptionRejected, TradingPair, UpdateSender,
+    reconnect_loop, session_writer,
+};
+use crate::health::FEEDS;
+use crate::metrics::METRICS;
+use crate::util::{
+    ParseError, current_timestamp_ms, parse_price_scaled, parse_quantity_smallest_unit,
+};
+
+const BYBIT_WS_URL: &str = "wss://stream.bybit.com/v5/public/spot";
+
+/// Levels per side of the `orderbook.{depth}.{symbol}` topic subscribed to by default.
+pub const BYBIT_BOOK_DEPTH: u32 = 50;
+
+/// Application-level ping; Bybit drops connections without one every 20s or so.
+const BYBIT_PING: &str = r#"{"op":"ping"}"#;
+
+pub struct BybitClient {
+    tx: UpdateSender,
+    /// WebSocket endpoint; `BYBIT_WS_URL` overrides it in `main`, e.g. for a sandbox or a mock server.
+    pub ws_url: String,
+    /// Levels per side of the `orderbook` topic: 1, 50 or 200 on spot.
+    pub depth: u32,
+    /// Backoff used to reconnect (and re-subscribe) after the stream drops.
+    pub reconnect: ReconnectConfig,
+    /// How often to send the heartbeat on an open connection.
+    pub heartbeat_interval: Duration,
+}
+
+impl BybitClient {
+    pub fn new(tx: impl Into<UpdateSender>) -> Self {
+        BybitClient {
+            tx: tx.into(),
+            ws_url: BYBIT_WS_URL.to_string(),
+            depth: BYBIT_BOOK_DEPTH,
+            reconnect: ReconnectConfig::default(),
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+        }
+    }
+
+    /// Listen to a specific symbol's `orderbook` topic on Bybit spot, reconnecting on drops.
+    pub async fn listen_pair(&self, pair: TradingPair) -> Result<(), ClientError> {
+        reconnect_loop(Exchange::Bybit, &self.reconnect, || self.run_session(&pair)).await
+    }
+
+    /// One connection's lifetime: connect, subscribe, read until close/error.
+    /// Returns whether any data message was handled.
+    async fn run_session(&self, pair: &TradingPair) -> SessionEnd {
+        let mut received_any = false;
+        let mut error = None;
+        let ctx = pair.context();
+
+        match connect_async(self.ws_url.as_str()).await {
+            Ok((mut ws_stream, _)) => {
+                let subscribe_msg = serde_json::json!({
+                    "op": "subscribe",
+                    "args": [format!("orderbook.{}.{}", self.depth, pair.bybit_symbol())]
+                });
+
+                if let Err(e) = ws_stream
+                    .send(Message::Text(subscribe_msg.to_string()))
+                    .await
+                {
+                    tracing::warn!(exchange = "bybit", error = %e, "subscribe failed");
+                    return SessionEnd {
+                        received_data: false,
+                        error: Some(ClientError::subscribe_send(Exchange::Bybit, e)),
+                    };
+                }
+
+                // A writer task owns the sink: Pongs are handed to it, heartbeats run on its timer.
+                let (write, mut read) = ws_stream.split();
+                let (outgoing, outgoing_rx) = mpsc::channel(8);
+                let writer = tokio::spawn(session_writer(
+                    write,
+                    outgoing_rx,
+                    Some((BYBIT_PING, self.heartbeat_interval)),
+                ));
+
+                while let Some(msg) = read.next().await {
+                    match msg {
+                        Ok(Message::Text(text)) => {
+                            let received_at = current_timestamp_ms();
+                            FEEDS.record_message(Exchange::Bybit, received_at);
+                            let result = self.handle_message(&text, received_at, &ctx).await;
+                            METRICS.record_message(Exchange::Bybit.as_str(), result.is_ok());
+                            received_any |= result
+                                .as_ref()
+                                .err()
+                                .is_none_or(ClientError::forwarded_levels);
+                            match result {
+                                Ok(()) => {}
+                                Err(e) if e.stops_client() => {
+                                    tracing::info!(exchange = "bybit", error = %e, "stopping client");
+                                    error = Some(e);
+                                    break;
+                                }
+                                Err(e) => {
+                                    METRICS.record_client_error(Exchange::Bybit.as_str(), &e);
+                                    tracing::debug!(exchange = "bybit", error = %e, "could not handle message");
+                                }
+                            }
+                        }
+                        // Answer pings from the read loop; see `session_writer`.
+                        Ok(Message::Ping(data)) => {
+                            let pong = outgoing.send(Message::Pong(data)).await;
+                            if pong.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(Message::Close(frame)) => {
+                            tracing::info!(
+                                exchange = "bybit",
+                                ?frame,
+                                "connection closed by exchange"
+                            );
+                            error = Some(ClientError::Stream {
+                                exchange: Exchange::Bybit,
+                                reason: "closed by the exchange".to_string(),
+                            });
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::warn!(exchange = "bybit", error = %e, "websocket read failed");
+                            error = Some(ClientError::Stream {
+                                exchange: Exchange::Bybit,
+                                reason: e.to_string(),
+                            });
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                writer.abort();
+            }
+            Err(e) => {
+                tracing::warn!(exchange = "bybit", error = %e, "websocket connect failed");
+                error = Some(ClientError::Connect {
+                    exchange: Exchange::Bybit,
+                    source: Box::new(e),
+                });
+            }
+        }
+
+        SessionEnd {
+            received_data: received_any,
+            error,
+        }
+    }
+
+    /// Parse one `snapshot` or `delta` book message and send its price levels to the aggregator.
+    async fn handle_message(
+        &self,
+        text: &str,
+        received_at: u64,
+        pair: &PairContext,
+    ) -> Result<(), ClientError> {
+        let mut levels = Vec::new();
+        let parsed = parse_bybit(text, received_at, pair, &mut levels);
+        for level in levels {
+            self.tx.send(level).await?;
+        }
+        parsed
+    }
+}
+
+/// Parse one `snapshot` or `delta` book message into `out`.
+#[instrument(skip(text, out), fields(exchange = "bybit", levels = tracing::field::Empty))]
+pub fn parse_bybit(
+    text: &str,
+    received_at: u64,
+    pair: &PairContext,
+    out: &mut Vec<ExchangePrice>,
+) -> Result<(), ClientError> {
+    tracing::trace!(text, "raw message");
+    if text.len() > 100_000 {
+        return Err(ClientError::protocol(Exchange::Bybit, "message too large"));
+    }
+
+    let v = {
+        let _span = tracing::info_span!("parse_json").entered();
+        serde_json::from_str::<Value>(text)
+            .map_err(|e| ClientError::protocol(Exchange::Bybit, e))?
+    };
+
+    // e.g. {"success":false,"ret_msg":"error:handler not found,topic:orderbook.50.BTCUSDX","op":"subscribe"}
+    if v.get("op").and_then(|o| o.as_str()) == Some("subscribe")
+        && v.get("success").and_then(|s| s.as_bool()) == Some(false)
+    {
+        let msg = v
+            .get("ret_msg")
+            .and_then(|m| m.as_str())
+            .unwrap_or("unknown error");
+        return Err(SubscriptionRejected::new(Exchange::Bybit, msg).into());
+    }
+
+    // Subscription acks and pongs carry "op"; book pushes carry "topic" and "data".
+    let Some(data) = v.get("data").filter(|d| d.is_object()) else {
+        return Ok(());
+    };
+
+    // "snapshot" is the whole book (also resent when Bybit restarts the feed) and replaces
+    // what we hold for it; "delta" carries changed levels with absolute sizes, "0" = removed.
+    let snapshot = match v.get("type").and_then(|t| t.as_str()) {
+        Some("snapshot") => true,
+        Some("delta") => false,
+        other => {
+            return Err(ClientError::protocol(
+                Exchange::Bybit,
+                format!("unknown book message type {other:?}"),
+            ));
+        }
+    };
+    let exchange_timestamp = v.get("ts").and_then(|t| t.as_u64()).unwrap_or(0);
+    let levels: usize = ["b", "a"]
+        .iter()
+        .filter_map(|key| data.get(key)?.as_array().map(Vec::len))
+        .sum();
+    tracing::Span::current().record("levels", levels);
+    let mut level_error = None;
+
+    // Levels: [["price", "size"], ...]
+    for (key, side) in [("b", Side::Buy), ("a", Side::Sell)] {
+        let levels = data
+            .get(key)
+            .and_then(|l| l.as_array())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let mut modes = SideModes::new(snapshot);
+        let stamp = MessageStamp {
+            pair,
+            exchange_timestamp,
+            received_at,
+        };
+        push_levels(levels, side, &mut modes, stamp, out, &mut level_error);
+        if let Some(clear) = modes.clear_if_empty(Exchange::Bybit, side) {
+            out.push(
+                clear
+                    .with_timestamps(exchange_timestamp, received_at)
+                    .with_pair(pair.symbol.clone()),
+            );
+        }
+    }
+
+    match level_error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// Push `[["price", "size"], ...]` levels for one side, tagged by `modes`.
+/// A zero size is forwarded so the book drops the level.
+fn push_levels(
+    levels: &[Value],
+    side: Side,
+    modes: &mut SideModes,
+    stamp: MessageStamp<'_>,
+    out: &mut Vec<ExchangePrice>,
+    level_error: &mut Option<ParseError>,
+) {
+    for level in levels {
+        if let Some(arr) = level.as_array()
+            && arr.len() >= 2
+            && let (Some(price_str), Some(size_str)) = (arr[0].as_str(), arr[1].as_str())
+        {
+            let (price_res, quantity_res) = {
+                let _span = tracing::info_span!("process_levels", ?side).entered();
+                (
+                    parse_price_scaled(price_str, stamp.pair.price_decimals),
+                    parse_quantity_smallest_unit(size_str, stamp.pair.base_decimals),
+                )
+            };
+
+            match (price_res, quantity_res) {
+                (Ok(price), Ok(quantity)) => {
+                    METRICS.record_level_parsed(Exchange::Bybit.as_str());
+                    out.push(
+                        stamp.apply(
+                            ExchangePrice::new(Exchange::Bybit, price, quantity, side)
+                                .with_mode(modes.next_mode()),
+                        ),
+                    );
+                }
+                (Err(error), _) | (_, Err(error)) => {
+                    METRICS.record_parse_error(Exchange::Bybit.as_str(), error);
+                    level_error.get_or_insert(error);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{SubscribeError, UpdateMode, golden};
+    use crate::orderbook::OrderBook;
+
+    async fn parse(text: &str) -> Vec<ExchangePrice> {
+        let (tx, mut rx) = mpsc::channel(64);
+        let client = BybitClient::new(tx);
+        let pair = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        client.handle_message(text, 42, &pair).await.unwrap();
+        drop(client);
+
+        let mut out = Vec::new();
+        while let Some(price) = rx.recv().await {
+            out.push(price);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn reports_subscription_errors() {
+        let (tx, _rx) = mpsc::channel(8);
+        let client = BybitClient::new(tx);
+        let pair = TradingPair::default_pair().context();
+        let err = client
+            .handle_message(r#"{"success":false,"ret_msg":"error:handler not found,topic:orderbook.50.BTCUSDX","conn_id":"d30fdpbboasp1pjbe7r0","op":"subscribe"}"#, 42, &pair)
+            .await
+            .unwrap_err();
+        let ClientError::Subscribe(SubscribeError::Rejected(rejected)) = err else {
+            panic!("expected a rejected subscription, got {err}");
+        };
+        assert_eq!(
+            rejected,
+            SubscriptionRejected::new(
+                Exchange::Bybit,
+                "error:handler not found,topic:orderbook.50.BTCUSDX"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshot_opens_each_side_with_replace() {
+        let text = r#"{"topic":"orderbook.50.BTCUSDT","ts":1672304484978,"type":"snapshot","data":{"s":"BTCUSDT","b":[["16493.50","0.006"],["16493.00","0.100"]],"a":[],"u":18521288,"seq":7961638724},"cts":1672304484976}"#;
+        let prices = parse(text).await;
+        let levels: Vec<_> = prices.iter().map(|p| (p.level(), p.mode())).collect();
+        assert_eq!(
+            levels,
+            [
+                ((1_649_350, 600_000, Side::Buy), UpdateMode::Replace),
+                ((1_649_300, 10_000_000, Side::Buy), UpdateMode::Delta),
+                // No asks: one empty level still clears the side.
+                ((0, 0, Side::Sell), UpdateMode::Replace),
+            ]
+        );
+        assert_eq!(prices[0].exchange_timestamp(), 1_672_304_484_978);
+        assert_eq!(prices[0].received_at(), 42);
+    }
+
+    #[tokio::test]
+    async fn deltas_set_absolute_sizes_and_snapshots_replace_the_book() {
+        let book = OrderBook::new("BTC-USDT".to_string());
+        let snapshot = r#"{"topic":"orderbook.50.BTCUSDT","ts":1,"type":"snapshot","data":{"s":"BTCUSDT","b":[["100.00","1"],["99.00","2"]],"a":[["101.00","3"]],"u":1,"seq":1}}"#;
+        let delta = r#"{"topic":"orderbook.50.BTCUSDT","ts":2,"type":"delta","data":{"s":"BTCUSDT","b":[["100.00","5"],["99.00","0"]],"a":[],"u":2,"seq":2}}"#;
+        book.update_price_levels(&parse(snapshot).await);
+        book.update_price_levels(&parse(delta).await);
+        assert_eq!(
+            book.top_bids_n(10),
+            [(Exchange::Bybit, 10_000, 500_000_000)]
+        );
+        assert_eq!(
+            book.top_asks_n(10),
+            [(Exchange::Bybit, 10_100, 300_000_000)]
+        );
+
+        // A new snapshot drops whatever it does not list.
+        let resync = r#"{"topic":"orderbook.50.BTCUSDT","ts":3,"type":"snapshot","data":{"s":"BTCUSDT","b":[["98.00","1"]],"a":[["102.00","1"]],"u":1,"seq":3}}"#;
+        book.update_price_levels(&parse(resync).await);
+        assert_eq!(book.top_bids_n(10), [(Exchange::Bybit, 9_800, 100_000_000)]);
+        assert_eq!(
+            book.top_asks_n(10),
+            [(Exchange::Bybit, 10_200, 100_000_000)]
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_acks_and_pongs() {
+        for text in [
+            r#"{"success":true,"ret_msg":"subscribe","conn_id":"2324d924","op":"subscribe"}"#,
+            r#"{"success":true,"ret_msg":"pong","conn_id":"2324d924","op":"ping"}"#,
+        ] {
+            assert!(parse(text).await.is_empty(), "{text}");
+        }
+    }
+
+    fn golden(text: &str) -> Vec<ExchangePrice> {
+        let pair = golden::context("BTC-USDT", 8, 2);
+        golden::parse(|out| parse_bybit(text, 42, &pair, out))
+    }
+
+    #[test]
+    fn golden_snapshot_replaces_each_side() {
+        let snapshot = golden(include_str!("testdata/bybit/snapshot.json"));
+        assert_eq!(
+            golden::levels(&snapshot),
+            [
+                ((1_649_350, 600_000, Side::Buy), UpdateMode::Replace),
+                ((1_649_300, 10_000_000, Side::Buy), UpdateMode::Delta),
+                ((1_661_100, 2_900_000, Side::Sell), UpdateMode::Replace),
+                ((1_661_200, 21_300_000, Side::Sell), UpdateMode::Delta),
+            ]
+        );
+        // The message's "ts", not the matching engine's "cts".
+        assert_eq!(snapshot[0].exchange_timestamp(), 1_672_304_484_978);
+    }
+
+    #[test]
+    fn golden_delta_sets_and_removes_levels() {
+        // A delta mixes new sizes with "0" sizes, which remove the level.
+        let delta = golden(include_str!("testdata/bybit/update.json"));
+        assert_eq!(
+            golden::levels(&delta),
+            [
+                ((3_024_720, 3_002_800_000, Side::Buy), UpdateMode::Delta),
+                ((3_024_540, 22_400_000, Side::Buy), UpdateMode::Delta),
+                ((3_024_210, 159_300_000, Side::Buy), UpdateMode::Delta),
+                ((3_023_900, 0, Side::Buy), UpdateMode::Delta),
+                ((3_024_870, 0, Side::Sell), UpdateMode::Delta),
+                ((3_024_930, 89_200_000, Side::Sell), UpdateMode::Delta),
+                ((3_024_950, 177_800_000, Side::Sell), UpdateMode::Delta),
+                ((3_024_960, 0, Side::Sell), UpdateMode::Delta),
+                ((3_025_190, 294_700_000, Side::Sell), UpdateMode::Delta),
+                ((3_025_220, 65_900_000, Side::Sell), UpdateMode::Delta),
+                ((3_025_250, 459_100_000, Side::Sell), UpdateMode::Delta),
+            ]
+        );
+        let book = golden::book_after(&[&delta]);
+        assert_eq!(
+            golden::top(&book, Side::Buy, 5),
+            [
+                (3_024_720, 3_002_800_000),
+                (3_024_540, 22_400_000),
+                (3_024_210, 159_300_000),
+            ]
+        );
+        assert_eq!(golden::top(&book, Side::Sell, 1), [(3_024_930, 89_200_000)]);
+    }
+}
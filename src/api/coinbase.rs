@@ -0,0 +1,465 @@
+use serde_json::Value;
+use tracing::instrument;
+
+use crate::api::{
+    ClientError, Exchange, ExchangeClient, ExchangePrice, MessageStamp, PairContext,
+    ReconnectConfig, Side, SideModes, SubscriptionRejected, TradingPair, UpdateSender,
+};
+use crate::metrics::METRICS;
+use crate::util::{ParseError, parse_price_scaled, parse_quantity_smallest_unit, parse_rfc3339_ms};
+
+const COINBASE_WS_URL: &str = "wss://ws-feed.exchange.coinbase.com";
+
+/// Coinbase channels the client can read the book from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoinbaseChannel {
+    /// Full book: a snapshot, then changes batched every 50ms.
+    #[default]
+    Level2Batch,
+    /// Best bid and ask only, pushed with every trade.
+    Ticker,
+}
+
+impl CoinbaseChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoinbaseChannel::Level2Batch => "level2_batch",
+            CoinbaseChannel::Ticker => "ticker",
+        }
+    }
+}
+
+impl std::str::FromStr for CoinbaseChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "level2_batch" => Ok(CoinbaseChannel::Level2Batch),
+            "ticker" => Ok(CoinbaseChannel::Ticker),
+            other => Err(format!("unknown Coinbase channel '{other}'")),
+        }
+    }
+}
+
+pub struct CoinbaseClient {
+    tx: UpdateSender,
+    /// WebSocket endpoint; `COINBASE_WS_URL` overrides it in `main`, e.g. for a sandbox or a mock server.
+    pub ws_url: String,
+    /// Which channel to subscribe to.
+    pub channel: CoinbaseChannel,
+    /// Backoff used to reconnect (and re-subscribe) after the stream drops.
+    pub reconnect: ReconnectConfig,
+}
+
+impl CoinbaseClient {
+    pub fn new(tx: impl Into<UpdateSender>) -> Self {
+        CoinbaseClient {
+            tx: tx.into(),
+            ws_url: COINBASE_WS_URL.to_string(),
+            channel: CoinbaseChannel::default(),
+            reconnect: ReconnectConfig::default(),
+        }
+    }
+
+    /// Listen to a specific product's channel on Coinbase, reconnecting on drops.
+    pub async fn listen_pair(&self, pair: TradingPair) -> Result<(), ClientError> {
+        crate::api::run(self, &self.tx, pair).await
+    }
+}
+
+impl ExchangeClient for CoinbaseClient {
+    const EXCHANGE: Exchange = Exchange::Coinbase;
+    type Session = ();
+
+    fn ws_url(&self, _pair: &TradingPair) -> String {
+        self.ws_url.clone()
+    }
+
+    fn subscribe_msg(&self, pair: &TradingPair) -> Option<String> {
+        let subscribe_msg = serde_json::json!({
+            "type": "subscribe",
+            "product_ids": [pair.coinbase_product_id()],
+            "channels": [self.channel.as_str()]
+        });
+        Some(subscribe_msg.to_string())
+    }
+
+    fn reconnect(&self) -> &ReconnectConfig {
+        &self.reconnect
+    }
+
+    fn parse_message(
+        &self,
+        text: &str,
+        received_at: u64,
+        pair: &PairContext,
+        _session: &mut (),
+        out: &mut Vec<ExchangePrice>,
+    ) -> Result<(), ClientError> {
+        parse_coinbase(text, received_at, pair, out)
+    }
+}
+
+/// Parse one `snapshot`, `l2update` or `ticker` message into `out`.
+#[instrument(skip(text, out), fields(exchange = "coinbase", levels = tracing::field::Empty))]
+pub fn parse_coinbase(
+    text: &str,
+    received_at: u64,
+    pair: &PairContext,
+    out: &mut Vec<ExchangePrice>,
+) -> Result<(), ClientError> {
+    tracing::trace!(text, "raw message");
+    // Snapshots carry the full book, so allow more than the other venues.
+    if text.len() > 5_000_000 {
+        return Err(ClientError::protocol(
+            Exchange::Coinbase,
+            "message too large",
+        ));
+    }
+
+    let v = {
+        let _span = tracing::info_span!("parse_json").entered();
+        serde_json::from_str::<Value>(text)
+            .map_err(|e| ClientError::protocol(Exchange::Coinbase, e))?
+    };
+
+    // "time" is RFC 3339 on updates (snapshots have none); 0 when missing or malformed.
+    let exchange_timestamp = v
+        .get("time")
+        .and_then(|t| t.as_str())
+        .and_then(parse_rfc3339_ms)
+        .unwrap_or(0);
+
+    let stamp = MessageStamp {
+        pair,
+        exchange_timestamp,
+        received_at,
+    };
+    let mut level_error = None;
+    match v.get("type").and_then(|t| t.as_str()) {
+        // Snapshot: "bids"/"asks" as [["price", "size"], ...].
+        Some("snapshot") => {
+            let side_len = |key| v.get(key).and_then(|l| l.as_array()).map_or(0, Vec::len);
+            tracing::Span::current().record("levels", side_len("bids") + side_len("asks"));
+            for (key, side) in [("bids", Side::Buy), ("asks", Side::Sell)] {
+                // The snapshot is the whole book, so it replaces Coinbase's side.
+                let mut modes = SideModes::new(true);
+                let levels = v.get(key).and_then(|l| l.as_array());
+                for level in levels.into_iter().flatten() {
+                    if let Some(arr) = level.as_array()
+                        && arr.len() >= 2
+                        && let (Some(price_str), Some(size_str)) =
+                            (arr[0].as_str(), arr[1].as_str())
+                    {
+                        push_level(
+                            price_str,
+                            size_str,
+                            side,
+                            &mut modes,
+                            stamp,
+                            out,
+                            &mut level_error,
+                        );
+                    }
+                }
+                out.extend(modes.clear_if_empty(Exchange::Coinbase, side).map(|clear| {
+                    clear
+                        .with_timestamps(exchange_timestamp, received_at)
+                        .with_pair(pair.symbol.clone())
+                }));
+            }
+        }
+        // Update: "changes" as [["buy" | "sell", "price", "size"], ...];
+        // a zero size is forwarded so the book drops the level.
+        Some("l2update") => {
+            let Some(changes) = v.get("changes").and_then(|c| c.as_array()) else {
+                return Ok(());
+            };
+            tracing::Span::current().record("levels", changes.len());
+            let mut modes = SideModes::new(false);
+            for change in changes {
+                if let Some(arr) = change.as_array()
+                    && arr.len() >= 3
+                    && let (Some(side_str), Some(price_str), Some(size_str)) =
+                        (arr[0].as_str(), arr[1].as_str(), arr[2].as_str())
+                {
+                    let side = match side_str {
+                        "buy" => Side::Buy,
+                        "sell" => Side::Sell,
+                        _ => continue,
+                    };
+                    push_level(
+                        price_str,
+                        size_str,
+                        side,
+                        &mut modes,
+                        stamp,
+                        out,
+                        &mut level_error,
+                    );
+                }
+            }
+        }
+        // Ticker: the current "best_bid"/"best_ask" with their sizes. Each is the venue's
+        // whole side as far as this channel knows, so it replaces the side.
+        Some("ticker") => {
+            let mut levels = 0;
+            for (price_key, size_key, side) in [
+                ("best_bid", "best_bid_size", Side::Buy),
+                ("best_ask", "best_ask_size", Side::Sell),
+            ] {
+                let field = |key| v.get(key).and_then(|f| f.as_str());
+                if let (Some(price_str), Some(size_str)) = (field(price_key), field(size_key)) {
+                    levels += 1;
+                    push_level(
+                        price_str,
+                        size_str,
+                        side,
+                        &mut SideModes::new(true),
+                        stamp,
+                        out,
+                        &mut level_error,
+                    );
+                }
+            }
+            tracing::Span::current().record("levels", levels);
+        }
+        // e.g. {"type":"error","message":"Failed to subscribe","reason":"BTC-USDX is not a valid product"}
+        Some("error") => {
+            let message = v.get("message").and_then(|m| m.as_str()).unwrap_or("error");
+            let reason = match v.get("reason").and_then(|r| r.as_str()) {
+                Some(reason) => format!("{message}: {reason}"),
+                None => message.to_string(),
+            };
+            return Err(SubscriptionRejected::new(Exchange::Coinbase, reason).into());
+        }
+        // Subscription acks and heartbeats.
+        _ => {}
+    }
+
+    match level_error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// Push one level onto `out`; a level that fails to parse is counted and kept in `level_error`.
+fn push_level(
+    price_str: &str,
+    size_str: &str,
+    side: Side,
+    modes: &mut SideModes,
+    stamp: MessageStamp<'_>,
+    out: &mut Vec<ExchangePrice>,
+    level_error: &mut Option<ParseError>,
+) {
+    let (price_res, quantity_res) = {
+        let _span = tracing::info_span!("process_levels", ?side).entered();
+        (
+            parse_price_scaled(price_str, stamp.pair.price_decimals),
+            parse_quantity_smallest_unit(size_str, stamp.pair.base_decimals),
+        )
+    };
+    match (price_res, quantity_res) {
+        (Ok(price), Ok(quantity)) => {
+            METRICS.record_level_parsed(Exchange::Coinbase.as_str());
+            out.push(
+                stamp.apply(
+                    ExchangePrice::new(Exchange::Coinbase, price, quantity, side)
+                        .with_mode(modes.next_mode()),
+                ),
+            );
+        }
+        (Err(error), _) | (_, Err(error)) => {
+            METRICS.record_parse_error(Exchange::Coinbase.as_str(), error);
+            level_error.get_or_insert(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{SubscribeError, UpdateMode, golden};
+    use tokio::sync::mpsc;
+
+    fn parse_into(text: &str, out: &mut Vec<ExchangePrice>) -> Result<(), ClientError> {
+        let (tx, _rx) = mpsc::channel(1);
+        let client = CoinbaseClient::new(tx);
+        let pair = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        client.parse_message(text, 42, &pair, &mut (), out)
+    }
+
+    fn parse(text: &str) -> Vec<ExchangePrice> {
+        let mut out = Vec::new();
+        parse_into(text, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn reports_subscription_errors() {
+        let err = parse_into(r#"{"type":"error","message":"Failed to subscribe","reason":"BTC-USDX is not a valid product"}"#, &mut Vec::new())
+            .unwrap_err();
+        let ClientError::Subscribe(SubscribeError::Rejected(rejected)) = err else {
+            panic!("expected a rejected subscription, got {err}");
+        };
+        assert_eq!(
+            rejected,
+            SubscriptionRejected::new(
+                Exchange::Coinbase,
+                "Failed to subscribe: BTC-USDX is not a valid product"
+            )
+        );
+    }
+
+    #[test]
+    fn forwards_valid_levels_and_reports_the_bad_one() {
+        let text = r#"{"type":"l2update","product_id":"BTC-USDT","changes":[["buy","abc","1.0"],["sell","101.00","0.5"]]}"#;
+        let mut out = Vec::new();
+        let err = parse_into(text, &mut out).unwrap_err();
+        assert!(matches!(err, ClientError::Parse(ParseError::InvalidFormat)));
+        assert!(matches!(
+            out[..],
+            [ExchangePrice::Coinbase {
+                price: 10_100,
+                side: Side::Sell,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn parses_snapshot_levels() {
+        let text = r#"{"type":"snapshot","product_id":"BTC-USDT","bids":[["10101.10","0.45054140"]],"asks":[["10102.55","0.57753524"]]}"#;
+        let prices = parse(text);
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices[0].pair(), "BTC-USDT");
+        assert!(matches!(
+            prices[0],
+            ExchangePrice::Coinbase {
+                price: 1_010_110,
+                quantity: 45_054_140,
+                received_at: 42,
+                side: Side::Buy,
+                ..
+            }
+        ));
+        assert!(matches!(
+            prices[1],
+            ExchangePrice::Coinbase {
+                price: 1_010_255,
+                side: Side::Sell,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_l2update_changes() {
+        let text = r#"{"type":"l2update","product_id":"BTC-USDT","changes":[["buy","10101.80","0.162567"],["sell","10102.00","0.00000000"]],"time":"2019-08-14T20:42:27.265Z"}"#;
+        let prices = parse(text);
+        assert_eq!(prices.len(), 2);
+        assert!(matches!(
+            prices[0],
+            ExchangePrice::Coinbase {
+                price: 1_010_180,
+                quantity: 16_256_700,
+                exchange_timestamp: 1_565_815_347_265,
+                side: Side::Buy,
+                ..
+            }
+        ));
+        assert!(matches!(
+            prices[1],
+            ExchangePrice::Coinbase {
+                quantity: 0,
+                side: Side::Sell,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_ticker_best_bid_and_ask() {
+        let text = r#"{"type":"ticker","sequence":37475248783,"product_id":"BTC-USDT","price":"43250.05","best_bid":"43250.00","best_bid_size":"0.75000000","best_ask":"43250.10","best_ask_size":"0.50000000","side":"buy","time":"2024-01-15T12:00:00.123456Z","trade_id":1,"last_size":"0.001"}"#;
+        let mut out = Vec::new();
+        parse_into(text, &mut out).unwrap();
+        let levels: Vec<_> = out
+            .iter()
+            .map(|price| (price.level(), price.mode(), price.exchange_timestamp()))
+            .collect();
+        assert_eq!(
+            levels,
+            [
+                (
+                    (4_325_000, 75_000_000, Side::Buy),
+                    UpdateMode::Replace,
+                    1_705_320_000_123
+                ),
+                (
+                    (4_325_010, 50_000_000, Side::Sell),
+                    UpdateMode::Replace,
+                    1_705_320_000_123
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn ticker_without_sizes_is_skipped() {
+        // Older ticker payloads carry prices only; without a size there is no level to store.
+        let text = r#"{"type":"ticker","product_id":"BTC-USDT","best_bid":"43250.00","best_ask":"43250.10"}"#;
+        assert!(parse(text).is_empty());
+    }
+
+    #[test]
+    fn ignores_subscription_acks() {
+        let text = r#"{"type":"subscriptions","channels":[{"name":"level2_batch","product_ids":["BTC-USDT"]}]}"#;
+        assert!(parse(text).is_empty());
+    }
+
+    fn golden(text: &str) -> Vec<ExchangePrice> {
+        let pair = golden::context("BTC-USD", 8, 2);
+        golden::parse(|out| parse_coinbase(text, 42, &pair, out))
+    }
+
+    #[test]
+    fn golden_snapshot_replaces_each_side() {
+        let snapshot = golden(include_str!("testdata/coinbase/snapshot.json"));
+        assert_eq!(
+            golden::levels(&snapshot),
+            [
+                ((1_010_110, 45_054_140, Side::Buy), UpdateMode::Replace),
+                ((1_010_255, 57_753_524, Side::Sell), UpdateMode::Replace),
+            ]
+        );
+        // Snapshots carry no "time".
+        assert_eq!(snapshot[0].exchange_timestamp(), 0);
+    }
+
+    #[test]
+    fn golden_update_sets_changed_levels() {
+        let update = golden(include_str!("testdata/coinbase/update.json"));
+        assert_eq!(
+            golden::levels(&update),
+            [((1_010_180, 16_256_700, Side::Buy), UpdateMode::Delta)]
+        );
+        // "2019-08-14T20:42:27.265Z"
+        assert_eq!(update[0].exchange_timestamp(), 1_565_815_347_265);
+    }
+
+    #[test]
+    fn golden_zero_size_removes_the_level() {
+        let snapshot = golden(include_str!("testdata/coinbase/snapshot.json"));
+        let removal = golden(include_str!("testdata/coinbase/removal.json"));
+        assert_eq!(
+            golden::levels(&removal),
+            [((1_010_255, 0, Side::Sell), UpdateMode::Delta)]
+        );
+        let book = golden::book_after(&[&snapshot, &removal]);
+        assert!(golden::top(&book, Side::Sell, 5).is_empty());
+        assert_eq!(golden::top(&book, Side::Buy, 5), [(1_010_110, 45_054_140)]);
+    }
+}
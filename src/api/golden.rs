@@ -0,0 +1,75 @@
+//! Helpers shared by the golden-file parser tests, which feed frames from `testdata/<venue>/`
+//! through a venue's parser and check what it would send to the book.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::api::{ExchangePrice, PairContext, Side, UpdateMode};
+use crate::orderbook::OrderBook;
+
+/// A parsed level as the golden tests compare it: `(price, quantity, side)` and its mode.
+pub type Level = ((u64, u64, Side), UpdateMode);
+
+/// Context for `symbol` with explicit decimals, so a frame's units do not depend on the
+/// asset table.
+pub fn context(symbol: &str, base_decimals: u32, price_decimals: u32) -> PairContext {
+    PairContext {
+        symbol: Arc::from(symbol),
+        base_decimals,
+        price_decimals,
+    }
+}
+
+/// Run a venue parser over one frame; the frame must parse cleanly.
+pub fn parse<E: Debug>(
+    parse: impl FnOnce(&mut Vec<ExchangePrice>) -> Result<(), E>,
+) -> Vec<ExchangePrice> {
+    let mut out = Vec::new();
+    parse(&mut out).unwrap();
+    out
+}
+
+/// `updates` reduced to what the golden tests compare.
+pub fn levels(updates: &[ExchangePrice]) -> Vec<Level> {
+    updates
+        .iter()
+        .map(|price| (price.level(), price.mode()))
+        .collect()
+}
+
+/// A fresh book with each frame's updates applied in order.
+pub fn book_after(frames: &[&[ExchangePrice]]) -> OrderBook {
+    let book = OrderBook::new("golden".to_string());
+    for updates in frames {
+        book.update_price_levels(updates);
+    }
+    book
+}
+
+/// The best `n` levels on `side` of a single-venue book as `(price, quantity)`, best first.
+pub fn top(book: &OrderBook, side: Side, n: usize) -> Vec<(u64, u64)> {
+    let levels = match side {
+        Side::Buy => book.top_bids_n(n),
+        Side::Sell => book.top_asks_n(n),
+    };
+    levels
+        .into_iter()
+        .map(|(_, price, quantity)| (price, quantity))
+        .collect()
+}
+
+/// CRC-32 (IEEE 802.3), which Kraken and OKX use for their book checksums.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -1,113 +1,469 @@
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::Duration;
 
-use tokio_stream::{wrappers::IntervalStream, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_stream::{
+    Stream, StreamExt,
+    wrappers::{IntervalStream, TcpListenerStream},
+};
+use tonic::transport::server::{Connected, TcpConnectInfo};
 use tonic::{Request, Response, Status};
 
-use crate::orderbook::OrderBook;
+use crate::orderbook::{LevelOut, OrderBook};
+use crate::util::{current_timestamp_ms, env_or};
 
 pub mod pb {
     tonic::include_proto!("orderbook");
 }
 
 use pb::{
+    Empty, ExchangeTop, Level, Summary,
     orderbook_aggregator_server::{OrderbookAggregator, OrderbookAggregatorServer},
-    Empty, Level, Summary,
 };
 
+impl From<LevelOut> for Level {
+    fn from(level: LevelOut) -> Self {
+        Level {
+            // The string field keeps the lowercase name whatever the book's exchange format.
+            exchange: level.exchange.venue.as_str().to_string(),
+            price: level.price.to_f64(),
+            amount: level.amount.to_f64(),
+            price_units: level.price.raw,
+            amount_units: level.amount.raw,
+            exchange_id: level.exchange.venue.id().into(),
+        }
+    }
+}
+
+/// When `BookSummary` streams emit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamMode {
+    /// A `Summary` every tick, changed or not.
+    Periodic,
+    /// Skip ticks where `OrderBook::version` has not moved since the last `Summary`.
+    OnChange,
+}
+
+impl std::str::FromStr for StreamMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "periodic" => Ok(StreamMode::Periodic),
+            "on-change" => Ok(StreamMode::OnChange),
+            other => Err(format!("unknown gRPC stream mode '{other}'")),
+        }
+    }
+}
+
 pub struct OrderbookService {
     pub orderbook: Arc<OrderBook>,
+    /// Levels per side included in each `Summary`.
+    pub depth: usize,
+    /// Time between `Summary` messages on each stream.
+    pub stream_interval: Duration,
+    /// Whether unchanged books are re-sent every tick.
+    pub stream_mode: StreamMode,
 }
 
-type SummaryStream =
-    Pin<Box<dyn Stream<Item = Result<Summary, Status>> + Send + Sync + 'static>>;
+type SummaryStream = Pin<Box<dyn Stream<Item = Result<Summary, Status>> + Send + Sync + 'static>>;
 
 #[tonic::async_trait]
 impl OrderbookAggregator for OrderbookService {
     type BookSummaryStream = SummaryStream;
 
+    #[allow(clippy::result_large_err)]
     async fn book_summary(
         &self,
         _request: Request<Empty>,
     ) -> Result<Response<Self::BookSummaryStream>, Status> {
         let ob = self.orderbook.clone();
+        let depth = self.depth;
+        let mode = self.stream_mode;
+        let mut last_version = None;
+
+        // The first tick completes immediately, so a new subscriber gets a snapshot on connect
+        // and then one per `stream_interval`. A slow consumer delays ticks rather than bursting.
+        let mut interval =
+            tokio::time::interval(self.stream_interval.max(Duration::from_millis(1)));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let stream = IntervalStream::new(interval).filter_map(move |_| {
+            let version = ob.version();
+            if mode == StreamMode::OnChange && last_version == Some(version) {
+                return None;
+            }
+            last_version = Some(version);
 
-        // Stream a snapshot every 500ms.
-        let interval = tokio::time::interval(Duration::from_millis(500));
-        let stream = IntervalStream::new(interval).map(move |_| {
             let _span = tracing::info_span!("grpc_snapshot").entered();
             let top_bids = {
                 let _s = tracing::info_span!("top_bids").entered();
-                ob.top_bids_all_exchanges()
+                ob.top_bids_n(depth)
             };
             let top_asks = {
                 let _s = tracing::info_span!("top_asks").entered();
-                ob.top_asks_all_exchanges()
+                ob.top_asks_n(depth)
             };
-            let spread_cents = ob.spread_all_exchanges();
+            let spread_units = ob.spread_all_exchanges().unwrap_or(0);
+            let tops = ob.exchange_tops();
 
-            let (bids, asks, spread) = {
+            let (bids, asks, exchanges) = {
                 let _s = tracing::info_span!("build_proto").entered();
-                let bids: Vec<Level> = top_bids
-                .iter()
-                .map(|(exchange, price_cents, qty_smallest)| {
-                    let exchange_str = match exchange {
-                        crate::api::Exchange::Binance => "binance",
-                        crate::api::Exchange::Bitstamp => "bitstamp",
-                    }
-                    .to_string();
-
-                    Level {
-                        exchange: exchange_str,
-                        price: *price_cents as f64 / 100.0,
-                        amount: *qty_smallest as f64 / 1e8, // assuming 8 decimals
-                    }
-                })
-                .collect();
-
-            let asks: Vec<Level> = top_asks
-                .iter()
-                .map(|(exchange, price_cents, qty_smallest)| {
-                    let exchange_str = match exchange {
-                        crate::api::Exchange::Binance => "binance",
-                        crate::api::Exchange::Bitstamp => "bitstamp",
-                    }
-                    .to_string();
-
-                    Level {
-                        exchange: exchange_str,
-                        price: *price_cents as f64 / 100.0,
-                        amount: *qty_smallest as f64 / 1e8,
-                    }
-                })
-                .collect();
-
-                let spread = spread_cents.map(|c| c as f64 / 100.0).unwrap_or(0.0);
-
-                (bids, asks, spread)
+                let to_proto = |level| Level::from(ob.level_out(level));
+                let bids: Vec<Level> = top_bids.into_iter().map(to_proto).collect();
+                let asks: Vec<Level> = top_asks.into_iter().map(to_proto).collect();
+                let exchanges = tops
+                    .into_iter()
+                    .map(|top| {
+                        let (best_bid_units, bid_amount_units) = top.bid.unwrap_or_default();
+                        let (best_ask_units, ask_amount_units) = top.ask.unwrap_or_default();
+                        ExchangeTop {
+                            exchange: top.exchange.as_str().to_string(),
+                            best_bid: ob.price_value(best_bid_units),
+                            bid_amount: ob.quantity_value(bid_amount_units),
+                            best_ask: ob.price_value(best_ask_units),
+                            ask_amount: ob.quantity_value(ask_amount_units),
+                            best_bid_units,
+                            bid_amount_units,
+                            best_ask_units,
+                            ask_amount_units,
+                            exchange_id: top.exchange.id().into(),
+                        }
+                    })
+                    .collect();
+
+                (bids, asks, exchanges)
             };
 
-            Ok(Summary {
-                spread,
+            Some(Ok(Summary {
+                spread: ob.price_value(spread_units),
                 bids,
                 asks,
-            })
+                exchanges,
+                spread_units,
+                price_decimals: ob.price_decimals,
+                base_decimals: ob.base_decimals,
+                generated_at: current_timestamp_ms(),
+            }))
         });
 
         Ok(Response::new(Box::pin(stream) as Self::BookSummaryStream))
     }
 }
 
-pub async fn run_grpc_server(orderbook: Arc<OrderBook>) -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "0.0.0.0:50051".parse()?;
-    let service = OrderbookService { orderbook };
+/// Server-side limits and keepalive settings for the gRPC endpoint.
+#[derive(Debug, Clone)]
+pub struct GrpcConfig {
+    pub addr: SocketAddr,
+    /// How often the server sends HTTP/2 keepalive pings.
+    pub keepalive_interval: Duration,
+    /// How long to wait for a ping ack before closing the connection.
+    pub keepalive_timeout: Duration,
+    /// Maximum concurrent HTTP/2 streams per connection.
+    pub max_concurrent_streams: u32,
+    /// Maximum open TCP connections; extra connections are closed on accept. Non-zero, since
+    /// a limit of 0 would refuse every client: `GRPC_MAX_CONNECTIONS=0` is rejected as invalid.
+    pub max_connections: NonZeroUsize,
+    /// Upper bound on a connection's (and so each stream's) lifetime, so a
+    /// stuck client's `BookSummary` stream is torn down and its resources freed.
+    pub max_connection_age: Duration,
+    /// Cadence of `BookSummary` snapshots.
+    pub stream_interval: Duration,
+    /// Only send a snapshot when the book changed (`on-change`), or every tick (`periodic`).
+    pub stream_mode: StreamMode,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        GrpcConfig {
+            addr: SocketAddr::from(([0, 0, 0, 0], 50051)),
+            keepalive_interval: Duration::from_secs(30),
+            keepalive_timeout: Duration::from_secs(10),
+            max_concurrent_streams: 128,
+            max_connections: NonZeroUsize::new(256).unwrap(),
+            max_connection_age: Duration::from_secs(3600),
+            stream_interval: Duration::from_millis(500),
+            stream_mode: StreamMode::OnChange,
+        }
+    }
+}
+
+impl GrpcConfig {
+    /// Build from `GRPC_*` env vars, using the defaults for anything unset.
+    pub fn from_env() -> Self {
+        let d = GrpcConfig::default();
+        GrpcConfig {
+            addr: env_or("GRPC_ADDR", d.addr),
+            keepalive_interval: Duration::from_secs(env_or(
+                "GRPC_KEEPALIVE_INTERVAL_SECS",
+                d.keepalive_interval.as_secs(),
+            )),
+            keepalive_timeout: Duration::from_secs(env_or(
+                "GRPC_KEEPALIVE_TIMEOUT_SECS",
+                d.keepalive_timeout.as_secs(),
+            )),
+            max_concurrent_streams: env_or("GRPC_MAX_CONCURRENT_STREAMS", d.max_concurrent_streams),
+            max_connections: env_or("GRPC_MAX_CONNECTIONS", d.max_connections),
+            max_connection_age: Duration::from_secs(env_or(
+                "GRPC_MAX_CONNECTION_AGE_SECS",
+                d.max_connection_age.as_secs(),
+            )),
+            stream_interval: Duration::from_millis(env_or(
+                "GRPC_STREAM_MS",
+                d.stream_interval.as_millis() as u64,
+            )),
+            stream_mode: env_or("GRPC_STREAM_MODE", d.stream_mode),
+        }
+    }
+}
+
+/// Accepted TCP connection that holds a slot of the connection limit until dropped.
+struct LimitedConn {
+    inner: TcpStream,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Connected for LimitedConn {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.inner.connect_info()
+    }
+}
+
+impl AsyncRead for LimitedConn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for LimitedConn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+pub async fn run_grpc_server(
+    orderbook: Arc<OrderBook>,
+    depth: usize,
+    config: GrpcConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(config.addr).await?;
+    let service = OrderbookService {
+        orderbook,
+        depth,
+        stream_interval: config.stream_interval,
+        stream_mode: config.stream_mode,
+    };
+    serve(listener, service, config).await
+}
+
+/// Serve the aggregator on an already-bound listener, enforcing `config`'s limits.
+async fn serve(
+    listener: TcpListener,
+    service: OrderbookService,
+    config: GrpcConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let max_connections = config.max_connections;
+    let limiter = Arc::new(Semaphore::new(max_connections.get()));
+    let incoming = TcpListenerStream::new(listener).filter_map(move |conn| match conn {
+        Ok(stream) => match limiter.clone().try_acquire_owned() {
+            Ok(permit) => Some(Ok(LimitedConn {
+                inner: stream,
+                _permit: permit,
+            })),
+            Err(_) => {
+                // Dropping the stream closes the socket straight away.
+                tracing::warn!(max_connections, "gRPC: rejecting connection, limit reached");
+                None
+            }
+        },
+        Err(e) => Some(Err(e)),
+    });
 
     tonic::transport::Server::builder()
+        .http2_keepalive_interval(Some(config.keepalive_interval))
+        .http2_keepalive_timeout(Some(config.keepalive_timeout))
+        .max_concurrent_streams(config.max_concurrent_streams)
+        .max_connection_age(config.max_connection_age)
         .add_service(OrderbookAggregatorServer::new(service))
-        .serve(addr)
+        .serve_with_incoming(incoming)
         .await?;
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn read_with_timeout(stream: &mut TcpStream) -> Option<usize> {
+        let mut buf = [0u8; 64];
+        tokio::time::timeout(Duration::from_millis(500), stream.read(&mut buf))
+            .await
+            .ok()
+            .map(|res| res.unwrap_or(0))
+    }
+
+    #[tokio::test]
+    async fn closes_connections_beyond_the_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = GrpcConfig {
+            addr,
+            max_connections: NonZeroUsize::MIN,
+            ..GrpcConfig::default()
+        };
+        let service = OrderbookService {
+            orderbook: Arc::new(OrderBook::new("TEST".to_string())),
+            depth: 10,
+            stream_interval: Duration::from_millis(500),
+            stream_mode: StreamMode::Periodic,
+        };
+        tokio::spawn(async move {
+            let _ = serve(listener, service, config).await;
+        });
+
+        // The first connection is served: the HTTP/2 server sends its SETTINGS preface.
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        assert!(matches!(read_with_timeout(&mut first).await, Some(n) if n > 0));
+
+        // The second is over the limit and gets closed without any bytes.
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(read_with_timeout(&mut second).await, Some(0));
+
+        // Once the first goes away its slot is released for new clients.
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        assert!(matches!(read_with_timeout(&mut third).await, Some(n) if n > 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn summary_stream_follows_configured_interval() {
+        let service = OrderbookService {
+            orderbook: Arc::new(OrderBook::new("TEST".to_string())),
+            depth: 10,
+            stream_interval: Duration::from_millis(200),
+            stream_mode: StreamMode::Periodic,
+        };
+        let mut stream = service
+            .book_summary(Request::new(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let start = tokio::time::Instant::now();
+        let mut offsets = Vec::new();
+        for _ in 0..4 {
+            stream.next().await.unwrap().unwrap();
+            offsets.push(start.elapsed().as_millis());
+        }
+        // First snapshot on connect, then one per interval.
+        assert_eq!(offsets, vec![0, 200, 400, 600]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn on_change_stream_skips_unchanged_ticks() {
+        let orderbook = Arc::new(OrderBook::new("TEST".to_string()));
+        let service = OrderbookService {
+            orderbook: orderbook.clone(),
+            depth: 10,
+            stream_interval: Duration::from_millis(100),
+            stream_mode: StreamMode::OnChange,
+        };
+        let mut stream = service
+            .book_summary(Request::new(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // Initial snapshot on connect, then nothing while the book is idle.
+        stream.next().await.unwrap().unwrap();
+        let idle = tokio::time::timeout(Duration::from_millis(350), stream.next()).await;
+        assert!(idle.is_err());
+
+        orderbook.update_price_level(crate::api::ExchangePrice::new(
+            crate::api::Exchange::Binance,
+            10_000,
+            100_000_000,
+            crate::api::Side::Buy,
+        ));
+        let summary = stream.next().await.unwrap().unwrap();
+        assert_eq!(summary.bids.len(), 1);
+        assert_eq!(summary.exchanges.len(), 1);
+        assert_eq!(summary.exchanges[0].exchange, "binance");
+        assert_eq!(summary.exchanges[0].exchange_id, 1);
+        assert_eq!(summary.bids[0].exchange_id, 1);
+        assert_eq!(summary.exchanges[0].best_bid, 100.0);
+        assert_eq!(summary.exchanges[0].best_ask, 0.0);
+    }
+
+    #[tokio::test]
+    async fn summary_carries_exact_integer_units() {
+        let mut orderbook = OrderBook::new("TEST".to_string());
+        orderbook.price_decimals = 8;
+        // Past 2^53, so the `double` fields cannot hold these exactly.
+        let bid = 9_007_199_254_740_993;
+        let ask = bid + 2;
+        orderbook.update_price_level(crate::api::ExchangePrice::new(
+            crate::api::Exchange::Binance,
+            bid,
+            123_456_789,
+            crate::api::Side::Buy,
+        ));
+        orderbook.update_price_level(crate::api::ExchangePrice::new(
+            crate::api::Exchange::Kraken,
+            ask,
+            1,
+            crate::api::Side::Sell,
+        ));
+        let service = OrderbookService {
+            orderbook: Arc::new(orderbook),
+            depth: 10,
+            stream_interval: Duration::from_millis(100),
+            stream_mode: StreamMode::Periodic,
+        };
+        let mut stream = service
+            .book_summary(Request::new(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let summary = stream.next().await.unwrap().unwrap();
+        assert_eq!((summary.price_decimals, summary.base_decimals), (8, 8));
+        assert_eq!(summary.spread_units, 2);
+        assert!(summary.generated_at > 1_600_000_000_000);
+        assert_eq!(summary.bids[0].price_units, bid);
+        assert_eq!(summary.bids[0].amount_units, 123_456_789);
+        assert_eq!(summary.asks[0].price_units, ask);
+        let binance = &summary.exchanges[0];
+        assert_eq!(
+            (binance.best_bid_units, binance.bid_amount_units),
+            (bid, 123_456_789)
+        );
+        assert_eq!((binance.best_ask_units, binance.ask_amount_units), (0, 0));
+    }
+}
@@ -0,0 +1,211 @@
+//! Plain HTTP access to the combined books: `GET /book?pair=BTC-USDT&depth=10` returns the
+//! same JSON snapshot the CLI prints, for scripts and dashboards that just poll, and
+//! `GET /book/stream` pushes that snapshot as Server-Sent Events.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{RawQuery, State};
+use axum::http::{StatusCode, header};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::{Router, routing::get};
+use futures_util::{Stream, StreamExt, stream};
+use tokio::net::TcpListener;
+use tokio::time::MissedTickBehavior;
+
+use crate::orderbook::{BookSnapshot, OrderBook};
+
+/// Largest `depth` a request may ask for.
+const MAX_DEPTH: usize = 1_000;
+
+/// Books and defaults shared by the HTTP handlers.
+#[derive(Clone)]
+pub struct HttpState {
+    pub books: Arc<HashMap<String, Arc<OrderBook>>>,
+    /// Pair served when the request has no `pair` parameter.
+    pub default_pair: String,
+    /// Levels per side when the request has no `depth` parameter.
+    pub default_depth: usize,
+    /// Time between events on `/book/stream`.
+    pub stream_interval: Duration,
+}
+
+/// Why a `/book` request could not be answered.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BookError {
+    UnknownPair(String),
+    BadDepth(String),
+}
+
+impl IntoResponse for BookError {
+    fn into_response(self) -> Response {
+        match self {
+            BookError::UnknownPair(pair) => {
+                (StatusCode::NOT_FOUND, format!("unknown pair '{pair}'\n")).into_response()
+            }
+            BookError::BadDepth(depth) => (
+                StatusCode::BAD_REQUEST,
+                format!("depth must be an integer from 1 to {MAX_DEPTH} (got '{depth}')\n"),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Resolve the `pair` and `depth` query parameters and snapshot that book.
+pub fn book_snapshot(state: &HttpState, query: Option<&str>) -> Result<BookSnapshot, BookError> {
+    let (book, depth) = resolve_book(state, query)?;
+    Ok(book.snapshot(depth))
+}
+
+/// The book and depth selected by the `pair` and `depth` query parameters.
+fn resolve_book(
+    state: &HttpState,
+    query: Option<&str>,
+) -> Result<(Arc<OrderBook>, usize), BookError> {
+    let mut pair = state.default_pair.clone();
+    let mut depth = state.default_depth;
+    for (key, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        match key.as_ref() {
+            "pair" => pair = value.into_owned(),
+            "depth" => {
+                depth = value
+                    .parse()
+                    .ok()
+                    .filter(|d| (1..=MAX_DEPTH).contains(d))
+                    .ok_or_else(|| BookError::BadDepth(value.into_owned()))?;
+            }
+            _ => {}
+        }
+    }
+
+    let book = state.books.get(&pair).ok_or(BookError::UnknownPair(pair))?;
+    Ok((book.clone(), depth))
+}
+
+/// A snapshot of `book` right away and then every `interval`.
+fn snapshot_stream(
+    book: Arc<OrderBook>,
+    depth: usize,
+    interval: Duration,
+) -> impl Stream<Item = BookSnapshot> {
+    let mut ticker = tokio::time::interval(interval.max(Duration::from_millis(1)));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    stream::unfold((ticker, book), move |(mut ticker, book)| async move {
+        ticker.tick().await;
+        let snapshot = book.snapshot(depth);
+        Some((snapshot, (ticker, book)))
+    })
+}
+
+async fn book_handler(
+    State(state): State<HttpState>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, BookError> {
+    let snapshot = book_snapshot(&state, query.as_deref())?;
+    let body = serde_json::to_string(&snapshot).unwrap_or_default();
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
+
+/// Server-Sent Events: one `data:` line of snapshot JSON per tick. The stream lives inside
+/// the connection, so it is dropped as soon as the client disconnects.
+async fn book_stream_handler(
+    State(state): State<HttpState>,
+    RawQuery(query): RawQuery,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, BookError> {
+    let (book, depth) = resolve_book(&state, query.as_deref())?;
+    let events = snapshot_stream(book, depth, state.stream_interval).map(|snapshot| {
+        let data = serde_json::to_string(&snapshot).unwrap_or_default();
+        Ok(Event::default().data(data))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Serve `/book` and `/book/stream` on `addr` until the task is dropped.
+pub async fn run_http_server(
+    addr: SocketAddr,
+    state: HttpState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let app = Router::new()
+        .route("/book", get(book_handler))
+        .route("/book/stream", get(book_stream_handler))
+        .with_state(state);
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Exchange, ExchangePrice, Side};
+
+    fn state() -> HttpState {
+        let btc = OrderBook::new("BTC-USDT".to_string());
+        for i in 0..5 {
+            btc.update_price_level(ExchangePrice::new(Exchange::Binance, 100 - i, 1, Side::Buy));
+        }
+        let eth = OrderBook::new("ETH-USDT".to_string());
+        HttpState {
+            books: Arc::new(HashMap::from([
+                ("BTC-USDT".to_string(), Arc::new(btc)),
+                ("ETH-USDT".to_string(), Arc::new(eth)),
+            ])),
+            default_pair: "BTC-USDT".to_string(),
+            default_depth: 3,
+            stream_interval: Duration::from_millis(250),
+        }
+    }
+
+    #[test]
+    fn resolves_pair_and_depth() {
+        let state = state();
+        let snapshot = book_snapshot(&state, None).unwrap();
+        assert_eq!(snapshot.symbol, "BTC-USDT");
+        assert_eq!(snapshot.bids.len(), 3);
+
+        let snapshot = book_snapshot(&state, Some("pair=BTC-USDT&depth=4")).unwrap();
+        assert_eq!(snapshot.bids.len(), 4);
+
+        let snapshot = book_snapshot(&state, Some("pair=ETH-USDT")).unwrap();
+        assert_eq!(snapshot.symbol, "ETH-USDT");
+    }
+
+    #[test]
+    fn rejects_unknown_pairs_and_bad_depth() {
+        let state = state();
+        assert_eq!(
+            book_snapshot(&state, Some("pair=DOGE-USDT")),
+            Err(BookError::UnknownPair("DOGE-USDT".to_string()))
+        );
+        for depth in ["0", "-1", "ten", "1001"] {
+            assert_eq!(
+                book_snapshot(&state, Some(&format!("depth={depth}"))),
+                Err(BookError::BadDepth(depth.to_string()))
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_emits_at_the_interval() {
+        let state = state();
+        let (book, depth) = resolve_book(&state, Some("depth=2")).unwrap();
+        let start = tokio::time::Instant::now();
+        let snapshots: Vec<_> = snapshot_stream(book.clone(), depth, state.stream_interval)
+            .take(3)
+            .collect()
+            .await;
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+        assert_eq!(snapshots[0].bids.len(), 2);
+
+        // Later events reflect updates made between ticks.
+        let mut stream = Box::pin(snapshot_stream(book.clone(), 1, state.stream_interval));
+        assert_eq!(stream.next().await.unwrap().bids[0].price.to_f64(), 1.0);
+        book.update_price_level(ExchangePrice::new(Exchange::Kraken, 101, 1, Side::Buy));
+        assert_eq!(stream.next().await.unwrap().bids[0].price.to_f64(), 1.01);
+    }
+}
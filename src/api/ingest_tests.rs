@@ -0,0 +1,162 @@
+//! End-to-end ingest tests: each client connects to a local WebSocket server that plays canned
+//! venue frames, and the levels it forwards are applied to an `OrderBook`.
+
+use std::future::Future;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::api::binance::BinanceClient;
+use crate::api::bitstamp::BitstampClient;
+use crate::api::coinbase::CoinbaseClient;
+use crate::api::{ClientError, Exchange, ExchangePrice, TradingPair};
+use crate::orderbook::OrderBook;
+
+/// One BTC in satoshis.
+const BTC: u64 = 100_000_000;
+
+/// Serve one connection on a local port: read the subscribe message if the venue sends one,
+/// play `frames`, close, and wait for the client to hang up. Returns the `ws://` URL.
+async fn serve(
+    frames: &'static [&'static str],
+    subscribes: bool,
+) -> (String, tokio::task::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        let mut ws = accept_async(tcp).await.unwrap();
+        if subscribes {
+            let subscribe = ws.next().await.unwrap().unwrap();
+            assert!(
+                subscribe.is_text(),
+                "expected a subscribe message, got {subscribe:?}"
+            );
+        }
+        for frame in frames {
+            ws.send(Message::text(*frame)).await.unwrap();
+        }
+        ws.close(None).await.unwrap();
+        // The client has handled every frame once it answers the close or drops the socket.
+        while let Some(Ok(_)) = ws.next().await {}
+    });
+    (url, server)
+}
+
+/// Run `listen` until the server has played its frames, then apply what it forwarded.
+async fn ingest<F>(
+    server: tokio::task::JoinHandle<()>,
+    mut rx: mpsc::Receiver<ExchangePrice>,
+    listen: F,
+) -> OrderBook
+where
+    F: Future<Output = Result<(), ClientError>> + Send + 'static,
+{
+    let client = tokio::spawn(listen);
+    server.await.unwrap();
+    client.abort();
+    let _ = client.await;
+
+    let book = OrderBook::new("BTC-USDT".to_string());
+    let mut updates = Vec::new();
+    while let Ok(update) = rx.try_recv() {
+        updates.push(update);
+    }
+    book.update_price_levels(&updates);
+    book
+}
+
+fn pair() -> TradingPair {
+    TradingPair::default_pair().with_price_decimals(Some(2))
+}
+
+#[tokio::test]
+async fn binance_partial_books_replace_and_diffs_update() {
+    static FRAMES: &[&str] = &[
+        r#"{"lastUpdateId":100,"bids":[["100.00","1.0"],["99.00","2.0"]],"asks":[["101.00","1.5"],["102.00","3.0"]]}"#,
+        r#"{"e":"depthUpdate","E":1700000000000,"s":"BTCUSDT","U":101,"u":102,"b":[["100.00","0.5"],["99.00","0"]],"a":[["101.50","1.0"]]}"#,
+        r#"{"lastUpdateId":200,"bids":[["98.00","1.0"]],"asks":[]}"#,
+    ];
+    let (url, server) = serve(FRAMES, false).await;
+    let (tx, rx) = mpsc::channel(64);
+    let mut client = BinanceClient::new(tx);
+    client.ws_url = url;
+    let book = ingest(server, rx, async move { client.listen_pair(pair()).await }).await;
+
+    // The second partial book drops the diffed levels and empties the ask side.
+    assert_eq!(book.top_bids_n(5), [(Exchange::Binance, 9_800, BTC)]);
+    assert!(book.top_asks_n(5).is_empty());
+}
+
+#[tokio::test]
+async fn binance_diff_sets_absolute_sizes() {
+    static FRAMES: &[&str] = &[
+        r#"{"lastUpdateId":100,"bids":[["100.00","1.0"],["99.00","2.0"]],"asks":[["101.00","1.5"],["102.00","3.0"]]}"#,
+        r#"{"e":"depthUpdate","E":1700000000000,"s":"BTCUSDT","U":101,"u":102,"b":[["100.00","0.5"],["99.00","0"]],"a":[["101.50","1.0"]]}"#,
+    ];
+    let (url, server) = serve(FRAMES, false).await;
+    let (tx, rx) = mpsc::channel(64);
+    let mut client = BinanceClient::new(tx);
+    client.ws_url = url;
+    let book = ingest(server, rx, async move { client.listen_pair(pair()).await }).await;
+
+    assert_eq!(book.top_bids_n(5), [(Exchange::Binance, 10_000, BTC / 2)]);
+    assert_eq!(
+        book.top_asks_n(5),
+        [
+            (Exchange::Binance, 10_100, 3 * BTC / 2),
+            (Exchange::Binance, 10_150, BTC),
+            (Exchange::Binance, 10_200, 3 * BTC),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn bitstamp_each_book_replaces_the_last() {
+    static FRAMES: &[&str] = &[
+        r#"{"event":"bts:subscription_succeeded","channel":"order_book_btcusdt","data":{}}"#,
+        r#"{"event":"data","channel":"order_book_btcusdt","data":{"timestamp":"1700000000","microtimestamp":"1700000000000000","bids":[["100.00","1.0"],["99.00","2.0"]],"asks":[["101.00","1.5"]]}}"#,
+        r#"{"event":"data","channel":"order_book_btcusdt","data":{"timestamp":"1700000001","microtimestamp":"1700000001000000","bids":[["100.00","0.5"]],"asks":[["101.00","1.5"],["102.00","1.0"]]}}"#,
+    ];
+    let (url, server) = serve(FRAMES, true).await;
+    let (tx, rx) = mpsc::channel(64);
+    let mut client = BitstampClient::new(tx);
+    client.ws_url = url;
+    let book = ingest(server, rx, async move { client.listen_pair(pair()).await }).await;
+
+    // 99.00 is not in the second book, so it is gone rather than left behind.
+    assert_eq!(book.top_bids_n(5), [(Exchange::Bitstamp, 10_000, BTC / 2)]);
+    assert_eq!(
+        book.top_asks_n(5),
+        [
+            (Exchange::Bitstamp, 10_100, 3 * BTC / 2),
+            (Exchange::Bitstamp, 10_200, BTC),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn coinbase_snapshot_then_l2updates() {
+    static FRAMES: &[&str] = &[
+        r#"{"type":"subscriptions","channels":[{"name":"level2","product_ids":["BTC-USDT"]}]}"#,
+        r#"{"type":"snapshot","product_id":"BTC-USDT","bids":[["100.00","1.0"],["99.00","2.0"]],"asks":[["101.00","1.5"]]}"#,
+        r#"{"type":"l2update","product_id":"BTC-USDT","time":"2023-11-14T22:13:20.000000Z","changes":[["buy","100.00","0.5"],["buy","99.00","0"],["sell","101.50","1.0"]]}"#,
+    ];
+    let (url, server) = serve(FRAMES, true).await;
+    let (tx, rx) = mpsc::channel(64);
+    let mut client = CoinbaseClient::new(tx);
+    client.ws_url = url;
+    let book = ingest(server, rx, async move { client.listen_pair(pair()).await }).await;
+
+    assert_eq!(book.top_bids_n(5), [(Exchange::Coinbase, 10_000, BTC / 2)]);
+    assert_eq!(
+        book.top_asks_n(5),
+        [
+            (Exchange::Coinbase, 10_100, 3 * BTC / 2),
+            (Exchange::Coinbase, 10_150, BTC),
+        ]
+    );
+}
@@ -0,0 +1,592 @@
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::instrument;
+
+use crate::api::{
+    ClientError, Exchange, ExchangePrice, PairContext, ReconnectConfig, SessionEnd, Side,
+    SideModes, SubscriptionRejected, TradingPair, UpdateMode, UpdateSender, reconnect_loop,
+};
+use crate::health::FEEDS;
+use crate::metrics::METRICS;
+use crate::util::{
+    ParseError, current_timestamp_ms, parse_price_scaled, parse_quantity_smallest_unit,
+};
+
+const KRAKEN_WS_URL: &str = "wss://ws.kraken.com";
+const KRAKEN_BOOK_DEPTH: u32 = 25;
+
+/// Kraken channels the client can read the book from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KrakenChannel {
+    /// The top `KRAKEN_BOOK_DEPTH` levels: a snapshot, then incremental updates.
+    #[default]
+    Book,
+    /// Best bid and ask with their volumes, pushed whenever either changes.
+    Spread,
+}
+
+impl KrakenChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KrakenChannel::Book => "book",
+            KrakenChannel::Spread => "spread",
+        }
+    }
+}
+
+impl std::str::FromStr for KrakenChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "book" => Ok(KrakenChannel::Book),
+            "spread" => Ok(KrakenChannel::Spread),
+            other => Err(format!("unknown Kraken channel '{other}'")),
+        }
+    }
+}
+
+pub struct KrakenClient {
+    tx: UpdateSender,
+    /// WebSocket endpoint; `KRAKEN_WS_URL` overrides it in `main`, e.g. for a sandbox or a mock server.
+    pub ws_url: String,
+    /// Which channel to subscribe to.
+    pub channel: KrakenChannel,
+    /// Backoff used to reconnect (and re-subscribe) after the stream drops.
+    pub reconnect: ReconnectConfig,
+}
+
+impl KrakenClient {
+    pub fn new(tx: impl Into<UpdateSender>) -> Self {
+        KrakenClient {
+            tx: tx.into(),
+            ws_url: KRAKEN_WS_URL.to_string(),
+            channel: KrakenChannel::default(),
+            reconnect: ReconnectConfig::default(),
+        }
+    }
+
+    /// Listen to a specific trading pair's channel on Kraken, reconnecting on drops.
+    pub async fn listen_pair(&self, pair: TradingPair) -> Result<(), ClientError> {
+        reconnect_loop(Exchange::Kraken, &self.reconnect, || {
+            self.run_session(&pair)
+        })
+        .await
+    }
+
+    /// One connection's lifetime: connect, subscribe, read until close/error.
+    /// Returns whether any data message was handled.
+    async fn run_session(&self, pair: &TradingPair) -> SessionEnd {
+        let mut received_any = false;
+        let mut error = None;
+        let ctx = pair.context();
+
+        match connect_async(self.ws_url.as_str()).await {
+            Ok((mut ws_stream, _)) => {
+                let subscription = match self.channel {
+                    KrakenChannel::Book => {
+                        serde_json::json!({ "name": "book", "depth": KRAKEN_BOOK_DEPTH })
+                    }
+                    KrakenChannel::Spread => serde_json::json!({ "name": "spread" }),
+                };
+                let subscribe_msg = serde_json::json!({
+                    "event": "subscribe",
+                    "pair": [pair.kraken_pair_name()],
+                    "subscription": subscription
+                });
+
+                if let Err(e) = ws_stream
+                    .send(Message::Text(subscribe_msg.to_string()))
+                    .await
+                {
+                    tracing::warn!(exchange = "kraken", error = %e, "subscribe failed");
+                    return SessionEnd {
+                        received_data: false,
+                        error: Some(ClientError::subscribe_send(Exchange::Kraken, e)),
+                    };
+                }
+
+                let (mut write, mut read) = ws_stream.split();
+
+                while let Some(msg) = read.next().await {
+                    match msg {
+                        Ok(Message::Text(text)) => {
+                            let received_at = current_timestamp_ms();
+                            FEEDS.record_message(Exchange::Kraken, received_at);
+                            let result = self.handle_message(&text, received_at, &ctx).await;
+                            METRICS.record_message(Exchange::Kraken.as_str(), result.is_ok());
+                            received_any |= result
+                                .as_ref()
+                                .err()
+                                .is_none_or(ClientError::forwarded_levels);
+                            match result {
+                                Ok(()) => {}
+                                Err(e) if e.stops_client() => {
+                                    tracing::info!(exchange = "kraken", error = %e, "stopping client");
+                                    error = Some(e);
+                                    break;
+                                }
+                                Err(e) => {
+                                    METRICS.record_client_error(Exchange::Kraken.as_str(), &e);
+                                    tracing::debug!(exchange = "kraken", error = %e, "could not handle message");
+                                }
+                            }
+                        }
+                        // Answer pings from the read loop; see `session_writer`.
+                        Ok(Message::Ping(data)) => {
+                            let pong = write.send(Message::Pong(data)).await;
+                            if pong.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(Message::Close(frame)) => {
+                            tracing::info!(
+                                exchange = "kraken",
+                                ?frame,
+                                "connection closed by exchange"
+                            );
+                            error = Some(ClientError::Stream {
+                                exchange: Exchange::Kraken,
+                                reason: "closed by the exchange".to_string(),
+                            });
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::warn!(exchange = "kraken", error = %e, "websocket read failed");
+                            error = Some(ClientError::Stream {
+                                exchange: Exchange::Kraken,
+                                reason: e.to_string(),
+                            });
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!(exchange = "kraken", error = %e, "websocket connect failed");
+                error = Some(ClientError::Connect {
+                    exchange: Exchange::Kraken,
+                    source: Box::new(e),
+                });
+            }
+        }
+
+        SessionEnd {
+            received_data: received_any,
+            error,
+        }
+    }
+
+    /// Parse one book message and send its price levels to the aggregator.
+    async fn handle_message(
+        &self,
+        text: &str,
+        received_at: u64,
+        pair: &PairContext,
+    ) -> Result<(), ClientError> {
+        let mut levels = Vec::new();
+        let parsed = parse_kraken(text, received_at, pair, &mut levels);
+        for level in levels {
+            self.tx.send(level).await?;
+        }
+        parsed
+    }
+}
+
+/// Parse one book or spread message into `out`. Levels that parse are kept even when another level
+/// fails; that failure is returned as the error.
+#[instrument(skip(text, out), fields(exchange = "kraken", levels = tracing::field::Empty))]
+pub fn parse_kraken(
+    text: &str,
+    received_at: u64,
+    pair: &PairContext,
+    out: &mut Vec<ExchangePrice>,
+) -> Result<(), ClientError> {
+    tracing::trace!(text, "raw message");
+    if text.len() > 100_000 {
+        return Err(ClientError::protocol(Exchange::Kraken, "message too large"));
+    }
+
+    let v = {
+        let _span = tracing::info_span!("parse_json").entered();
+        serde_json::from_str::<Value>(text)
+            .map_err(|e| ClientError::protocol(Exchange::Kraken, e))?
+    };
+
+    // e.g. {"event":"subscriptionStatus","status":"error","errorMessage":"Currency pair not supported"}
+    if v.get("event").and_then(|e| e.as_str()) == Some("subscriptionStatus")
+        && v.get("status").and_then(|s| s.as_str()) == Some("error")
+    {
+        let reason = v
+            .get("errorMessage")
+            .and_then(|m| m.as_str())
+            .unwrap_or("unknown error");
+        return Err(SubscriptionRejected::new(Exchange::Kraken, reason).into());
+    }
+
+    // Events (heartbeat, systemStatus, subscriptionStatus) are objects;
+    // book data arrives as [channelID, payload, (payload,) channelName, pair].
+    let items = match v.as_array() {
+        Some(items) if items.len() >= 4 => items,
+        _ => return Ok(()),
+    };
+
+    // Spread data: [channelID, ["bid", "ask", "timestamp", "bidVolume", "askVolume"], "spread", pair].
+    if items[items.len() - 2].as_str() == Some("spread") {
+        let quote: Vec<&str> = items[1]
+            .as_array()
+            .map(|fields| fields.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let [bid, ask, timestamp, bid_volume, ask_volume, ..] = quote[..] else {
+            return Err(ClientError::protocol(
+                Exchange::Kraken,
+                "short spread payload",
+            ));
+        };
+        tracing::Span::current().record("levels", 2);
+        let mut level_error = None;
+        // Each side of the spread is the venue's whole side as far as this channel knows.
+        for (price, volume, side) in [(bid, bid_volume, Side::Buy), (ask, ask_volume, Side::Sell)] {
+            if let Some((price, quantity, exchange_timestamp)) =
+                parse_level(price, volume, timestamp, side, pair, &mut level_error)
+            {
+                out.push(
+                    ExchangePrice::new(Exchange::Kraken, price, quantity, side)
+                        .with_mode(UpdateMode::Replace)
+                        .with_timestamps(exchange_timestamp, received_at)
+                        .with_pair(pair.symbol.clone()),
+                );
+            }
+        }
+        return match level_error {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        };
+    }
+
+    let payloads = &items[1..items.len() - 2];
+    let levels: usize = payloads
+        .iter()
+        .flat_map(|payload| ["bs", "b", "as", "a"].map(|key| payload.get(key)))
+        .filter_map(|levels| levels?.as_array().map(Vec::len))
+        .sum();
+    tracing::Span::current().record("levels", levels);
+    let mut level_error = None;
+
+    for payload in payloads {
+        if let Some(checksum) = payload.get("c").and_then(|c| c.as_str()) {
+            tracing::debug!(
+                checksum,
+                "kraken book checksum received (not validated yet)"
+            );
+        }
+
+        // Snapshots use "bs"/"as" and replace the side, incremental updates use "b"/"a".
+        for (key, side, snapshot) in [
+            ("bs", Side::Buy, true),
+            ("b", Side::Buy, false),
+            ("as", Side::Sell, true),
+            ("a", Side::Sell, false),
+        ] {
+            if let Some(levels) = payload.get(key).and_then(|l| l.as_array()) {
+                let mut modes = SideModes::new(snapshot);
+                push_levels(
+                    levels,
+                    side,
+                    &mut modes,
+                    received_at,
+                    pair,
+                    out,
+                    &mut level_error,
+                );
+                if let Some(clear) = modes.clear_if_empty(Exchange::Kraken, side) {
+                    out.push(
+                        clear
+                            .with_timestamps(0, received_at)
+                            .with_pair(pair.symbol.clone()),
+                    );
+                }
+            }
+        }
+    }
+
+    match level_error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// Push `[["price", "volume", "timestamp"], ...]` levels for one side.
+/// A zero volume is forwarded so the book drops the level.
+fn push_levels(
+    levels: &[Value],
+    side: Side,
+    modes: &mut SideModes,
+    received_at: u64,
+    pair: &PairContext,
+    out: &mut Vec<ExchangePrice>,
+    level_error: &mut Option<ParseError>,
+) {
+    for level in levels {
+        if let Some(arr) = level.as_array()
+            && arr.len() >= 3
+            && let (Some(price), Some(volume), Some(timestamp)) =
+                (arr[0].as_str(), arr[1].as_str(), arr[2].as_str())
+            && let Some((price, quantity, exchange_timestamp)) =
+                parse_level(price, volume, timestamp, side, pair, level_error)
+        {
+            out.push(
+                ExchangePrice::new(Exchange::Kraken, price, quantity, side)
+                    .with_mode(modes.next_mode())
+                    .with_timestamps(exchange_timestamp, received_at)
+                    .with_pair(pair.symbol.clone()),
+            );
+        }
+    }
+}
+
+/// Parse one level's price, volume and "seconds.micros" timestamp strings into (price,
+/// quantity, exchange timestamp in ms), counting it in metrics. A bad price or volume is
+/// kept in `level_error` (the first one wins) and the level skipped.
+fn parse_level(
+    price: &str,
+    volume: &str,
+    timestamp: &str,
+    side: Side,
+    pair: &PairContext,
+    level_error: &mut Option<ParseError>,
+) -> Option<(u64, u64, u64)> {
+    let (price_res, quantity_res) = {
+        let _span = tracing::info_span!("process_levels", ?side).entered();
+        (
+            parse_price_scaled(price, pair.price_decimals),
+            parse_quantity_smallest_unit(volume, pair.base_decimals),
+        )
+    };
+    // Keep millisecond precision of the timestamp.
+    let exchange_timestamp = parse_quantity_smallest_unit(timestamp, 3).unwrap_or(0);
+
+    match (price_res, quantity_res) {
+        (Ok(price), Ok(quantity)) => {
+            METRICS.record_level_parsed(Exchange::Kraken.as_str());
+            Some((price, quantity, exchange_timestamp))
+        }
+        (Err(error), _) | (_, Err(error)) => {
+            METRICS.record_parse_error(Exchange::Kraken.as_str(), error);
+            level_error.get_or_insert(error);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{SubscribeError, UpdateMode, golden};
+    use crate::orderbook::OrderBook;
+    use tokio::sync::mpsc;
+
+    async fn parse(text: &str) -> Vec<ExchangePrice> {
+        let (tx, mut rx) = mpsc::channel(64);
+        let client = KrakenClient::new(tx);
+        let pair = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        client.handle_message(text, 42, &pair).await.unwrap();
+        drop(client);
+
+        let mut out = Vec::new();
+        while let Some(price) = rx.recv().await {
+            out.push(price);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn reports_subscription_errors() {
+        let (tx, _rx) = mpsc::channel(8);
+        let client = KrakenClient::new(tx);
+        let pair = TradingPair::default_pair().context();
+        let err = client
+            .handle_message(r#"{"event":"subscriptionStatus","status":"error","errorMessage":"Currency pair not supported XBT/USDX","pair":"XBT/USDX"}"#, 42, &pair)
+            .await
+            .unwrap_err();
+        let ClientError::Subscribe(SubscribeError::Rejected(rejected)) = err else {
+            panic!("expected a rejected subscription, got {err}");
+        };
+        assert_eq!(
+            rejected,
+            SubscriptionRejected::new(Exchange::Kraken, "Currency pair not supported XBT/USDX")
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_snapshot_levels() {
+        let text = r#"[0,{"as":[["5541.30000","2.50700000","1534614248.123678"]],"bs":[["5541.20000","1.52900000","1534614248.765567"]]},"book-25","XBT/USD"]"#;
+        let prices = parse(text).await;
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices[0].pair(), "BTC-USDT");
+        assert!(matches!(
+            prices[0],
+            ExchangePrice::Kraken {
+                price: 554120,
+                quantity: 152_900_000,
+                exchange_timestamp: 1_534_614_248_765,
+                received_at: 42,
+                side: Side::Buy,
+                ..
+            }
+        ));
+        assert!(matches!(
+            prices[1],
+            ExchangePrice::Kraken {
+                price: 554130,
+                quantity: 250_700_000,
+                side: Side::Sell,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn parses_split_update_with_checksum_and_removal() {
+        let text = r#"[1234,{"a":[["5541.30000","0.00000000","1534614335.345903"]]},{"b":[["5541.20000","1.00000000","1534614335.345903"]],"c":"974942666"},"book-25","XBT/USD"]"#;
+        let prices = parse(text).await;
+        assert_eq!(prices.len(), 2);
+        assert!(matches!(
+            prices[0],
+            ExchangePrice::Kraken {
+                quantity: 0,
+                side: Side::Sell,
+                ..
+            }
+        ));
+        assert!(matches!(
+            prices[1],
+            ExchangePrice::Kraken {
+                quantity: 100_000_000,
+                side: Side::Buy,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_spread_best_bid_and_ask() {
+        let text = r#"[0,["5698.40000","5700.00000","1542057299.545897","1.01234567","0.98765432"],"spread","XBT/USD"]"#;
+        let pair = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        let mut out = Vec::new();
+        parse_kraken(text, 42, &pair, &mut out).unwrap();
+        let levels: Vec<_> = out
+            .iter()
+            .map(|price| (price.level(), price.mode(), price.exchange_timestamp()))
+            .collect();
+        assert_eq!(
+            levels,
+            [
+                (
+                    (569_840, 101_234_567, Side::Buy),
+                    UpdateMode::Replace,
+                    1_542_057_299_545
+                ),
+                (
+                    (570_000, 98_765_432, Side::Sell),
+                    UpdateMode::Replace,
+                    1_542_057_299_545
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_events() {
+        let text = r#"{"event":"heartbeat"}"#;
+        assert!(parse(text).await.is_empty());
+    }
+
+    fn golden(text: &str) -> Vec<ExchangePrice> {
+        // Kraken quotes this book with 5 price and 8 volume decimals.
+        let pair = golden::context("ETH-XBT", 8, 5);
+        golden::parse(|out| parse_kraken(text, 42, &pair, out))
+    }
+
+    /// Kraken's book checksum: CRC-32 of the top ten asks then the top ten bids, each as its
+    /// price and volume digits without the decimal point or leading zeros, which with the
+    /// venue's own decimals is just the scaled integers.
+    fn checksum(book: &OrderBook) -> u32 {
+        let asks = golden::top(book, Side::Sell, 10);
+        let bids = golden::top(book, Side::Buy, 10);
+        let digits: String = asks
+            .into_iter()
+            .chain(bids)
+            .map(|(price, volume)| format!("{price}{volume}"))
+            .collect();
+        golden::crc32(digits.as_bytes())
+    }
+
+    /// The `"c"` a frame carries.
+    fn frame_checksum(text: &str) -> u32 {
+        let frame: Value = serde_json::from_str(text).unwrap();
+        let payloads = frame.as_array().unwrap();
+        payloads
+            .iter()
+            .find_map(|payload| payload.get("c")?.as_str()?.parse().ok())
+            .unwrap()
+    }
+
+    #[test]
+    fn golden_snapshot_matches_the_documented_checksum() {
+        let snapshot = golden(include_str!("testdata/kraken/snapshot.json"));
+        let levels = golden::levels(&snapshot);
+        assert_eq!(levels.len(), 20);
+        assert_eq!(levels[0], ((5_000, 500, Side::Buy), UpdateMode::Replace));
+        assert_eq!(levels[10], ((5_005, 500, Side::Sell), UpdateMode::Replace));
+        assert!(
+            levels
+                .iter()
+                .enumerate()
+                .all(|(i, (_, mode))| (*mode == UpdateMode::Replace) == (i % 10 == 0))
+        );
+        // Each level carries its own timestamp, in seconds with microseconds.
+        assert_eq!(snapshot[0].exchange_timestamp(), 1_582_905_487_439);
+        // The checksum Kraken's guide gives for this book.
+        assert_eq!(checksum(&golden::book_after(&[&snapshot])), 974_947_235);
+    }
+
+    #[test]
+    fn golden_update_matches_its_checksum() {
+        let snapshot = golden(include_str!("testdata/kraken/snapshot.json"));
+        let text = include_str!("testdata/kraken/update.json");
+        let update = golden(text);
+        assert_eq!(
+            golden::levels(&update),
+            [((5_000, 1_500, Side::Buy), UpdateMode::Delta)]
+        );
+        let book = golden::book_after(&[&snapshot, &update]);
+        assert_eq!(checksum(&book), frame_checksum(text));
+    }
+
+    #[test]
+    fn golden_removal_and_republish_match_the_checksum() {
+        // A zero volume removes the level; the level that moves into the top ten is
+        // republished with a trailing "r" and read like any other.
+        let snapshot = golden(include_str!("testdata/kraken/snapshot.json"));
+        let update = golden(include_str!("testdata/kraken/update.json"));
+        let text = include_str!("testdata/kraken/removal.json");
+        let removal = golden(text);
+        assert_eq!(
+            golden::levels(&removal),
+            [
+                ((5_005, 0, Side::Sell), UpdateMode::Delta),
+                ((5_055, 500, Side::Sell), UpdateMode::Delta),
+            ]
+        );
+        let book = golden::book_after(&[&snapshot, &update, &removal]);
+        assert_eq!(golden::top(&book, Side::Sell, 1), [(5_010, 500)]);
+        assert_eq!(checksum(&book), frame_checksum(text));
+    }
+}
@@ -1,46 +1,419 @@
 pub mod binance;
 pub mod bitstamp;
+pub mod bybit;
+pub mod coinbase;
+#[cfg(test)]
+mod golden;
+#[cfg(feature = "grpc")]
 pub mod grpc;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(test)]
+mod ingest_tests;
+pub mod kraken;
+pub mod okx;
+pub mod rest;
+#[cfg(feature = "ws-server")]
+pub mod ws_server;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use futures_util::{Sink, SinkExt, StreamExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::health::FEEDS;
+use crate::metrics::METRICS;
+use crate::util::{ParseError, current_timestamp_ms};
+
+/// Serializes as its lowercase name (`as_str`); deserializes from a name in any case or
+/// from its numeric `id`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Exchange {
     Binance,
     Bitstamp,
+    Kraken,
+    Okx,
+    Coinbase,
+    Bybit,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Exchange {
+    /// Every supported venue, in the order clients are spawned by default.
+    pub const ALL: [Exchange; 6] = [
+        Exchange::Binance,
+        Exchange::Bitstamp,
+        Exchange::Kraken,
+        Exchange::Okx,
+        Exchange::Coinbase,
+        Exchange::Bybit,
+    ];
+
+    /// `<EXCHANGE>_WS_URL` from the environment (e.g. `BINANCE_WS_URL`), for pointing the
+    /// venue's client at a sandbox or a local mock server instead of production.
+    pub fn ws_url_override(&self) -> Option<String> {
+        let key = format!("{}_WS_URL", self.as_str().to_ascii_uppercase());
+        std::env::var(key).ok().filter(|url| !url.trim().is_empty())
+    }
+
+    /// Lowercase venue name used in snapshots, gRPC levels and metrics labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Exchange::Binance => "binance",
+            Exchange::Bitstamp => "bitstamp",
+            Exchange::Kraken => "kraken",
+            Exchange::Okx => "okx",
+            Exchange::Coinbase => "coinbase",
+            Exchange::Bybit => "bybit",
+        }
+    }
+
+    /// The variant's name as written in Rust, e.g. `"Okx"`.
+    pub fn enum_name(&self) -> &'static str {
+        match self {
+            Exchange::Binance => "Binance",
+            Exchange::Bitstamp => "Bitstamp",
+            Exchange::Kraken => "Kraken",
+            Exchange::Okx => "Okx",
+            Exchange::Coinbase => "Coinbase",
+            Exchange::Bybit => "Bybit",
+        }
+    }
+
+    /// Stable numeric id for consumers that key on integers. Ids are fixed per venue and
+    /// never reused: a new venue takes the next free one, whatever its position in the enum.
+    pub fn id(&self) -> u8 {
+        match self {
+            Exchange::Binance => 1,
+            Exchange::Bitstamp => 2,
+            Exchange::Kraken => 3,
+            Exchange::Okx => 4,
+            Exchange::Coinbase => 5,
+            Exchange::Bybit => 6,
+        }
+    }
+
+    /// The venue with this `id`.
+    pub fn from_id(id: u8) -> Option<Exchange> {
+        Exchange::ALL
+            .into_iter()
+            .find(|exchange| exchange.id() == id)
+    }
+}
+
+impl<'de> Deserialize<'de> for Exchange {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Id(u8),
+            Name(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Id(id) => Exchange::from_id(id)
+                .ok_or_else(|| serde::de::Error::custom(format!("unknown exchange id {id}"))),
+            Repr::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl std::str::FromStr for Exchange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        Exchange::ALL
+            .into_iter()
+            .find(|exchange| exchange.as_str() == name)
+            .ok_or_else(|| {
+                let valid: Vec<&str> = Exchange::ALL.iter().map(Exchange::as_str).collect();
+                format!("unknown exchange '{name}' (valid: {})", valid.join(", "))
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Side {
     Buy,
     Sell,
 }
 
+/// Fallback precision for assets missing from `asset_decimals`.
+const DEFAULT_ASSET_DECIMALS: u32 = 8;
+
+/// Assets we've already warned about falling back to `DEFAULT_ASSET_DECIMALS`.
+static WARNED_UNKNOWN_ASSETS: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Smallest-unit precision we keep for an asset's quantities.
+///
+/// This is the precision exchanges quote sizes with, not the on-chain precision:
+/// ETH is kept at 8 rather than 18 decimals so large sizes still fit in a `u64`.
+fn asset_decimals(asset: &str) -> Option<u32> {
+    match asset {
+        "BTC" | "XBT" | "ETH" | "LTC" | "BCH" | "DOGE" | "DOT" | "LINK" | "AVAX" => Some(8),
+        "SOL" => Some(9),
+        "XRP" | "ADA" | "USDT" | "USDC" | "TRX" => Some(6),
+        "USD" | "EUR" | "GBP" => Some(2),
+        _ => None,
+    }
+}
+
+/// Alternative asset codes and per-venue spellings, shared by every `TradingPair` unless
+/// `with_asset_aliases` swaps in another table.
+static DEFAULT_ASSET_ALIASES: LazyLock<Arc<AssetAliases>> =
+    LazyLock::new(|| Arc::new(AssetAliases::default()));
+
+/// Normalizes asset codes so one logical pair maps to each venue's own symbol.
+///
+/// `canonical` folds alternative codes into one (XBT → BTC), so `XBT-USD` and `BTC-USD` are
+/// the same pair. `venue_names` spells a canonical code the way one venue expects it
+/// (BTC → XBT on Kraken). Overridable with `ASSET_ALIASES=alias:canonical,...` and
+/// `ASSET_NAMES_<EXCHANGE>=canonical:venue,...`; each replaces its default table.
+#[derive(Debug, Clone)]
+pub struct AssetAliases {
+    canonical: HashMap<String, String>,
+    venue_names: HashMap<(Exchange, String), String>,
+}
+
+impl Default for AssetAliases {
+    fn default() -> Self {
+        AssetAliases {
+            canonical: HashMap::from([("XBT".to_string(), "BTC".to_string())]),
+            venue_names: HashMap::from([(
+                (Exchange::Kraken, "BTC".to_string()),
+                "XBT".to_string(),
+            )]),
+        }
+    }
+}
+
+impl AssetAliases {
+    /// The defaults, with any table set in the environment replacing its default.
+    /// Malformed entries are reported and skipped.
+    pub fn from_env() -> Self {
+        let mut aliases = AssetAliases::default();
+        if let Ok(list) = std::env::var("ASSET_ALIASES") {
+            aliases.canonical = Self::parse_list(&list, "ASSET_ALIASES").collect();
+        }
+        for exchange in Exchange::ALL {
+            let key = format!("ASSET_NAMES_{}", exchange.as_str().to_ascii_uppercase());
+            if let Ok(list) = std::env::var(&key) {
+                aliases
+                    .venue_names
+                    .retain(|(venue, _), _| *venue != exchange);
+                aliases.venue_names.extend(
+                    Self::parse_list(&list, &key)
+                        .map(|(canonical, name)| ((exchange, canonical), name)),
+                );
+            }
+        }
+        aliases
+    }
+
+    /// `from:to` entries of a comma-separated list, uppercased; `source` names it in warnings.
+    fn parse_list<'a>(
+        list: &'a str,
+        source: &'a str,
+    ) -> impl Iterator<Item = (String, String)> + 'a {
+        list.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(move |entry| match entry.split_once(':') {
+                Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => Some((
+                    from.trim().to_ascii_uppercase(),
+                    to.trim().to_ascii_uppercase(),
+                )),
+                _ => {
+                    tracing::warn!(source, entry, "ignoring asset alias; expected from:to");
+                    None
+                }
+            })
+    }
+
+    /// The canonical code for `asset`, uppercased.
+    fn canonical(&self, asset: &str) -> String {
+        let asset = asset.to_ascii_uppercase();
+        self.canonical.get(&asset).cloned().unwrap_or(asset)
+    }
+
+    /// How `exchange` spells the canonical `asset`.
+    fn venue_name(&self, exchange: Exchange, asset: String) -> String {
+        self.venue_names
+            .get(&(exchange, asset.clone()))
+            .cloned()
+            .unwrap_or(asset)
+    }
+}
+
+/// Pair of an `ExchangePrice` built without `with_pair`.
+static NO_PAIR: LazyLock<Arc<str>> = LazyLock::new(|| Arc::from(""));
+
+/// Characters accepted between the base and quote asset of a pair.
+const PAIR_SEPARATORS: [char; 3] = ['-', '_', '/'];
+
 /// Logical trading pair shared across exchanges, configured at runtime.
 ///
 /// Stored in a normalized "raw" string form (as provided via env),
-/// and converted per-exchange as needed.
+/// and converted per-exchange as needed. Parsing guarantees it is `BASE<sep>QUOTE` with
+/// alphanumeric assets and a single separator from `PAIR_SEPARATORS`.
 #[derive(Debug, Clone)]
 pub struct TradingPair {
     raw: String,
+    /// Overrides the price scale derived from the quote asset (`--price-decimals`).
+    price_decimals: Option<u32>,
+    /// Venue symbols to use instead of the derived ones (`SYMBOL_OVERRIDE_<EXCHANGE>`).
+    symbol_overrides: BTreeMap<Exchange, String>,
+    /// Asset normalization used for the derived symbols and precision lookups.
+    aliases: Arc<AssetAliases>,
 }
 
-impl TradingPair {
-    /// Create from a string; returns `None` if empty/whitespace.
-    pub fn from_str(s: &str) -> Option<Self> {
-        let trimmed = s.trim();
-        if trimmed.is_empty() {
-            None
-        } else {
-            Some(TradingPair {
-                raw: trimmed.to_string(),
-            })
+impl std::str::FromStr for TradingPair {
+    type Err = String;
+
+    /// Accept `BASE-QUOTE` (or '/' / '_' as the separator) with non-empty alphanumeric
+    /// assets, e.g. "BTC-USDT" or "eth/usdt", ignoring surrounding whitespace.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = s.trim();
+        let problem = match raw.split(PAIR_SEPARATORS).collect::<Vec<_>>()[..] {
+            [""] => Some("it is empty".to_string()),
+            [_] => Some("there is no separator between base and quote".to_string()),
+            ["", _] => Some("the base asset is empty".to_string()),
+            [_, ""] => Some("the quote asset is empty".to_string()),
+            [base, quote] => base
+                .chars()
+                .chain(quote.chars())
+                .find(|c| !c.is_ascii_alphanumeric())
+                .map(|c| format!("'{c}' is not a letter or digit")),
+            _ => Some("it has more than one separator".to_string()),
+        };
+        match problem {
+            Some(problem) => Err(format!(
+                "invalid trading pair '{raw}': {problem}; expected BASE-QUOTE, e.g. BTC-USDT"
+            )),
+            None => Ok(TradingPair {
+                raw: raw.to_string(),
+                price_decimals: None,
+                symbol_overrides: BTreeMap::new(),
+                aliases: DEFAULT_ASSET_ALIASES.clone(),
+            }),
         }
     }
+}
+
+impl TradingPair {
+    /// Parse a comma-separated list such as "BTC-USDT, ETH-USDT", skipping empty entries
+    /// and duplicates. Fails on the first malformed pair.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        let mut pairs = s
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<TradingPair>, String>>()?;
+        Self::dedup(&mut pairs);
+        Ok(pairs)
+    }
+
+    /// Drop pairs that name the same assets as an earlier one once aliases are applied
+    /// (e.g. `XBT-USD` after `BTC-USD`), keeping the first.
+    pub fn dedup(pairs: &mut Vec<Self>) {
+        let mut seen = HashSet::new();
+        pairs.retain(|pair| seen.insert(pair.assets()));
+    }
 
     /// Default trading pair when none is configured.
     pub fn default_pair() -> Self {
         // Use a common default; user can override via TRADING_PAIR env.
         TradingPair {
             raw: "BTC-USDT".to_string(),
+            price_decimals: None,
+            symbol_overrides: BTreeMap::new(),
+            aliases: DEFAULT_ASSET_ALIASES.clone(),
+        }
+    }
+
+    /// Canonical base and quote asset codes, uppercased, e.g. ("BTC", "USDT") for "xbt-usdt".
+    fn assets(&self) -> (String, String) {
+        let (base, quote) = self
+            .raw
+            .split_once(PAIR_SEPARATORS)
+            .unwrap_or((&self.raw, ""));
+        (self.aliases.canonical(base), self.aliases.canonical(quote))
+    }
+
+    /// Normalize assets with `aliases` instead of the default table.
+    pub fn with_asset_aliases(mut self, aliases: Arc<AssetAliases>) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Decimals used to scale base-asset quantities into smallest units.
+    pub fn base_decimals(&self) -> u32 {
+        Self::decimals_or_default(self.assets().0)
+    }
+
+    /// Decimals of the quote asset (e.g. 6 for USDT).
+    pub fn quote_decimals(&self) -> u32 {
+        Self::decimals_or_default(self.assets().1)
+    }
+
+    /// Decimals prices are kept with: the override if set, otherwise the quote asset's
+    /// precision (never below 2), so e.g. USDT prices keep 6 places and USD prices cents.
+    pub fn price_decimals(&self) -> u32 {
+        self.price_decimals
+            .unwrap_or_else(|| self.quote_decimals().max(2))
+    }
+
+    /// Keep prices with exactly `decimals` places instead of the quote asset's precision.
+    pub fn with_price_decimals(mut self, decimals: Option<u32>) -> Self {
+        self.price_decimals = decimals;
+        self
+    }
+
+    /// Use the venue symbols in `overrides` whose key is this pair's derived symbol.
+    pub fn with_symbol_overrides(mut self, overrides: &SymbolOverrides) -> Self {
+        for exchange in Exchange::ALL {
+            let derived = self.derived_symbol(exchange);
+            if let Some(symbol) = overrides.get(exchange, &derived) {
+                self.symbol_overrides.insert(exchange, symbol.to_string());
+            }
+        }
+        self
+    }
+
+    /// Look up `asset`, falling back to 8 decimals and warning once per unknown asset.
+    fn decimals_or_default(asset: String) -> u32 {
+        if let Some(decimals) = asset_decimals(&asset) {
+            return decimals;
+        }
+        let mut warned = match WARNED_UNKNOWN_ASSETS.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if warned.insert(asset.clone()) {
+            tracing::warn!(
+                asset,
+                decimals = DEFAULT_ASSET_DECIMALS,
+                "no decimal precision known for asset; assuming the default"
+            );
+        }
+        DEFAULT_ASSET_DECIMALS
+    }
+
+    /// Values clients need per message, computed once per subscription.
+    pub fn context(&self) -> PairContext {
+        PairContext {
+            symbol: Arc::from(self.raw.as_str()),
+            base_decimals: self.base_decimals(),
+            price_decimals: self.price_decimals(),
         }
     }
 
@@ -49,50 +422,1787 @@ impl TradingPair {
         &self.raw
     }
 
-    /// Symbol used on Binance, e.g. "ETHUSDT", "SOLUSDT" (lowercased internally).
+    /// The symbol `exchange` knows this pair by: an override if one is configured, otherwise
+    /// the one derived from the base and quote assets.
+    pub fn symbol(&self, exchange: Exchange) -> String {
+        match self.symbol_overrides.get(&exchange) {
+            Some(symbol) => symbol.clone(),
+            None => self.derived_symbol(exchange),
+        }
+    }
+
+    /// The venue's usual spelling of base and quote: "btcusdt" on Binance and Bitstamp,
+    /// "BTCUSDT" on Bybit, "BTC-USDT" on OKX and Coinbase, "XBT/USDT" on Kraken.
+    fn derived_symbol(&self, exchange: Exchange) -> String {
+        let (base, quote) = self.assets();
+        let base = self.aliases.venue_name(exchange, base);
+        let quote = self.aliases.venue_name(exchange, quote);
+        match exchange {
+            Exchange::Binance | Exchange::Bitstamp => format!("{base}{quote}").to_ascii_lowercase(),
+            Exchange::Okx | Exchange::Coinbase => format!("{base}-{quote}"),
+            Exchange::Kraken => format!("{base}/{quote}"),
+            Exchange::Bybit => format!("{base}{quote}"),
+        }
+    }
+
+    /// Symbol used on Binance, e.g. "ethusdt", "solusdt".
     pub fn binance_symbol(&self) -> String {
-        // Drop separators and lowercase.
-        self.raw
-            .chars()
-            .filter(|c| !matches!(c, '-' | '_' | '/'))
-            .collect::<String>()
-            .to_ascii_lowercase()
+        self.symbol(Exchange::Binance)
     }
 
     /// Pair code used on Bitstamp channels, e.g. "btcusd".
     pub fn bitstamp_pair_code(&self) -> String {
-        self.raw
-            .chars()
-            .filter(|c| !matches!(c, '-' | '_' | '/'))
-            .collect::<String>()
-            .to_ascii_lowercase()
+        self.symbol(Exchange::Bitstamp)
+    }
+
+    /// Instrument id used on OKX, e.g. "BTC-USDT".
+    pub fn okx_inst_id(&self) -> String {
+        self.symbol(Exchange::Okx)
+    }
+
+    /// Product id used on Coinbase, e.g. "BTC-USD".
+    pub fn coinbase_product_id(&self) -> String {
+        self.symbol(Exchange::Coinbase)
+    }
+
+    /// Pair name used on Kraken subscriptions, e.g. "XBT/USDT".
+    pub fn kraken_pair_name(&self) -> String {
+        self.symbol(Exchange::Kraken)
+    }
+
+    /// Symbol used in Bybit topics, e.g. "BTCUSDT".
+    pub fn bybit_symbol(&self) -> String {
+        self.symbol(Exchange::Bybit)
+    }
+}
+
+/// The pairs to subscribe to on each of `exchanges`, in configured order. A feed subscribed
+/// twice would apply every update twice, so a venue listed again, or the same pair (same book
+/// key) listed again for a venue, is logged as an error and dropped, keeping the first. Two
+/// different pairs whose venue symbols match (ignoring case), e.g. through a symbol override,
+/// are both kept and logged as a warning, since each feeds its own book.
+pub fn subscriptions(
+    exchanges: &[Exchange],
+    pairs: &[TradingPair],
+) -> Vec<(Exchange, Vec<TradingPair>)> {
+    let mut plan: Vec<(Exchange, Vec<TradingPair>)> = Vec::new();
+    for &exchange in exchanges {
+        if plan.iter().any(|(planned, _)| *planned == exchange) {
+            tracing::error!(
+                exchange = exchange.as_str(),
+                "exchange listed more than once; subscribing once"
+            );
+            continue;
+        }
+        let mut symbols: HashMap<String, &TradingPair> = HashMap::new();
+        let mut venue_pairs: Vec<TradingPair> = Vec::new();
+        for pair in pairs {
+            if venue_pairs
+                .iter()
+                .any(|planned| planned.as_str() == pair.as_str())
+            {
+                tracing::error!(
+                    exchange = exchange.as_str(),
+                    pair = pair.as_str(),
+                    "pair listed more than once; subscribing once"
+                );
+                continue;
+            }
+            let symbol = pair.symbol(exchange);
+            if let Some(first) = symbols.get(&symbol.to_ascii_lowercase()) {
+                tracing::warn!(
+                    exchange = exchange.as_str(),
+                    symbol,
+                    pair = pair.as_str(),
+                    first = first.as_str(),
+                    "pair reads the same venue symbol as an earlier pair"
+                );
+            } else {
+                symbols.insert(symbol.to_ascii_lowercase(), pair);
+            }
+            venue_pairs.push(pair.clone());
+        }
+        plan.push((exchange, venue_pairs));
+    }
+    plan
+}
+
+/// Per-venue symbol replacements, for venues that list a pair under another name than the
+/// one derived from it (e.g. Bitstamp's `btcusd` for BTC-USDT).
+///
+/// Read from `SYMBOL_OVERRIDE_<EXCHANGE>` env vars, one per venue, each a comma-separated
+/// list of `derived:actual` symbols, e.g. `SYMBOL_OVERRIDE_BITSTAMP=btcusdt:btcusd`.
+/// The derived side is matched ignoring case.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolOverrides {
+    by_exchange: HashMap<Exchange, HashMap<String, String>>,
+}
+
+impl SymbolOverrides {
+    /// Overrides from the `SYMBOL_OVERRIDE_<EXCHANGE>` env vars. Malformed entries are
+    /// reported and skipped.
+    pub fn from_env() -> Self {
+        let mut overrides = SymbolOverrides::default();
+        for exchange in Exchange::ALL {
+            let key = format!("SYMBOL_OVERRIDE_{}", exchange.as_str().to_ascii_uppercase());
+            if let Ok(value) = std::env::var(&key) {
+                overrides.add_list(exchange, &value, &key);
+            }
+        }
+        overrides
+    }
+
+    /// Add `derived:actual` entries from a comma-separated `list`; `source` names it in
+    /// warnings.
+    fn add_list(&mut self, exchange: Exchange, list: &str, source: &str) {
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once(':') {
+                Some((derived, actual))
+                    if !derived.trim().is_empty() && !actual.trim().is_empty() =>
+                {
+                    self.by_exchange.entry(exchange).or_default().insert(
+                        derived.trim().to_ascii_lowercase(),
+                        actual.trim().to_string(),
+                    );
+                }
+                _ => tracing::warn!(
+                    source,
+                    entry,
+                    "ignoring symbol override; expected derived:actual"
+                ),
+            }
+        }
+    }
+
+    /// The replacement for `derived` on `exchange`, if any.
+    fn get(&self, exchange: Exchange, derived: &str) -> Option<&str> {
+        self.by_exchange
+            .get(&exchange)?
+            .get(&derived.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+/// What a client attaches to or needs for every update of one pair.
+#[derive(Debug, Clone)]
+pub struct PairContext {
+    /// Pair as configured, copied into each `ExchangePrice`.
+    pub symbol: Arc<str>,
+    /// Decimals for scaling base-asset quantities (`TradingPair::base_decimals`).
+    pub base_decimals: u32,
+    /// Decimals for scaling prices (`TradingPair::price_decimals`).
+    pub price_decimals: u32,
+}
+
+/// What every level parsed from one message is stamped with.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageStamp<'a> {
+    pub pair: &'a PairContext,
+    /// Venue time of the message in ms, 0 when it carries none.
+    pub exchange_timestamp: u64,
+    /// Local receive time in ms.
+    pub received_at: u64,
+}
+
+impl MessageStamp<'_> {
+    /// `price` tagged with the message's timestamps and pair.
+    pub fn apply(&self, price: ExchangePrice) -> ExchangePrice {
+        price
+            .with_timestamps(self.exchange_timestamp, self.received_at)
+            .with_pair(self.pair.symbol.clone())
     }
 }
 
-#[derive(Debug)]
+/// A venue refused the subscription, e.g. for a symbol it does not list. Reconnecting
+/// would be refused the same way, so the client stops instead of retrying.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriptionRejected {
+    pub exchange: Exchange,
+    /// The venue's own explanation.
+    pub reason: String,
+}
+
+impl SubscriptionRejected {
+    pub fn new(exchange: Exchange, reason: impl Into<String>) -> Self {
+        SubscriptionRejected {
+            exchange,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rejected the subscription: {}",
+            self.exchange.as_str(),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for SubscriptionRejected {}
+
+/// Why subscribing to a venue's feed failed.
+#[derive(Debug, thiserror::Error)]
+pub enum SubscribeError {
+    /// The subscribe frame could not be written; retried like a dropped stream.
+    #[error("could not send subscribe to {}: {source}", .exchange.as_str())]
+    Send {
+        exchange: Exchange,
+        source: Box<tokio_tungstenite::tungstenite::Error>,
+    },
+    /// The venue refused the subscription; never retried.
+    #[error("{0}")]
+    Rejected(#[from] SubscriptionRejected),
+}
+
+/// What went wrong in an exchange client: returned from `listen_pair` when the client
+/// stops, and from `handle_message` for a message that could not be handled.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// The WebSocket could not be opened.
+    #[error("could not connect to {}: {source}", .exchange.as_str())]
+    Connect {
+        exchange: Exchange,
+        source: Box<tokio_tungstenite::tungstenite::Error>,
+    },
+    /// The subscription could not be sent, or the venue refused it.
+    #[error("{0}")]
+    Subscribe(#[from] SubscribeError),
+    /// The connection failed or was closed after it opened.
+    #[error("{} stream failed: {reason}", .exchange.as_str())]
+    Stream { exchange: Exchange, reason: String },
+    /// A message that is not what the venue's API promises: malformed JSON, oversized, or
+    /// out of sequence.
+    #[error("unexpected message from {}: {source}", .exchange.as_str())]
+    Protocol {
+        exchange: Exchange,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A price or quantity in a message did not parse; the message's other levels were
+    /// still forwarded.
+    #[error("invalid price level: {0}")]
+    Parse(#[from] ParseError),
+    /// The REST book snapshot a diff stream starts from could not be fetched or applied.
+    #[error("could not sync {} book snapshot: {reason}", .exchange.as_str())]
+    Snapshot { exchange: Exchange, reason: String },
+    /// The aggregator stopped receiving updates (shutdown); never retried.
+    #[error("update channel closed, dropping {} updates", .exchange.as_str())]
+    ChannelClosed { exchange: Exchange },
+}
+
+impl From<SubscriptionRejected> for ClientError {
+    fn from(rejected: SubscriptionRejected) -> Self {
+        ClientError::Subscribe(rejected.into())
+    }
+}
+
+impl ClientError {
+    /// The subscribe frame could not be sent on a freshly opened connection.
+    pub fn subscribe_send(
+        exchange: Exchange,
+        source: tokio_tungstenite::tungstenite::Error,
+    ) -> Self {
+        ClientError::Subscribe(SubscribeError::Send {
+            exchange,
+            source: Box::new(source),
+        })
+    }
+
+    pub fn protocol(
+        exchange: Exchange,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        ClientError::Protocol {
+            exchange,
+            source: source.into(),
+        }
+    }
+
+    /// Whether reconnecting cannot help, so the client should stop.
+    pub fn stops_client(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Subscribe(SubscribeError::Rejected(_)) | ClientError::ChannelClosed { .. }
+        )
+    }
+
+    /// Whether the message this error came from still delivered data: a `Parse` error
+    /// skips only the levels that did not parse and forwards the rest.
+    pub fn forwarded_levels(&self) -> bool {
+        matches!(self, ClientError::Parse(_))
+    }
+
+    /// Short label for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientError::Connect { .. } => "connect",
+            ClientError::Subscribe(_) => "subscribe",
+            ClientError::Stream { .. } => "stream",
+            ClientError::Protocol { .. } => "protocol",
+            ClientError::Parse(_) => "parse",
+            ClientError::Snapshot { .. } => "snapshot",
+            ClientError::ChannelClosed { .. } => "channel_closed",
+        }
+    }
+}
+
+/// How one connection ended, as reported to `reconnect_loop`.
+#[derive(Debug, Default)]
+pub struct SessionEnd {
+    /// At least one data message was handled.
+    pub received_data: bool,
+    /// What ended the session, if it did not just end cleanly.
+    pub error: Option<ClientError>,
+}
+
+/// Backoff settings used when an exchange connection drops or fails to open.
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    /// Delay before the first retry; doubles after every session that yields no data.
+    pub base_delay: Duration,
+    /// Upper bound for the retry delay.
+    pub max_delay: Duration,
+    /// Consecutive failed sessions tolerated before giving up (`None` retries forever).
+    pub max_retries: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(30),
+            max_retries: None,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Delay to use after `delay`: doubled, capped at `max_delay`.
+    fn next_delay(&self, delay: Duration) -> Duration {
+        delay.saturating_mul(2).min(self.max_delay)
+    }
+}
+
+/// Run `session` repeatedly, sleeping with exponential backoff (plus jitter) in between.
+///
+/// Each call to `session` should connect, (re-)subscribe and read until the socket
+/// closes, reporting whether at least one data message arrived and what ended it. A
+/// session that got data resets the backoff to `base_delay`; otherwise it counts towards
+/// `max_retries`, and giving up returns the last session's error. Errors that reconnecting
+/// cannot fix (a rejected subscription, a closed update channel) end the loop at once.
+/// Every session's error is counted in `METRICS`, and its start and end update `FEEDS`.
+pub async fn reconnect_loop<F, Fut>(
+    exchange: Exchange,
+    config: &ReconnectConfig,
+    mut session: F,
+) -> Result<(), ClientError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = SessionEnd>,
+{
+    let mut delay = config.base_delay;
+    let mut failures: u32 = 0;
+
+    loop {
+        FEEDS.record_connecting(exchange);
+        let mut end = session().await;
+        FEEDS.record_disconnected(exchange);
+        if let Some(error) = &end.error {
+            METRICS.record_client_error(exchange.as_str(), error);
+        }
+        if let Some(error) = end.error.take_if(|error| error.stops_client()) {
+            return Err(error);
+        }
+        if end.received_data {
+            delay = config.base_delay;
+            failures = 0;
+        } else {
+            failures += 1;
+            if config.max_retries.is_some_and(|max| failures > max) {
+                tracing::error!(
+                    exchange = exchange.as_str(),
+                    failures,
+                    "giving up after repeated failed connection attempts"
+                );
+                return Err(end.error.unwrap_or_else(|| ClientError::Stream {
+                    exchange,
+                    reason: "closed without sending data".to_string(),
+                }));
+            }
+        }
+
+        // Sleep somewhere in [delay / 2, delay] so venues don't see synchronized retries.
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        tokio::time::sleep(delay.mul_f64(jitter)).await;
+
+        if failures > 0 {
+            delay = config.next_delay(delay);
+        }
+    }
+}
+
+/// What an exchange client does with an update when the channel to the aggregator is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait for room. Nothing is lost, but the read loop falls behind the feed.
+    #[default]
+    Block,
+    /// Drop the update and count it, keeping the read loop at feed speed. A `Replace`
+    /// update still waits, since losing it would leave the venue's stale levels in the book.
+    Drop,
+}
+
+impl OverflowPolicy {
+    /// Label for the `agg_channel_full_total` counter: what happened to the update.
+    pub fn action(&self) -> &'static str {
+        match self {
+            OverflowPolicy::Block => "waited",
+            OverflowPolicy::Drop => "dropped",
+        }
+    }
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop" => Ok(OverflowPolicy::Drop),
+            other => Err(format!(
+                "unknown channel overflow policy '{other}' (valid: block, drop)"
+            )),
+        }
+    }
+}
+
+/// The exchange clients' end of the update channel. Every send tries the channel first,
+/// so a full channel is always counted and logged before `on_full` decides what happens.
+#[derive(Debug, Clone)]
+pub struct UpdateSender {
+    tx: mpsc::Sender<ExchangePrice>,
+    on_full: OverflowPolicy,
+}
+
+impl UpdateSender {
+    pub fn new(tx: mpsc::Sender<ExchangePrice>, on_full: OverflowPolicy) -> Self {
+        UpdateSender { tx, on_full }
+    }
+
+    /// Send one update to the aggregator. Fails with `ChannelClosed` once shutdown has
+    /// closed the channel.
+    pub async fn send(&self, price: ExchangePrice) -> Result<(), ClientError> {
+        let source = price.exchange();
+        let exchange = source.as_str();
+        match self.tx.try_send(price) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed(_)) => Err(ClientError::ChannelClosed { exchange: source }),
+            Err(TrySendError::Full(price)) => {
+                // A `Replace` opens a snapshot; dropping it would leave the venue's stale
+                // levels under the new ones, so it always waits.
+                let on_full = match price.mode() {
+                    UpdateMode::Replace => OverflowPolicy::Block,
+                    UpdateMode::Delta => self.on_full,
+                };
+                let action = on_full.action();
+                let total = METRICS.record_channel_full(exchange, action);
+                // Warn on the first and then every 1000th, so a sustained burst doesn't flood the log.
+                if total % 1000 == 1 {
+                    tracing::warn!(exchange, action, total, "update channel full");
+                }
+                if on_full == OverflowPolicy::Drop {
+                    return Ok(());
+                }
+                self.tx
+                    .send(price)
+                    .await
+                    .map_err(|_| ClientError::ChannelClosed { exchange: source })
+            }
+        }
+    }
+}
+
+impl From<mpsc::Sender<ExchangePrice>> for UpdateSender {
+    fn from(tx: mpsc::Sender<ExchangePrice>) -> Self {
+        UpdateSender::new(tx, OverflowPolicy::default())
+    }
+}
+
+/// Default period for venue heartbeats. OKX closes connections idle for 30s and Bybit and
+/// Bitstamp drop them soon after, so 20s leaves headroom under the shortest timeout.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Own one session's write half: forward `outgoing` frames (Pongs from the read loop) and,
+/// given a `heartbeat` frame and period, send it on that period, for venues that close
+/// connections they consider idle.
+///
+/// Pongs go through here because tungstenite only answers a Ping itself while the same
+/// stream is written to; once the stream is `split()` the read loop has to queue the Pong,
+/// and venues drop connections that leave Pings unanswered.
+///
+/// Returns once `outgoing` is closed, i.e. the read loop is done, or a write fails; the read
+/// loop in turn sees a failed send on `outgoing` and ends the session.
+pub async fn session_writer<S>(
+    mut write: S,
+    mut outgoing: mpsc::Receiver<Message>,
+    heartbeat: Option<(&'static str, Duration)>,
+) where
+    S: Sink<Message> + Unpin,
+{
+    let mut ticker = heartbeat.map(|(frame, period)| {
+        let ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        (frame, ticker)
+    });
+    loop {
+        let frame = tokio::select! {
+            frame = outgoing.recv() => match frame {
+                Some(frame) => frame,
+                None => break,
+            },
+            frame = next_heartbeat(ticker.as_mut()) => Message::Text(frame.to_string()),
+        };
+        if write.send(frame).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// The heartbeat frame once its next tick is due; never, without a heartbeat.
+async fn next_heartbeat(
+    ticker: Option<&mut (&'static str, tokio::time::Interval)>,
+) -> &'static str {
+    match ticker {
+        Some((frame, ticker)) => {
+            ticker.tick().await;
+            frame
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// What differs between venues whose clients share the connect / subscribe / read loop that
+/// `run` drives: where to connect, what to subscribe with and how to read a message.
+/// Reconnects, Pings, heartbeats, metrics, feed health and sending levels are `run`'s job.
+pub trait ExchangeClient: Sync {
+    const EXCHANGE: Exchange;
+
+    /// Parser state kept for one connection and reset on reconnect, e.g. Binance update ids.
+    type Session: Default + Send;
+
+    /// WebSocket URL to connect to for `pair`.
+    fn ws_url(&self, pair: &TradingPair) -> String;
+
+    /// Message sent right after connecting, or `None` when the URL alone selects the stream.
+    fn subscribe_msg(&self, pair: &TradingPair) -> Option<String>;
+
+    /// Parse one text frame, pushing its price levels onto `levels`. Levels pushed before an
+    /// error (e.g. one bad price among several) are still sent.
+    fn parse_message(
+        &self,
+        text: &str,
+        received_at: u64,
+        pair: &PairContext,
+        session: &mut Self::Session,
+        levels: &mut Vec<ExchangePrice>,
+    ) -> Result<(), ClientError>;
+
+    /// Backoff used to reconnect (and re-subscribe) after the stream drops.
+    fn reconnect(&self) -> &ReconnectConfig;
+
+    /// Frame to send periodically, and how often, for venues that drop idle connections.
+    fn heartbeat(&self) -> Option<(&'static str, Duration)> {
+        None
+    }
+
+    /// Whether `error` means the stream can no longer be trusted, so the session should end
+    /// and reconnect from scratch (Binance sequence gaps).
+    fn resyncs_on(&self, _error: &ClientError) -> bool {
+        false
+    }
+}
+
+/// Listen to `pair` with `client`, sending its levels on `tx` and reconnecting on drops
+/// through `reconnect_loop`. Returns when the client stops, as `listen_pair` does.
+pub async fn run<C: ExchangeClient>(
+    client: &C,
+    tx: &UpdateSender,
+    pair: TradingPair,
+) -> Result<(), ClientError> {
+    let url = client.ws_url(&pair);
+    let subscribe = client.subscribe_msg(&pair);
+    let ctx = pair.context();
+    reconnect_loop(C::EXCHANGE, client.reconnect(), || {
+        run_session(client, tx, &url, subscribe.as_deref(), &ctx)
+    })
+    .await
+}
+
+/// One connection's lifetime: connect, subscribe, read until close/error.
+async fn run_session<C: ExchangeClient>(
+    client: &C,
+    tx: &UpdateSender,
+    url: &str,
+    subscribe: Option<&str>,
+    pair: &PairContext,
+) -> SessionEnd {
+    let exchange = C::EXCHANGE;
+    let name = exchange.as_str();
+    let mut received_any = false;
+    let mut error = None;
+    let mut session = C::Session::default();
+    let mut levels = Vec::new();
+
+    let mut ws_stream = match connect_async(url).await {
+        Ok((ws_stream, _)) => ws_stream,
+        Err(e) => {
+            tracing::warn!(exchange = name, error = %e, "websocket connect failed");
+            return SessionEnd {
+                received_data: false,
+                error: Some(ClientError::Connect {
+                    exchange,
+                    source: Box::new(e),
+                }),
+            };
+        }
+    };
+
+    if let Some(subscribe) = subscribe
+        && let Err(e) = ws_stream.send(Message::Text(subscribe.to_string())).await
+    {
+        tracing::warn!(exchange = name, error = %e, "subscribe failed");
+        return SessionEnd {
+            received_data: false,
+            error: Some(ClientError::subscribe_send(exchange, e)),
+        };
+    }
+
+    // A writer task owns the sink: Pongs are handed to it, heartbeats run on its timer.
+    let (write, mut read) = ws_stream.split();
+    let (outgoing, outgoing_rx) = mpsc::channel(8);
+    let writer = tokio::spawn(session_writer(write, outgoing_rx, client.heartbeat()));
+
+    while let Some(msg) = read.next().await {
+        match msg {
+            Ok(Message::Text(text)) => {
+                let received_at = current_timestamp_ms();
+                FEEDS.record_message(exchange, received_at);
+                let mut result =
+                    client.parse_message(&text, received_at, pair, &mut session, &mut levels);
+                for price in levels.drain(..) {
+                    if let Err(e) = tx.send(price).await {
+                        result = Err(e);
+                        break;
+                    }
+                }
+                METRICS.record_message(name, result.is_ok());
+                received_any |= result
+                    .as_ref()
+                    .err()
+                    .is_none_or(ClientError::forwarded_levels);
+                match result {
+                    Ok(()) => {}
+                    Err(e) if e.stops_client() => {
+                        tracing::info!(exchange = name, error = %e, "stopping client");
+                        error = Some(e);
+                        break;
+                    }
+                    Err(e) if client.resyncs_on(&e) => {
+                        // Reconnecting starts a fresh stream and session state.
+                        METRICS.record_client_error(name, &e);
+                        tracing::warn!(exchange = name, error = %e, "resyncing");
+                        break;
+                    }
+                    Err(e) => {
+                        METRICS.record_client_error(name, &e);
+                        tracing::debug!(exchange = name, error = %e, "could not handle message");
+                    }
+                }
+            }
+            // Answer pings from the read loop; see `session_writer`.
+            Ok(Message::Ping(data)) => {
+                let pong = outgoing.send(Message::Pong(data)).await;
+                if pong.is_err() {
+                    break;
+                }
+            }
+            Ok(Message::Close(frame)) => {
+                tracing::info!(exchange = name, ?frame, "connection closed by exchange");
+                error = Some(ClientError::Stream {
+                    exchange,
+                    reason: "closed by the exchange".to_string(),
+                });
+                break;
+            }
+            Err(e) => {
+                tracing::warn!(exchange = name, error = %e, "websocket read failed");
+                error = Some(ClientError::Stream {
+                    exchange,
+                    reason: e.to_string(),
+                });
+                break;
+            }
+            _ => {}
+        }
+    }
+    writer.abort();
+
+    SessionEnd {
+        received_data: received_any,
+        error,
+    }
+}
+
+/// How an update applies to the venue's book. Quantities are always the level's new
+/// absolute size, as every venue sends them; 0 removes the level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateMode {
+    /// Set (or remove) this one level: an incremental update. Also read from `add`, the
+    /// mode recordings gave non-Bybit levels when updates were added to the resting size;
+    /// those sizes were absolute all along, so they replay correctly as `delta`.
+    #[default]
+    #[serde(alias = "add")]
+    Delta,
+    /// Opens a snapshot: the venue's other levels on this side are dropped, then the level
+    /// is set as with `Delta`.
+    Replace,
+}
+
+/// Modes for the levels of one side of one message. In a snapshot the first level sent
+/// opens it with `Replace`, and `clear_if_empty` covers a side that sent none; every other
+/// level is a `Delta`.
+#[derive(Debug, Clone, Copy)]
+pub struct SideModes {
+    replace_next: bool,
+}
+
+impl SideModes {
+    pub fn new(snapshot: bool) -> Self {
+        SideModes {
+            replace_next: snapshot,
+        }
+    }
+
+    /// Mode for the next level sent on this side.
+    pub fn next_mode(&mut self) -> UpdateMode {
+        if std::mem::take(&mut self.replace_next) {
+            UpdateMode::Replace
+        } else {
+            UpdateMode::Delta
+        }
+    }
+
+    /// A zero-size `Replace` level that empties `exchange`'s `side`, when this is a snapshot
+    /// side that has not sent a level of its own.
+    pub fn clear_if_empty(self, exchange: Exchange, side: Side) -> Option<ExchangePrice> {
+        self.replace_next
+            .then(|| ExchangePrice::new(exchange, 0, 0, side).with_mode(UpdateMode::Replace))
+    }
+}
+
+/// One price level update from one exchange. Serializes as a flat object tagged with the
+/// exchange, e.g. `{"exchange":"okx","price":..,"side":"buy","pair":"BTC-USDT",..}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "exchange", rename_all = "lowercase")]
 pub enum ExchangePrice {
     Binance {
-        price: u64,              // Price in cents
+        price: u64,              // Price in 10^-price_decimals units of the quote asset
         quantity: u64,           // Quantity in smallest unit (e.g., satoshis for BTC)
-        exchange_timestamp: u64, // Timestamp from the exchange
-        received_at: u64,        // Timestamp when we received the message
+        exchange_timestamp: u64, // Timestamp from the exchange (epoch ms, 0 if unknown)
+        received_at: u64,        // Timestamp when we received the message (epoch ms)
         side: Side,
+        pair: Arc<str>, // Trading pair as configured, e.g. "BTC-USDT"
+        #[serde(default)]
+        mode: UpdateMode, // `Replace` opens a snapshot of the venue's side
     },
     Bitstamp {
-        price: u64,              // Price in cents
+        price: u64,              // Price in 10^-price_decimals units of the quote asset
+        quantity: u64,           // Quantity in smallest unit (e.g., satoshis for BTC)
+        exchange_timestamp: u64, // Timestamp from the exchange (epoch ms, 0 if unknown)
+        received_at: u64,        // Timestamp when we received the message (epoch ms)
+        side: Side,
+        pair: Arc<str>, // Trading pair as configured, e.g. "BTC-USDT"
+        #[serde(default)]
+        mode: UpdateMode, // `Replace` opens a snapshot of the venue's side
+    },
+    Kraken {
+        price: u64,              // Price in 10^-price_decimals units of the quote asset
+        quantity: u64,           // Quantity in smallest unit (e.g., satoshis for BTC)
+        exchange_timestamp: u64, // Timestamp from the exchange (epoch ms, 0 if unknown)
+        received_at: u64,        // Timestamp when we received the message (epoch ms)
+        side: Side,
+        pair: Arc<str>, // Trading pair as configured, e.g. "BTC-USDT"
+        #[serde(default)]
+        mode: UpdateMode, // `Replace` opens a snapshot of the venue's side
+    },
+    Okx {
+        price: u64,              // Price in 10^-price_decimals units of the quote asset
+        quantity: u64,           // Quantity in smallest unit (e.g., satoshis for BTC)
+        exchange_timestamp: u64, // Timestamp from the exchange (epoch ms, 0 if unknown)
+        received_at: u64,        // Timestamp when we received the message (epoch ms)
+        side: Side,
+        pair: Arc<str>, // Trading pair as configured, e.g. "BTC-USDT"
+        #[serde(default)]
+        mode: UpdateMode, // `Replace` opens a snapshot of the venue's side
+    },
+    Coinbase {
+        price: u64,              // Price in 10^-price_decimals units of the quote asset
+        quantity: u64,           // Quantity in smallest unit (e.g., satoshis for BTC)
+        exchange_timestamp: u64, // Timestamp from the exchange (epoch ms, 0 if unknown)
+        received_at: u64,        // Timestamp when we received the message (epoch ms)
+        side: Side,
+        pair: Arc<str>, // Trading pair as configured, e.g. "BTC-USDT"
+        #[serde(default)]
+        mode: UpdateMode, // `Replace` opens a snapshot of the venue's side
+    },
+    Bybit {
+        price: u64,              // Price in 10^-price_decimals units of the quote asset
         quantity: u64,           // Quantity in smallest unit (e.g., satoshis for BTC)
-        exchange_timestamp: u64, // Timestamp from the exchange
-        received_at: u64,        // Timestamp when we received the message
+        exchange_timestamp: u64, // Timestamp from the exchange (epoch ms, 0 if unknown)
+        received_at: u64,        // Timestamp when we received the message (epoch ms)
         side: Side,
+        pair: Arc<str>, // Trading pair as configured, e.g. "BTC-USDT"
+        #[serde(default)]
+        mode: UpdateMode, // `Replace` opens a snapshot of the venue's side
     },
 }
 
 impl ExchangePrice {
+    /// Build an update for `exchange` with both timestamps defaulted to 0.
+    pub fn new(exchange: Exchange, price: u64, quantity: u64, side: Side) -> Self {
+        match exchange {
+            Exchange::Binance => ExchangePrice::Binance {
+                price,
+                quantity,
+                exchange_timestamp: 0,
+                received_at: 0,
+                side,
+                pair: NO_PAIR.clone(),
+                mode: UpdateMode::Delta,
+            },
+            Exchange::Bitstamp => ExchangePrice::Bitstamp {
+                price,
+                quantity,
+                exchange_timestamp: 0,
+                received_at: 0,
+                side,
+                pair: NO_PAIR.clone(),
+                mode: UpdateMode::Delta,
+            },
+            Exchange::Kraken => ExchangePrice::Kraken {
+                price,
+                quantity,
+                exchange_timestamp: 0,
+                received_at: 0,
+                side,
+                pair: NO_PAIR.clone(),
+                mode: UpdateMode::Delta,
+            },
+            Exchange::Okx => ExchangePrice::Okx {
+                price,
+                quantity,
+                exchange_timestamp: 0,
+                received_at: 0,
+                side,
+                pair: NO_PAIR.clone(),
+                mode: UpdateMode::Delta,
+            },
+            Exchange::Coinbase => ExchangePrice::Coinbase {
+                price,
+                quantity,
+                exchange_timestamp: 0,
+                received_at: 0,
+                side,
+                pair: NO_PAIR.clone(),
+                mode: UpdateMode::Delta,
+            },
+            Exchange::Bybit => ExchangePrice::Bybit {
+                price,
+                quantity,
+                exchange_timestamp: 0,
+                received_at: 0,
+                side,
+                pair: NO_PAIR.clone(),
+                mode: UpdateMode::Delta,
+            },
+        }
+    }
+
+    /// Set the exchange and receive timestamps (epoch millis).
+    pub fn with_timestamps(mut self, exchange_ts: u64, received_ts: u64) -> Self {
+        match &mut self {
+            ExchangePrice::Binance {
+                exchange_timestamp,
+                received_at,
+                ..
+            }
+            | ExchangePrice::Bitstamp {
+                exchange_timestamp,
+                received_at,
+                ..
+            }
+            | ExchangePrice::Kraken {
+                exchange_timestamp,
+                received_at,
+                ..
+            }
+            | ExchangePrice::Okx {
+                exchange_timestamp,
+                received_at,
+                ..
+            }
+            | ExchangePrice::Coinbase {
+                exchange_timestamp,
+                received_at,
+                ..
+            }
+            | ExchangePrice::Bybit {
+                exchange_timestamp,
+                received_at,
+                ..
+            } => {
+                *exchange_timestamp = exchange_ts;
+                *received_at = received_ts;
+            }
+        }
+        self
+    }
+
+    /// Tag the update with the trading pair it belongs to.
+    pub fn with_pair(mut self, symbol: Arc<str>) -> Self {
+        match &mut self {
+            ExchangePrice::Binance { pair, .. }
+            | ExchangePrice::Bitstamp { pair, .. }
+            | ExchangePrice::Kraken { pair, .. }
+            | ExchangePrice::Okx { pair, .. }
+            | ExchangePrice::Coinbase { pair, .. }
+            | ExchangePrice::Bybit { pair, .. } => *pair = symbol,
+        }
+        self
+    }
+
+    /// Apply the update with `mode` instead of the default `Delta`.
+    pub fn with_mode(mut self, update_mode: UpdateMode) -> Self {
+        match &mut self {
+            ExchangePrice::Binance { mode, .. }
+            | ExchangePrice::Bitstamp { mode, .. }
+            | ExchangePrice::Kraken { mode, .. }
+            | ExchangePrice::Okx { mode, .. }
+            | ExchangePrice::Coinbase { mode, .. }
+            | ExchangePrice::Bybit { mode, .. } => *mode = update_mode,
+        }
+        self
+    }
+
+    /// How the update applies to the venue's book.
+    pub fn mode(&self) -> UpdateMode {
+        match self {
+            ExchangePrice::Binance { mode, .. }
+            | ExchangePrice::Bitstamp { mode, .. }
+            | ExchangePrice::Kraken { mode, .. }
+            | ExchangePrice::Okx { mode, .. }
+            | ExchangePrice::Coinbase { mode, .. }
+            | ExchangePrice::Bybit { mode, .. } => *mode,
+        }
+    }
+
+    /// Trading pair this update belongs to ("" if never tagged).
+    pub fn pair(&self) -> &str {
+        match self {
+            ExchangePrice::Binance { pair, .. }
+            | ExchangePrice::Bitstamp { pair, .. }
+            | ExchangePrice::Kraken { pair, .. }
+            | ExchangePrice::Okx { pair, .. }
+            | ExchangePrice::Coinbase { pair, .. }
+            | ExchangePrice::Bybit { pair, .. } => pair,
+        }
+    }
+
+    /// The level this update sets: (price, quantity, side).
+    pub fn level(&self) -> (u64, u64, Side) {
+        match self {
+            ExchangePrice::Binance {
+                price,
+                quantity,
+                side,
+                ..
+            }
+            | ExchangePrice::Bitstamp {
+                price,
+                quantity,
+                side,
+                ..
+            }
+            | ExchangePrice::Kraken {
+                price,
+                quantity,
+                side,
+                ..
+            }
+            | ExchangePrice::Okx {
+                price,
+                quantity,
+                side,
+                ..
+            }
+            | ExchangePrice::Coinbase {
+                price,
+                quantity,
+                side,
+                ..
+            }
+            | ExchangePrice::Bybit {
+                price,
+                quantity,
+                side,
+                ..
+            } => (*price, *quantity, *side),
+        }
+    }
+
+    /// Exchange timestamp (epoch millis, 0 if unknown).
+    pub fn exchange_timestamp(&self) -> u64 {
+        match self {
+            ExchangePrice::Binance {
+                exchange_timestamp, ..
+            }
+            | ExchangePrice::Bitstamp {
+                exchange_timestamp, ..
+            }
+            | ExchangePrice::Kraken {
+                exchange_timestamp, ..
+            }
+            | ExchangePrice::Okx {
+                exchange_timestamp, ..
+            }
+            | ExchangePrice::Coinbase {
+                exchange_timestamp, ..
+            }
+            | ExchangePrice::Bybit {
+                exchange_timestamp, ..
+            } => *exchange_timestamp,
+        }
+    }
+
+    /// Local receive timestamp (epoch millis).
+    pub fn received_at(&self) -> u64 {
+        match self {
+            ExchangePrice::Binance { received_at, .. }
+            | ExchangePrice::Bitstamp { received_at, .. }
+            | ExchangePrice::Kraken { received_at, .. }
+            | ExchangePrice::Okx { received_at, .. }
+            | ExchangePrice::Coinbase { received_at, .. }
+            | ExchangePrice::Bybit { received_at, .. } => *received_at,
+        }
+    }
+
+    /// Exchange this update came from.
+    pub fn exchange(&self) -> Exchange {
+        match self {
+            ExchangePrice::Binance { .. } => Exchange::Binance,
+            ExchangePrice::Bitstamp { .. } => Exchange::Bitstamp,
+            ExchangePrice::Kraken { .. } => Exchange::Kraken,
+            ExchangePrice::Okx { .. } => Exchange::Okx,
+            ExchangePrice::Coinbase { .. } => Exchange::Coinbase,
+            ExchangePrice::Bybit { .. } => Exchange::Bybit,
+        }
+    }
+
     /// Exchange name for tracing/logging.
     pub fn exchange_name(&self) -> &'static str {
-        match self {
-            ExchangePrice::Binance { .. } => "binance",
-            ExchangePrice::Bitstamp { .. } => "bitstamp",
+        self.exchange().as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::str::FromStr;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let config = ReconnectConfig {
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(1),
+            max_retries: None,
+        };
+        let d1 = config.next_delay(config.base_delay);
+        let d2 = config.next_delay(d1);
+        let d3 = config.next_delay(d2);
+        assert_eq!(d1, Duration::from_millis(500));
+        assert_eq!(d2, Duration::from_secs(1));
+        assert_eq!(d3, Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_loop_gives_up_after_max_retries() {
+        let config = ReconnectConfig {
+            max_retries: Some(3),
+            ..ReconnectConfig::default()
+        };
+        let attempts = Cell::new(0);
+        let result = reconnect_loop(Exchange::Okx, &config, || {
+            attempts.set(attempts.get() + 1);
+            async { SessionEnd::default() }
+        })
+        .await;
+        // The first attempt plus three retries.
+        assert_eq!(attempts.get(), 4);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "okx stream failed: closed without sending data"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn successful_session_resets_retry_budget() {
+        let config = ReconnectConfig {
+            max_retries: Some(1),
+            ..ReconnectConfig::default()
+        };
+        // fail, succeed, fail, fail -> gives up on the fourth attempt.
+        let outcomes = [false, true, false, false];
+        let attempts = Cell::new(0);
+        let result = reconnect_loop(Exchange::Okx, &config, || {
+            let i = attempts.get();
+            attempts.set(i + 1);
+            let end = SessionEnd {
+                received_data: outcomes[i],
+                error: Some(ClientError::Stream {
+                    exchange: Exchange::Okx,
+                    reason: format!("attempt {i}"),
+                }),
+            };
+            async move { end }
+        })
+        .await;
+        assert_eq!(attempts.get(), 4);
+        // The error that made it give up is the last session's.
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "okx stream failed: attempt 3"
+        );
+    }
+
+    #[test]
+    fn unsent_subscription_is_retried_but_counted_as_subscribe() {
+        let err = ClientError::subscribe_send(
+            Exchange::Okx,
+            tokio_tungstenite::tungstenite::Error::ConnectionClosed,
+        );
+        assert!(!err.stops_client());
+        assert_eq!(err.kind(), "subscribe");
+        assert!(ClientError::from(SubscriptionRejected::new(Exchange::Okx, "no")).stops_client());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejected_subscription_stops_without_retrying() {
+        let config = ReconnectConfig::default();
+        let attempts = Cell::new(0);
+        let result = reconnect_loop(Exchange::Okx, &config, || {
+            attempts.set(attempts.get() + 1);
+            async {
+                SessionEnd {
+                    received_data: true,
+                    error: Some(
+                        SubscriptionRejected::new(Exchange::Okx, "no such instrument").into(),
+                    ),
+                }
+            }
+        })
+        .await;
+        assert_eq!(attempts.get(), 1);
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "okx rejected the subscription: no such instrument"
+        );
+    }
+
+    #[tokio::test]
+    async fn closed_update_channel_stops_the_client() {
+        let (tx, rx) = mpsc::channel(8);
+        let updates = UpdateSender::from(tx);
+        drop(rx);
+        let err = updates
+            .send(ExchangePrice::new(Exchange::Kraken, 100, 1, Side::Buy))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::ChannelClosed {
+                exchange: Exchange::Kraken
+            }
+        ));
+        assert_eq!(err.kind(), "channel_closed");
+
+        let attempts = Cell::new(0);
+        let result = reconnect_loop(Exchange::Kraken, &ReconnectConfig::default(), || {
+            attempts.set(attempts.get() + 1);
+            async {
+                SessionEnd {
+                    received_data: true,
+                    error: Some(ClientError::ChannelClosed {
+                        exchange: Exchange::Kraken,
+                    }),
+                }
+            }
+        })
+        .await;
+        assert_eq!(attempts.get(), 1);
+        assert!(result.unwrap_err().stops_client());
+    }
+
+    /// A venue whose messages are a bare price: bids, or a subscription error for "reject".
+    struct EchoClient {
+        reconnect: ReconnectConfig,
+    }
+
+    impl ExchangeClient for EchoClient {
+        const EXCHANGE: Exchange = Exchange::Okx;
+        type Session = u32;
+
+        fn ws_url(&self, _pair: &TradingPair) -> String {
+            unreachable!("tests connect to a local server")
+        }
+
+        fn subscribe_msg(&self, pair: &TradingPair) -> Option<String> {
+            Some(format!("subscribe {}", pair.as_str()))
         }
+
+        fn parse_message(
+            &self,
+            text: &str,
+            _received_at: u64,
+            pair: &PairContext,
+            messages: &mut u32,
+            levels: &mut Vec<ExchangePrice>,
+        ) -> Result<(), ClientError> {
+            *messages += 1;
+            if text == "reject" {
+                return Err(SubscriptionRejected::new(Exchange::Okx, "no").into());
+            }
+            // "100,x": forward 100, then report the level that did not parse.
+            if let Some((price, _)) = text.split_once(',') {
+                let price = price.parse().unwrap();
+                levels.push(ExchangePrice::new(Exchange::Okx, price, 1, Side::Buy));
+                return Err(ParseError::InvalidFormat.into());
+            }
+            let price = text
+                .parse()
+                .map_err(|e| ClientError::protocol(Exchange::Okx, e))?;
+            levels.push(
+                ExchangePrice::new(Exchange::Okx, price, *messages as u64, Side::Buy)
+                    .with_pair(pair.symbol.clone()),
+            );
+            Ok(())
+        }
+
+        fn reconnect(&self) -> &ReconnectConfig {
+            &self.reconnect
+        }
+    }
+
+    #[tokio::test]
+    async fn run_session_subscribes_and_forwards_parsed_levels() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            let subscribe = ws.next().await.unwrap().unwrap();
+            for text in ["100", "oops", "101", "reject", "102"] {
+                ws.send(Message::Text(text.to_string())).await.unwrap();
+            }
+            subscribe
+        });
+
+        let (tx, mut rx) = mpsc::channel(8);
+        let client = EchoClient {
+            reconnect: ReconnectConfig::default(),
+        };
+        let pair = TradingPair::default_pair();
+        let end = run_session(
+            &client,
+            &UpdateSender::from(tx),
+            &url,
+            client.subscribe_msg(&pair).as_deref(),
+            &pair.context(),
+        )
+        .await;
+
+        assert_eq!(
+            server.await.unwrap(),
+            Message::Text("subscribe BTC-USDT".to_string())
+        );
+        // The malformed message is skipped; the rejection ends the session before "102".
+        assert!(end.received_data);
+        assert!(matches!(
+            end.error,
+            Some(ClientError::Subscribe(SubscribeError::Rejected(_)))
+        ));
+        let mut levels = Vec::new();
+        while let Ok(price) = rx.try_recv() {
+            assert_eq!(price.pair(), "BTC-USDT");
+            levels.push(price.level());
+        }
+        assert_eq!(levels, [(100, 1, Side::Buy), (101, 3, Side::Buy)]);
+    }
+
+    #[tokio::test]
+    async fn partially_parsed_messages_count_as_received_data() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            for text in ["100,x", "reject"] {
+                ws.send(Message::Text(text.to_string())).await.unwrap();
+            }
+        });
+
+        let (tx, mut rx) = mpsc::channel(8);
+        let client = EchoClient {
+            reconnect: ReconnectConfig::default(),
+        };
+        let pair = TradingPair::default_pair();
+        let end = run_session(
+            &client,
+            &UpdateSender::from(tx),
+            &url,
+            None,
+            &pair.context(),
+        )
+        .await;
+
+        assert!(end.received_data);
+        assert_eq!(rx.try_recv().unwrap().level(), (100, 1, Side::Buy));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn session_writer_forwards_frames_and_sends_heartbeats() {
+        let (sent_tx, mut sent) = mpsc::unbounded_channel();
+        let sink = Box::pin(futures_util::sink::unfold(
+            sent_tx,
+            |tx, frame: Message| async move {
+                tx.send(frame).map_err(|_| ())?;
+                Ok::<_, ()>(tx)
+            },
+        ));
+        let (outgoing, outgoing_rx) = mpsc::channel(8);
+        let writer = tokio::spawn(session_writer(
+            sink,
+            outgoing_rx,
+            Some(("ping", Duration::from_secs(20))),
+        ));
+
+        outgoing.send(Message::Pong(vec![1])).await.unwrap();
+        assert_eq!(sent.recv().await, Some(Message::Pong(vec![1])));
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(sent.recv().await, Some(Message::Text("ping".to_string())));
+        assert_eq!(start.elapsed(), Duration::from_secs(20));
+
+        // Closing `outgoing` (the read loop finishing) stops the writer.
+        drop(outgoing);
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_channel_drops_or_waits_per_policy() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let update = |exchange| ExchangePrice::new(exchange, 100, 1, Side::Buy);
+
+        // Drop: the second update is discarded and counted, the first stays queued.
+        let dropping = UpdateSender::new(tx.clone(), OverflowPolicy::Drop);
+        dropping.send(update(Exchange::Coinbase)).await.unwrap();
+        dropping.send(update(Exchange::Coinbase)).await.unwrap();
+        assert_eq!(METRICS.channel_full_count("coinbase", "dropped"), 1);
+        assert_eq!(tx.capacity(), 0);
+
+        // Block: counted, then delivered once the aggregator makes room.
+        let blocking = UpdateSender::new(tx, OverflowPolicy::Block);
+        let send = tokio::spawn(async move { blocking.send(update(Exchange::Bitstamp)).await });
+        // Let the send find the channel full before the aggregator reads.
+        tokio::task::yield_now().await;
+        assert!(matches!(
+            rx.recv().await,
+            Some(ExchangePrice::Coinbase { .. })
+        ));
+        send.await.unwrap().unwrap();
+        assert!(matches!(
+            rx.recv().await,
+            Some(ExchangePrice::Bitstamp { .. })
+        ));
+        assert_eq!(METRICS.channel_full_count("bitstamp", "waited"), 1);
+    }
+
+    #[tokio::test]
+    async fn full_channel_never_drops_a_snapshot_opener() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let dropping = UpdateSender::new(tx, OverflowPolicy::Drop);
+        dropping
+            .send(ExchangePrice::new(Exchange::Kraken, 100, 1, Side::Buy))
+            .await
+            .unwrap();
+        let replace =
+            ExchangePrice::new(Exchange::Kraken, 101, 1, Side::Buy).with_mode(UpdateMode::Replace);
+        let send = tokio::spawn(async move { dropping.send(replace).await });
+        tokio::task::yield_now().await;
+        assert_eq!(rx.recv().await.unwrap().mode(), UpdateMode::Delta);
+        send.await.unwrap().unwrap();
+        assert_eq!(rx.recv().await.unwrap().mode(), UpdateMode::Replace);
+        assert_eq!(METRICS.channel_full_count("kraken", "waited"), 1);
+        assert_eq!(METRICS.channel_full_count("kraken", "dropped"), 0);
+    }
+
+    /// Sustained throughput with every exchange bursting into one book, per channel capacity.
+    /// Run with `cargo test --release channel_capacity_throughput -- --ignored --nocapture`.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn channel_capacity_throughput() {
+        const PER_EXCHANGE: u64 = 200_000;
+        for capacity in [100, 1_000, 10_000, 100_000] {
+            let (tx, mut rx) = mpsc::channel(capacity);
+            let book = crate::orderbook::OrderBook::new("BTC-USDT".to_string());
+            let start = std::time::Instant::now();
+            for exchange in Exchange::ALL {
+                let updates = UpdateSender::from(tx.clone());
+                tokio::spawn(async move {
+                    for i in 0..PER_EXCHANGE {
+                        let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+                        let price = ExchangePrice::new(exchange, 10_000 + i % 500, i, side);
+                        updates.send(price).await.unwrap();
+                    }
+                });
+            }
+            drop(tx);
+
+            let mut applied = 0u64;
+            while let Some(price) = rx.recv().await {
+                book.update_price_level(price);
+                applied += 1;
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "capacity {capacity:>7}: {applied} updates in {elapsed:?} ({:.0}/s)",
+                applied as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    #[test]
+    fn kraken_pair_name_uses_slash_separator() {
+        let pair = TradingPair::from_str("eth-usdt").unwrap();
+        assert_eq!(pair.kraken_pair_name(), "ETH/USDT");
+    }
+
+    #[test]
+    fn okx_inst_id_uses_dash_separator() {
+        let pair = TradingPair::from_str("btc/usdt").unwrap();
+        assert_eq!(pair.okx_inst_id(), "BTC-USDT");
+    }
+
+    #[test]
+    fn bybit_symbol_is_uppercase_without_separator() {
+        let pair = "xbt_usdt".parse::<TradingPair>().unwrap();
+        assert_eq!(pair.bybit_symbol(), "BTCUSDT");
+    }
+
+    #[test]
+    fn exchange_names_round_trip() {
+        for exchange in Exchange::ALL {
+            assert_eq!(exchange.as_str().parse::<Exchange>(), Ok(exchange));
+        }
+        assert_eq!(" OKX ".parse::<Exchange>(), Ok(Exchange::Okx));
+        assert!("ftx".parse::<Exchange>().is_err());
+    }
+
+    #[test]
+    fn exchange_ids_are_stable() {
+        // Downstream schemas key on these; changing one is a breaking change.
+        let ids: Vec<(Exchange, u8)> = Exchange::ALL.iter().map(|e| (*e, e.id())).collect();
+        assert_eq!(
+            ids,
+            [
+                (Exchange::Binance, 1),
+                (Exchange::Bitstamp, 2),
+                (Exchange::Kraken, 3),
+                (Exchange::Okx, 4),
+                (Exchange::Coinbase, 5),
+                (Exchange::Bybit, 6),
+            ]
+        );
+        for exchange in Exchange::ALL {
+            assert_eq!(Exchange::from_id(exchange.id()), Some(exchange));
+        }
+        assert_eq!(Exchange::from_id(0), None);
+    }
+
+    #[test]
+    fn exchange_serde_writes_the_name_and_reads_any_representation() {
+        assert_eq!(serde_json::to_string(&Exchange::Okx).unwrap(), r#""okx""#);
+        for text in [r#""okx""#, r#""Okx""#, "4"] {
+            assert_eq!(
+                serde_json::from_str::<Exchange>(text).unwrap(),
+                Exchange::Okx,
+                "{text}"
+            );
+        }
+        assert!(serde_json::from_str::<Exchange>("99").is_err());
+        assert!(serde_json::from_str::<Exchange>(r#""ftx""#).is_err());
+    }
+
+    #[test]
+    fn coinbase_product_id_uses_dash_separator() {
+        let pair = TradingPair::from_str("eth_usd").unwrap();
+        assert_eq!(pair.coinbase_product_id(), "ETH-USD");
+    }
+
+    #[test]
+    fn parse_list_splits_on_commas() {
+        let pairs = TradingPair::parse_list("BTC-USDT, ETH-USDT,,SOL-USDT,BTC-USDT").unwrap();
+        let raw: Vec<&str> = pairs.iter().map(|p| p.as_str()).collect();
+        assert_eq!(raw, vec!["BTC-USDT", "ETH-USDT", "SOL-USDT"]);
+        assert!(TradingPair::parse_list(" , ").unwrap().is_empty());
+        assert!(TradingPair::parse_list("BTC-USDT,BTCUSDT").is_err());
+    }
+
+    #[test]
+    fn symbol_overrides_replace_only_their_venue_and_symbol() {
+        let mut overrides = SymbolOverrides::default();
+        overrides.add_list(
+            Exchange::Bitstamp,
+            "BTCUSDT:btcusd, bogus, ethusdt:",
+            "test",
+        );
+        overrides.add_list(Exchange::Kraken, "xbt/usd:XXBTZUSD", "test");
+
+        let pair = "btc-usdt"
+            .parse::<TradingPair>()
+            .unwrap()
+            .with_symbol_overrides(&overrides);
+        assert_eq!(pair.bitstamp_pair_code(), "btcusd");
+        assert_eq!(pair.binance_symbol(), "btcusdt");
+        assert_eq!(pair.kraken_pair_name(), "XBT/USDT");
+
+        let pair = "BTC/USD"
+            .parse::<TradingPair>()
+            .unwrap()
+            .with_symbol_overrides(&overrides);
+        assert_eq!(pair.kraken_pair_name(), "XXBTZUSD");
+        assert_eq!(pair.bitstamp_pair_code(), "btcusd");
+
+        // Malformed entries were skipped rather than mapping to an empty symbol.
+        let eth = "ETH-USDT"
+            .parse::<TradingPair>()
+            .unwrap()
+            .with_symbol_overrides(&overrides);
+        assert_eq!(eth.bitstamp_pair_code(), "ethusdt");
+    }
+
+    #[test]
+    fn subscriptions_collapse_duplicate_exchanges_and_pairs() {
+        let mut overrides = SymbolOverrides::default();
+        overrides.add_list(Exchange::Bitstamp, "btcusdt:btcusd", "test");
+        // Both pairs read Bitstamp's btcusd book once the override applies; BTC-USDT is
+        // also listed twice.
+        let pairs: Vec<TradingPair> = ["BTC-USDT", "BTC-USD", "BTC-USDT"]
+            .into_iter()
+            .map(|raw| {
+                raw.parse::<TradingPair>()
+                    .unwrap()
+                    .with_symbol_overrides(&overrides)
+            })
+            .collect();
+
+        let plan = subscriptions(&[Exchange::Okx, Exchange::Bitstamp, Exchange::Okx], &pairs);
+        let plan: Vec<(Exchange, Vec<&str>)> = plan
+            .iter()
+            .map(|(exchange, pairs)| (*exchange, pairs.iter().map(TradingPair::as_str).collect()))
+            .collect();
+        assert_eq!(
+            plan,
+            [
+                (Exchange::Okx, vec!["BTC-USDT", "BTC-USD"]),
+                // A shared venue symbol is only warned about; each pair has its own book.
+                (Exchange::Bitstamp, vec!["BTC-USDT", "BTC-USD"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn aliased_assets_map_to_each_venues_spelling() {
+        // Kraken-style venue: BTC is listed as XBT, whichever code the pair was given in.
+        for configured in ["BTC-USD", "xbt/usd"] {
+            let pair = configured.parse::<TradingPair>().unwrap();
+            assert_eq!(pair.kraken_pair_name(), "XBT/USD", "{configured}");
+            assert_eq!(pair.coinbase_product_id(), "BTC-USD", "{configured}");
+            assert_eq!(pair.binance_symbol(), "btcusd", "{configured}");
+            assert_eq!(pair.base_decimals(), 8);
+        }
+        // Both spellings are one logical pair.
+        let pairs = TradingPair::parse_list("BTC-USD,XBT-USD,ETH-USD").unwrap();
+        let raw: Vec<&str> = pairs.iter().map(|p| p.as_str()).collect();
+        assert_eq!(raw, vec!["BTC-USD", "ETH-USD"]);
+
+        // A replaced table: USDT folds into USD, and Kraken keeps BTC.
+        let aliases = Arc::new(AssetAliases {
+            canonical: HashMap::from([
+                ("XBT".to_string(), "BTC".to_string()),
+                ("USDT".to_string(), "USD".to_string()),
+            ]),
+            venue_names: HashMap::new(),
+        });
+        let mut pairs: Vec<TradingPair> = TradingPair::parse_list("BTC-USD,XBT-USDT")
+            .unwrap()
+            .into_iter()
+            .map(|pair| pair.with_asset_aliases(aliases.clone()))
+            .collect();
+        TradingPair::dedup(&mut pairs);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].kraken_pair_name(), "BTC/USD");
+        assert_eq!(
+            AssetAliases::parse_list(" xbt:btc, bad ,:x", "test").collect::<Vec<_>>(),
+            vec![("XBT".to_string(), "BTC".to_string())]
+        );
+    }
+
+    #[test]
+    fn pairs_need_two_alphanumeric_assets_around_one_separator() {
+        for valid in ["BTC-USDT", " eth/usdt ", "sol_usdc", "1INCH-USDT"] {
+            assert!(valid.parse::<TradingPair>().is_ok(), "{valid}");
+        }
+        for (malformed, problem) in [
+            ("", "it is empty"),
+            ("BTCUSDT", "no separator"),
+            ("BTCUSDT-", "quote asset is empty"),
+            ("-USDT", "base asset is empty"),
+            ("btc/usd/t", "more than one separator"),
+            ("BTC--USDT", "more than one separator"),
+            ("BTC-US DT", "' ' is not a letter or digit"),
+            ("BTC.X-USDT", "'.' is not a letter or digit"),
+        ] {
+            let err = malformed.parse::<TradingPair>().unwrap_err();
+            assert!(err.contains(problem), "{malformed}: {err}");
+        }
+    }
+
+    #[test]
+    fn decimals_follow_the_asset_table() {
+        let pair = TradingPair::from_str("SOL-USDT").unwrap();
+        assert_eq!(pair.base_decimals(), 9);
+        assert_eq!(pair.quote_decimals(), 6);
+
+        let unknown = TradingPair::from_str("FOO/BAR").unwrap();
+        assert_eq!(unknown.base_decimals(), DEFAULT_ASSET_DECIMALS);
+    }
+
+    #[test]
+    fn price_decimals_follow_the_quote_asset() {
+        assert_eq!(
+            TradingPair::from_str("BTC-USDT").unwrap().price_decimals(),
+            6
+        );
+        assert_eq!(
+            TradingPair::from_str("BTC-USD").unwrap().price_decimals(),
+            2
+        );
+        let pair = TradingPair::from_str("PEPE-USD")
+            .unwrap()
+            .with_price_decimals(Some(10));
+        assert_eq!(pair.context().price_decimals, 10);
+    }
+
+    #[test]
+    fn new_defaults_timestamps_to_zero() {
+        match ExchangePrice::new(Exchange::Bitstamp, 100, 2, Side::Sell) {
+            ExchangePrice::Bitstamp {
+                price,
+                quantity,
+                exchange_timestamp,
+                received_at,
+                side,
+                ..
+            } => {
+                assert_eq!(price, 100);
+                assert_eq!(quantity, 2);
+                assert_eq!(exchange_timestamp, 0);
+                assert_eq!(received_at, 0);
+                assert!(matches!(side, Side::Sell));
+            }
+            other => panic!("unexpected variant: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn with_timestamps_sets_both_fields() {
+        let update =
+            ExchangePrice::new(Exchange::Binance, 101, 3, Side::Buy).with_timestamps(1_000, 1_005);
+        match update {
+            ExchangePrice::Binance {
+                price,
+                quantity,
+                exchange_timestamp,
+                received_at,
+                side,
+                ..
+            } => {
+                assert_eq!(price, 101);
+                assert_eq!(quantity, 3);
+                assert_eq!(exchange_timestamp, 1_000);
+                assert_eq!(received_at, 1_005);
+                assert!(matches!(side, Side::Buy));
+            }
+            other => panic!("unexpected variant: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn sides_and_exchanges_work_as_keys() {
+        let keys: HashSet<(Exchange, Side)> = [
+            (Exchange::Kraken, Side::Buy),
+            (Exchange::Kraken, Side::Sell),
+            (Exchange::Kraken, Side::Buy),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(serde_json::from_str::<Side>(r#""buy""#).unwrap(), Side::Buy);
+        assert_eq!(serde_json::to_string(&Exchange::Okx).unwrap(), r#""okx""#);
+    }
+
+    #[test]
+    fn exchange_price_round_trips_through_json() {
+        for exchange in Exchange::ALL {
+            let update = ExchangePrice::new(exchange, 10_050, 3, Side::Sell)
+                .with_timestamps(1_000, 1_250)
+                .with_pair("ETH-USDT".into());
+            let json = serde_json::to_value(&update).unwrap();
+            assert_eq!(json["exchange"], exchange.as_str());
+            assert_eq!(json["side"], "sell");
+
+            let back: ExchangePrice = serde_json::from_value(json).unwrap();
+            assert_eq!(back.exchange(), exchange);
+            assert_eq!(back.level(), (10_050, 3, Side::Sell));
+            assert_eq!(back.exchange_timestamp(), 1_000);
+            assert_eq!(back.received_at(), 1_250);
+            assert_eq!(back.pair(), "ETH-USDT");
+        }
+    }
+
+    #[test]
+    fn with_pair_tags_update() {
+        let untagged = ExchangePrice::new(Exchange::Okx, 100, 1, Side::Buy);
+        assert_eq!(untagged.pair(), "");
+
+        let ctx = TradingPair::from_str("ETH-USDT").unwrap().context();
+        let tagged = untagged.with_pair(ctx.symbol.clone());
+        assert_eq!(tagged.pair(), "ETH-USDT");
+        assert_eq!(ctx.base_decimals, 8);
     }
 }
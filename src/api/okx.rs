@@ -0,0 +1,545 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::instrument;
+
+use crate::api::{
+    ClientError, Exchange, ExchangePrice, HEARTBEAT_INTERVAL, MessageStamp, PairContext,
+    ReconnectConfig, SessionEnd, Side, SideModes, SubscriptionRejected, TradingPair, UpdateSender,
+    reconnect_loop, session_writer,
+};
+use crate::health::FEEDS;
+use crate::metrics::METRICS;
+use crate::util::{
+    ParseError, current_timestamp_ms, parse_price_scaled, parse_quantity_smallest_unit,
+};
+
+const OKX_WS_URL: &str = "wss://ws.okx.com:8443/ws/v5/public";
+
+/// Application-level ping; OKX closes connections idle for 30s and answers with `pong`.
+const OKX_PING: &str = "ping";
+
+/// OKX order book channels, from shallow/fast to full depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OkxDepthChannel {
+    /// Best bid and ask only, pushed tick-by-tick as a one-level snapshot.
+    BboTbt,
+    /// 400 levels: a snapshot followed by incremental updates every 100ms.
+    Books,
+    /// Top 5 levels, full snapshot every 100ms.
+    Books5,
+    /// 50 levels tick-by-tick (requires a VIP tier on OKX).
+    Books50L2Tbt,
+    /// 400 levels tick-by-tick (requires a VIP tier on OKX).
+    BooksL2Tbt,
+}
+
+impl OkxDepthChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OkxDepthChannel::BboTbt => "bbo-tbt",
+            OkxDepthChannel::Books => "books",
+            OkxDepthChannel::Books5 => "books5",
+            OkxDepthChannel::Books50L2Tbt => "books50-l2-tbt",
+            OkxDepthChannel::BooksL2Tbt => "books-l2-tbt",
+        }
+    }
+}
+
+impl std::str::FromStr for OkxDepthChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bbo-tbt" => Ok(OkxDepthChannel::BboTbt),
+            "books" => Ok(OkxDepthChannel::Books),
+            "books5" => Ok(OkxDepthChannel::Books5),
+            "books50-l2-tbt" => Ok(OkxDepthChannel::Books50L2Tbt),
+            "books-l2-tbt" => Ok(OkxDepthChannel::BooksL2Tbt),
+            other => Err(format!("unknown OKX book channel '{other}'")),
+        }
+    }
+}
+
+pub struct OkxClient {
+    tx: UpdateSender,
+    /// WebSocket endpoint; `OKX_WS_URL` overrides it in `main`, e.g. for a sandbox or a mock server.
+    pub ws_url: String,
+    /// Which book channel to subscribe to.
+    pub depth_channel: OkxDepthChannel,
+    /// Backoff used to reconnect (and re-subscribe) after the stream drops.
+    pub reconnect: ReconnectConfig,
+    /// How often to send the heartbeat on an open connection.
+    pub heartbeat_interval: Duration,
+}
+
+impl OkxClient {
+    pub fn new(tx: impl Into<UpdateSender>) -> Self {
+        OkxClient {
+            tx: tx.into(),
+            ws_url: OKX_WS_URL.to_string(),
+            depth_channel: OkxDepthChannel::Books,
+            reconnect: ReconnectConfig::default(),
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+        }
+    }
+
+    /// Listen to a specific trading pair's book channel on OKX, reconnecting on drops.
+    pub async fn listen_pair(&self, pair: TradingPair) -> Result<(), ClientError> {
+        reconnect_loop(Exchange::Okx, &self.reconnect, || self.run_session(&pair)).await
+    }
+
+    /// One connection's lifetime: connect, subscribe, read until close/error.
+    /// Returns whether any data message was handled.
+    async fn run_session(&self, pair: &TradingPair) -> SessionEnd {
+        let mut received_any = false;
+        let mut error = None;
+        let ctx = pair.context();
+
+        match connect_async(self.ws_url.as_str()).await {
+            Ok((mut ws_stream, _)) => {
+                let subscribe_msg = serde_json::json!({
+                    "op": "subscribe",
+                    "args": [{
+                        "channel": self.depth_channel.as_str(),
+                        "instId": pair.okx_inst_id()
+                    }]
+                });
+
+                if let Err(e) = ws_stream
+                    .send(Message::Text(subscribe_msg.to_string()))
+                    .await
+                {
+                    tracing::warn!(exchange = "okx", error = %e, "subscribe failed");
+                    return SessionEnd {
+                        received_data: false,
+                        error: Some(ClientError::subscribe_send(Exchange::Okx, e)),
+                    };
+                }
+
+                // A writer task owns the sink: Pongs are handed to it, heartbeats run on its timer.
+                let (write, mut read) = ws_stream.split();
+                let (outgoing, outgoing_rx) = mpsc::channel(8);
+                let writer = tokio::spawn(session_writer(
+                    write,
+                    outgoing_rx,
+                    Some((OKX_PING, self.heartbeat_interval)),
+                ));
+
+                while let Some(msg) = read.next().await {
+                    match msg {
+                        // Reply to our heartbeat.
+                        Ok(Message::Text(text)) if text == "pong" => {}
+                        Ok(Message::Text(text)) => {
+                            let received_at = current_timestamp_ms();
+                            FEEDS.record_message(Exchange::Okx, received_at);
+                            let result = self.handle_message(&text, received_at, &ctx).await;
+                            METRICS.record_message(Exchange::Okx.as_str(), result.is_ok());
+                            received_any |= result
+                                .as_ref()
+                                .err()
+                                .is_none_or(ClientError::forwarded_levels);
+                            match result {
+                                Ok(()) => {}
+                                Err(e) if e.stops_client() => {
+                                    tracing::info!(exchange = "okx", error = %e, "stopping client");
+                                    error = Some(e);
+                                    break;
+                                }
+                                Err(e) => {
+                                    METRICS.record_client_error(Exchange::Okx.as_str(), &e);
+                                    tracing::debug!(exchange = "okx", error = %e, "could not handle message");
+                                }
+                            }
+                        }
+                        // Answer pings from the read loop; see `session_writer`.
+                        Ok(Message::Ping(data)) => {
+                            let pong = outgoing.send(Message::Pong(data)).await;
+                            if pong.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(Message::Close(frame)) => {
+                            tracing::info!(
+                                exchange = "okx",
+                                ?frame,
+                                "connection closed by exchange"
+                            );
+                            error = Some(ClientError::Stream {
+                                exchange: Exchange::Okx,
+                                reason: "closed by the exchange".to_string(),
+                            });
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::warn!(exchange = "okx", error = %e, "websocket read failed");
+                            error = Some(ClientError::Stream {
+                                exchange: Exchange::Okx,
+                                reason: e.to_string(),
+                            });
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                writer.abort();
+            }
+            Err(e) => {
+                tracing::warn!(exchange = "okx", error = %e, "websocket connect failed");
+                error = Some(ClientError::Connect {
+                    exchange: Exchange::Okx,
+                    source: Box::new(e),
+                });
+            }
+        }
+
+        SessionEnd {
+            received_data: received_any,
+            error,
+        }
+    }
+
+    /// Parse one book message and send its price levels to the aggregator.
+    async fn handle_message(
+        &self,
+        text: &str,
+        received_at: u64,
+        pair: &PairContext,
+    ) -> Result<(), ClientError> {
+        let mut levels = Vec::new();
+        let parsed = parse_okx(text, received_at, pair, &mut levels);
+        for level in levels {
+            self.tx.send(level).await?;
+        }
+        parsed
+    }
+}
+
+/// Parse one book message into `out`.
+#[instrument(skip(text, out), fields(exchange = "okx", levels = tracing::field::Empty))]
+pub fn parse_okx(
+    text: &str,
+    received_at: u64,
+    pair: &PairContext,
+    out: &mut Vec<ExchangePrice>,
+) -> Result<(), ClientError> {
+    tracing::trace!(text, "raw message");
+    if text.len() > 100_000 {
+        return Err(ClientError::protocol(Exchange::Okx, "message too large"));
+    }
+
+    let v = {
+        let _span = tracing::info_span!("parse_json").entered();
+        serde_json::from_str::<Value>(text).map_err(|e| ClientError::protocol(Exchange::Okx, e))?
+    };
+
+    // e.g. {"event":"error","code":"60018","msg":"Wrong URL or channel:..."}
+    if v.get("event").and_then(|e| e.as_str()) == Some("error") {
+        let code = v.get("code").and_then(|c| c.as_str()).unwrap_or_default();
+        let msg = v
+            .get("msg")
+            .and_then(|m| m.as_str())
+            .unwrap_or("unknown error");
+        return Err(
+            SubscriptionRejected::new(Exchange::Okx, format!("{msg} (code {code})")).into(),
+        );
+    }
+
+    // Subscription acks carry "event"; book pushes carry "data".
+    let data = match v.get("data").and_then(|d| d.as_array()) {
+        Some(d) => d,
+        None => return Ok(()),
+    };
+
+    // "snapshot" is the full book (books5 pushes have no action and are always one) and
+    // replaces OKX's side; "update" carries changed levels with absolute sizes (0 = removed).
+    let action = v
+        .get("action")
+        .and_then(|a| a.as_str())
+        .unwrap_or("snapshot");
+    tracing::trace!(action, "okx book message");
+    let snapshot = action == "snapshot";
+    let levels: usize = data
+        .iter()
+        .flat_map(|book| ["bids", "asks"].map(|key| book.get(key)))
+        .filter_map(|levels| levels?.as_array().map(Vec::len))
+        .sum();
+    tracing::Span::current().record("levels", levels);
+    let mut level_error = None;
+
+    for book in data {
+        let exchange_timestamp = book
+            .get("ts")
+            .and_then(|t| t.as_str())
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(0);
+
+        // Levels: [["price", "size", "deprecated", "order count"], ...]
+        for (key, side) in [("bids", Side::Buy), ("asks", Side::Sell)] {
+            if let Some(levels) = book.get(key).and_then(|l| l.as_array()) {
+                let mut modes = SideModes::new(snapshot);
+                let stamp = MessageStamp {
+                    pair,
+                    exchange_timestamp,
+                    received_at,
+                };
+                push_levels(levels, side, &mut modes, stamp, out, &mut level_error);
+                if let Some(clear) = modes.clear_if_empty(Exchange::Okx, side) {
+                    out.push(
+                        clear
+                            .with_timestamps(exchange_timestamp, received_at)
+                            .with_pair(pair.symbol.clone()),
+                    );
+                }
+            }
+        }
+    }
+
+    match level_error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// Push `[["price", "size", ...], ...]` levels for one side.
+/// A zero size is forwarded so the book drops the level.
+fn push_levels(
+    levels: &[Value],
+    side: Side,
+    modes: &mut SideModes,
+    stamp: MessageStamp<'_>,
+    out: &mut Vec<ExchangePrice>,
+    level_error: &mut Option<ParseError>,
+) {
+    for level in levels {
+        if let Some(arr) = level.as_array()
+            && arr.len() >= 2
+            && let (Some(price_str), Some(size_str)) = (arr[0].as_str(), arr[1].as_str())
+        {
+            let (price_res, quantity_res) = {
+                let _span = tracing::info_span!("process_levels", ?side).entered();
+                (
+                    parse_price_scaled(price_str, stamp.pair.price_decimals),
+                    parse_quantity_smallest_unit(size_str, stamp.pair.base_decimals),
+                )
+            };
+
+            match (price_res, quantity_res) {
+                (Ok(price), Ok(quantity)) => {
+                    METRICS.record_level_parsed(Exchange::Okx.as_str());
+                    out.push(
+                        stamp.apply(
+                            ExchangePrice::new(Exchange::Okx, price, quantity, side)
+                                .with_mode(modes.next_mode()),
+                        ),
+                    );
+                }
+                (Err(error), _) | (_, Err(error)) => {
+                    METRICS.record_parse_error(Exchange::Okx.as_str(), error);
+                    level_error.get_or_insert(error);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{SubscribeError, UpdateMode, golden};
+    use crate::orderbook::OrderBook;
+
+    async fn parse(text: &str) -> Vec<ExchangePrice> {
+        let (tx, mut rx) = mpsc::channel(64);
+        let client = OkxClient::new(tx);
+        let pair = TradingPair::default_pair()
+            .with_price_decimals(Some(2))
+            .context();
+        client.handle_message(text, 42, &pair).await.unwrap();
+        drop(client);
+
+        let mut out = Vec::new();
+        while let Some(price) = rx.recv().await {
+            out.push(price);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn reports_subscription_errors() {
+        let (tx, _rx) = mpsc::channel(8);
+        let client = OkxClient::new(tx);
+        let pair = TradingPair::default_pair().context();
+        let err = client
+            .handle_message(r#"{"event":"error","code":"60018","msg":"Wrong URL or channel:books,instId:BTC-USDX doesn't exist."}"#, 42, &pair)
+            .await
+            .unwrap_err();
+        let ClientError::Subscribe(SubscribeError::Rejected(rejected)) = err else {
+            panic!("expected a rejected subscription, got {err}");
+        };
+        assert_eq!(
+            rejected,
+            SubscriptionRejected::new(
+                Exchange::Okx,
+                "Wrong URL or channel:books,instId:BTC-USDX doesn't exist. (code 60018)"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_snapshot_levels() {
+        let text = r#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"snapshot","data":[{"asks":[["8476.98","0.415","0","13"]],"bids":[["8476.97","0.256","0","12"]],"ts":"1597026383085","checksum":-855196043}]}"#;
+        let prices = parse(text).await;
+        assert_eq!(prices.len(), 2);
+        assert!(matches!(
+            prices[0],
+            ExchangePrice::Okx {
+                price: 847697,
+                quantity: 25_600_000,
+                exchange_timestamp: 1_597_026_383_085,
+                received_at: 42,
+                side: Side::Buy,
+                ..
+            }
+        ));
+        assert!(matches!(
+            prices[1],
+            ExchangePrice::Okx {
+                price: 847698,
+                quantity: 41_500_000,
+                side: Side::Sell,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn forwards_zero_size_updates() {
+        let text = r#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[["8476.98","0","0","0"]],"bids":[],"ts":"1597026383086","checksum":123}]}"#;
+        let prices = parse(text).await;
+        assert_eq!(prices.len(), 1);
+        assert!(matches!(
+            prices[0],
+            ExchangePrice::Okx {
+                quantity: 0,
+                side: Side::Sell,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn depth_channel_round_trips_through_str() {
+        for channel in [
+            OkxDepthChannel::Books,
+            OkxDepthChannel::Books5,
+            OkxDepthChannel::Books50L2Tbt,
+            OkxDepthChannel::BooksL2Tbt,
+        ] {
+            assert_eq!(channel.as_str().parse::<OkxDepthChannel>(), Ok(channel));
+        }
+        assert!("books1000".parse::<OkxDepthChannel>().is_err());
+    }
+
+    #[tokio::test]
+    async fn ignores_subscription_events() {
+        let text = r#"{"event":"subscribe","arg":{"channel":"books","instId":"BTC-USDT"},"connId":"a4d3ae55"}"#;
+        assert!(parse(text).await.is_empty());
+    }
+
+    fn golden(text: &str) -> Vec<ExchangePrice> {
+        let pair = golden::context("BTC-USDT", 8, 2);
+        golden::parse(|out| parse_okx(text, 42, &pair, out))
+    }
+
+    /// `value` in units of `10^-decimals`, written the way OKX writes it: no trailing zeros.
+    fn decimal(value: u64, decimals: u32) -> String {
+        let scale = 10u64.pow(decimals);
+        let (whole, fraction) = (value / scale, value % scale);
+        if fraction == 0 {
+            return whole.to_string();
+        }
+        let fraction = format!("{fraction:0width$}", width = decimals as usize);
+        format!("{whole}.{}", fraction.trim_end_matches('0'))
+    }
+
+    /// OKX's book checksum: signed CRC-32 of the top 25 bids and asks interleaved as
+    /// `bid:size:ask:size:...`, sides running out independently.
+    fn checksum(book: &OrderBook) -> i32 {
+        let bids = golden::top(book, Side::Buy, 25);
+        let asks = golden::top(book, Side::Sell, 25);
+        let mut fields = Vec::new();
+        for i in 0..bids.len().max(asks.len()) {
+            for (price, size) in [bids.get(i), asks.get(i)].into_iter().flatten() {
+                fields.push(decimal(*price, 2));
+                fields.push(decimal(*size, 8));
+            }
+        }
+        golden::crc32(fields.join(":").as_bytes()) as i32
+    }
+
+    /// The `checksum` a frame carries.
+    fn frame_checksum(text: &str) -> i32 {
+        let frame: Value = serde_json::from_str(text).unwrap();
+        frame["data"][0]["checksum"].as_i64().unwrap() as i32
+    }
+
+    #[test]
+    fn golden_snapshot_replaces_each_side() {
+        let text = include_str!("testdata/okx/snapshot.json");
+        let snapshot = golden(text);
+        assert_eq!(
+            golden::levels(&snapshot),
+            [
+                ((4_325_000, 75_000_000, Side::Buy), UpdateMode::Replace),
+                ((4_324_950, 200_000_000, Side::Buy), UpdateMode::Delta),
+                ((4_325_010, 50_000_000, Side::Sell), UpdateMode::Replace),
+                ((4_325_100, 120_000_000, Side::Sell), UpdateMode::Delta),
+            ]
+        );
+        // "ts" is a string of epoch milliseconds.
+        assert_eq!(snapshot[0].exchange_timestamp(), 1_700_000_000_123);
+        assert_eq!(
+            checksum(&golden::book_after(&[&snapshot])),
+            frame_checksum(text)
+        );
+    }
+
+    #[test]
+    fn golden_update_sets_changed_levels() {
+        let snapshot = golden(include_str!("testdata/okx/snapshot.json"));
+        let text = include_str!("testdata/okx/update.json");
+        let update = golden(text);
+        assert_eq!(
+            golden::levels(&update),
+            [
+                ((4_325_000, 100_000_000, Side::Buy), UpdateMode::Delta),
+                ((4_325_150, 80_000_000, Side::Sell), UpdateMode::Delta),
+            ]
+        );
+        let book = golden::book_after(&[&snapshot, &update]);
+        assert_eq!(checksum(&book), frame_checksum(text));
+    }
+
+    #[test]
+    fn golden_zero_size_removes_the_level() {
+        // A removal is a size of "0" with an order count of "0".
+        let snapshot = golden(include_str!("testdata/okx/snapshot.json"));
+        let update = golden(include_str!("testdata/okx/update.json"));
+        let text = include_str!("testdata/okx/removal.json");
+        let removal = golden(text);
+        assert_eq!(
+            golden::levels(&removal),
+            [((4_325_010, 0, Side::Sell), UpdateMode::Delta)]
+        );
+        let book = golden::book_after(&[&snapshot, &update, &removal]);
+        assert_eq!(
+            golden::top(&book, Side::Sell, 1),
+            [(4_325_100, 120_000_000)]
+        );
+        assert_eq!(checksum(&book), frame_checksum(text));
+    }
+}
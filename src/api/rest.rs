@@ -0,0 +1,110 @@
+//! Minimal HTTP/1.1 GET for venue REST endpoints (e.g. Binance depth snapshots).
+
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::{Request, Uri, header};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_native_tls::{TlsConnector, native_tls};
+
+pub type RestError = Box<dyn std::error::Error + Send + Sync>;
+
+/// GET `url` (`http://` or `https://`) and return the body of a 2xx response as text.
+pub async fn get(url: &str) -> Result<String, RestError> {
+    let uri: Uri = url.parse()?;
+    let host = uri.host().ok_or("URL has no host")?.to_string();
+    let https = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        other => return Err(format!("unsupported URL scheme {other:?}").into()),
+    };
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+    let authority = uri.authority().ok_or("URL has no host")?.to_string();
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    let request = Request::get(path)
+        .header(header::HOST, authority)
+        .header(header::ACCEPT, "application/json")
+        .body(Empty::<Bytes>::new())?;
+
+    let tcp = TcpStream::connect((host.as_str(), port)).await?;
+    if https {
+        let tls = TlsConnector::from(native_tls::TlsConnector::new()?);
+        send(tls.connect(&host, tcp).await?, request).await
+    } else {
+        send(tcp, request).await
+    }
+}
+
+async fn send<S>(io: S, request: Request<Empty<Bytes>>) -> Result<String, RestError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+    // Drives the connection until the response body has been read.
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::debug!(error = %e, "REST connection ended with an error");
+        }
+    });
+
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    let body = String::from_utf8(body.to_vec())?;
+    if !status.is_success() {
+        return Err(format!("HTTP {status}: {body}").into());
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answer one request with `response` and return the request's head.
+    async fn serve_once(response: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = tcp.read(&mut request).await.unwrap();
+            tcp.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn returns_the_body_of_a_successful_response() {
+        let (url, server) = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 18\r\n\r\n{\"lastUpdateId\":1}",
+        )
+        .await;
+        let body = get(&format!("{url}/api/v3/depth?symbol=BTCUSDT"))
+            .await
+            .unwrap();
+        assert_eq!(body, r#"{"lastUpdateId":1}"#);
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("GET /api/v3/depth?symbol=BTCUSDT HTTP/1.1\r\n"),
+            "{request}"
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_error_statuses_with_the_body() {
+        let (url, _server) = serve_once(
+            "HTTP/1.1 429 Too Many Requests\r\nContent-Length: 16\r\n\r\n{\"code\":-1003}  ",
+        )
+        .await;
+        let err = get(&url).await.unwrap_err();
+        assert!(
+            err.to_string().starts_with("HTTP 429 Too Many Requests"),
+            "{err}"
+        );
+    }
+}
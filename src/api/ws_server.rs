@@ -0,0 +1,187 @@
+//! WebSocket output: clients connect to `WS_SERVER_ADDR` and receive one `BookSnapshot` JSON
+//! text frame per configured pair every interval.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+use crate::orderbook::OrderBook;
+
+/// Frames buffered per client; a client further behind than this skips the oldest frames.
+const CLIENT_BUFFER: usize = 16;
+
+/// Pause after a failed `accept()` so a persistent error (e.g. out of file descriptors)
+/// doesn't spin the loop.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Books and cadence for the WebSocket server.
+#[derive(Clone)]
+pub struct WsServerState {
+    pub books: Vec<Arc<OrderBook>>,
+    /// Levels per side in each snapshot.
+    pub depth: usize,
+    /// Time between rounds of snapshots.
+    pub interval: Duration,
+}
+
+/// Serve snapshots on `addr` until the task is dropped. Only binding the listener can fail.
+pub async fn run_ws_server(
+    addr: SocketAddr,
+    state: WsServerState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    serve(listener, state).await;
+    Ok(())
+}
+
+/// Accept clients on an already-bound listener. One task serializes each snapshot once and
+/// broadcasts it; a full client buffer drops frames for that client instead of blocking.
+///
+/// The broadcaster lives in a `JoinSet` owned by this future, so dropping the server aborts
+/// it, and dropping the sender closes every client's stream. A failed `accept()` is logged
+/// and retried rather than ending the server.
+async fn serve(listener: TcpListener, state: WsServerState) {
+    let (frames, _) = broadcast::channel::<Arc<str>>(CLIENT_BUFFER);
+
+    let mut broadcaster = JoinSet::new();
+    broadcaster.spawn(broadcast_snapshots(state, frames.clone()));
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(serve_client(stream, peer, frames.subscribe()));
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "websocket accept failed");
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Snapshot every book each tick and publish the JSON to all connected clients.
+async fn broadcast_snapshots(state: WsServerState, frames: broadcast::Sender<Arc<str>>) {
+    let mut ticker = tokio::time::interval(state.interval.max(Duration::from_millis(1)));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        // Nobody listening: skip the work.
+        if frames.receiver_count() == 0 {
+            continue;
+        }
+        for book in &state.books {
+            let json = serde_json::to_string(&book.snapshot(state.depth)).unwrap_or_default();
+            let _ = frames.send(json.into());
+        }
+    }
+}
+
+/// Forward broadcast frames to one client until it disconnects.
+async fn serve_client(
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut frames: broadcast::Receiver<Arc<str>>,
+) {
+    let ws_stream = match accept_async(stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            tracing::debug!(%peer, error = %e, "websocket handshake failed");
+            return;
+        }
+    };
+    let (mut write, mut read) = ws_stream.split();
+
+    loop {
+        tokio::select! {
+            frame = frames.recv() => match frame {
+                Ok(json) => {
+                    if write.send(Message::Text(json.to_string())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::debug!(%peer, skipped, "slow websocket client, dropped frames");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            // Incoming frames are only read to notice the client going away.
+            msg = read.next() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Exchange, ExchangePrice, Side};
+    use tokio_tungstenite::client_async;
+
+    #[tokio::test]
+    async fn streams_snapshot_frames() {
+        let book = Arc::new(OrderBook::new("BTC-USDT".to_string()));
+        book.update_price_level(ExchangePrice::new(Exchange::Binance, 10_000, 1, Side::Buy));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = WsServerState {
+            books: vec![book],
+            depth: 5,
+            interval: Duration::from_millis(20),
+        };
+        tokio::spawn(serve(listener, state));
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (mut ws, _) = client_async(format!("ws://{addr}/"), tcp).await.unwrap();
+        let frame = tokio::time::timeout(Duration::from_secs(2), ws.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let Message::Text(text) = frame else {
+            panic!("expected a text frame, got {frame:?}");
+        };
+        let snapshot: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(snapshot["symbol"], "BTC-USDT");
+        assert_eq!(snapshot["bids"][0]["exchange"], "binance");
+    }
+
+    #[tokio::test]
+    async fn aborting_the_server_stops_the_broadcaster() {
+        let book = Arc::new(OrderBook::new("BTC-USDT".to_string()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = WsServerState {
+            books: vec![book],
+            depth: 5,
+            interval: Duration::from_millis(20),
+        };
+        let server = tokio::spawn(serve(listener, state));
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (mut ws, _) = client_async(format!("ws://{addr}/"), tcp).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(2), ws.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        server.abort();
+        // With the broadcaster gone the client's stream ends instead of ticking forever.
+        let ended = tokio::time::timeout(Duration::from_secs(2), async {
+            while let Some(Ok(_)) = ws.next().await {}
+        })
+        .await;
+        assert!(
+            ended.is_ok(),
+            "client still receiving after the server was aborted"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Command-line options. Each flag falls back to its environment variable, then a default.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::api::{Exchange, OverflowPolicy, TradingPair};
+use crate::orderbook::{DEFAULT_DEPTH, ExchangeFormat, OutputFormat};
+use crate::replay::ReplaySpeed;
+
+/// Aggregate exchange order books into one combined view.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Trading pair(s) to aggregate, comma-separated (e.g. BTC-USDT,ETH-USDT).
+    #[arg(long, env = "TRADING_PAIR", default_value = "BTC-USDT", value_parser = parse_pairs)]
+    pub pair: PairList,
+
+    /// Exchanges to connect to, comma-separated (e.g. binance,okx).
+    #[arg(
+        long,
+        env = "EXCHANGES",
+        value_delimiter = ',',
+        default_value = "binance,bitstamp,kraken,okx,coinbase,bybit"
+    )]
+    pub exchanges: Vec<Exchange>,
+
+    /// Decimal places kept for prices; defaults to the quote asset's precision
+    /// (e.g. 6 for USDT, 2 for USD). Raise it for low-priced pairs.
+    #[arg(long, env = "PRICE_DECIMALS", value_parser = clap::value_parser!(u32).range(0..=12))]
+    pub price_decimals: Option<u32>,
+
+    /// Updates buffered between the exchange clients and the aggregator. Larger absorbs
+    /// bursts without the clients waiting, at the cost of memory and staler queued updates.
+    #[arg(
+        long,
+        env = "CHANNEL_CAPACITY",
+        default_value_t = 10_000,
+        value_parser = clap::value_parser!(u64).range(1..=10_000_000)
+    )]
+    pub channel_capacity: u64,
+
+    /// When the update channel is full: `block` waits for room (no loss, the feed lags),
+    /// `drop` discards the update unless it opens a snapshot. Either way it is counted in
+    /// `agg_channel_full_total`.
+    #[arg(long, env = "CHANNEL_FULL_POLICY", default_value = "block")]
+    pub on_channel_full: OverflowPolicy,
+
+    /// Levels per side in snapshots and gRPC summaries.
+    #[arg(long, env = "AGG_DEPTH", default_value_t = DEFAULT_DEPTH)]
+    pub depth: usize,
+
+    /// How snapshots write prices and amounts: `float` (whole units as JSON numbers),
+    /// `integer` (stored units plus a `scale` object) or `decimal` (exact strings).
+    #[arg(long, env = "OUTPUT_FORMAT", default_value = "float")]
+    pub output_format: OutputFormat,
+
+    /// How snapshots name exchanges: `name` (`"okx"`), `enum` (`"Okx"`) or the stable
+    /// numeric `id` (`4`).
+    #[arg(long, env = "EXCHANGE_FORMAT", default_value = "name")]
+    pub exchange_format: ExchangeFormat,
+
+    /// Track only each venue's best bid and ask, read from its lightest channel (a ticker
+    /// where the venue has one). Depth queries then return one level per venue.
+    #[arg(long, env = "TOP_OF_BOOK")]
+    pub top_of_book: bool,
+
+    /// Stop after this many seconds instead of waiting for Ctrl+C.
+    #[arg(long, env = "RUN_DURATION_SECS", value_name = "SECS")]
+    pub duration: Option<u64>,
+
+    /// Run until Ctrl+C, ignoring --duration.
+    #[arg(long, env = "RUN_FOREVER")]
+    pub run_forever: bool,
+
+    /// Address for the gRPC server (overrides GRPC_ADDR).
+    #[cfg(feature = "grpc")]
+    #[arg(long, env = "GRPC_ADDR")]
+    pub grpc_addr: Option<SocketAddr>,
+
+    /// Serve `GET /book` snapshots on this address (off when unset).
+    #[cfg(feature = "http")]
+    #[arg(long, env = "HTTP_ADDR")]
+    pub http_addr: Option<SocketAddr>,
+
+    /// Serve snapshot JSON frames over WebSocket on this address (off when unset).
+    #[cfg(feature = "ws-server")]
+    #[arg(long, env = "WS_SERVER_ADDR")]
+    pub ws_server_addr: Option<SocketAddr>,
+
+    /// Append periodic snapshots of every book to this file as NDJSON (off when unset).
+    #[arg(long, env = "RECORD_PATH")]
+    pub record_path: Option<PathBuf>,
+
+    /// Append every update received to this file as NDJSON, for `--replay` (off when unset).
+    #[arg(long, env = "RECORD_UPDATES_PATH")]
+    pub record_updates_path: Option<PathBuf>,
+
+    /// Feed the updates recorded in this file through the books instead of connecting to
+    /// the exchanges; stops once the file is exhausted.
+    #[arg(long, env = "REPLAY_PATH", value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+
+    /// Replay pacing: `max` (as fast as possible), `realtime` (the recorded gaps), or a
+    /// speed-up factor such as `10x`.
+    #[arg(long, env = "REPLAY_SPEED", default_value = "max")]
+    pub replay_speed: ReplaySpeed,
+
+    /// Address for the Prometheus metrics endpoint.
+    #[arg(long, env = "METRICS_ADDR", default_value = "127.0.0.1:9100")]
+    pub metrics_addr: SocketAddr,
+}
+
+impl Cli {
+    /// How long to run, or `None` to run until Ctrl+C. A zero duration also means forever.
+    pub fn run_duration(&self) -> Option<u64> {
+        if self.run_forever {
+            return None;
+        }
+        self.duration.filter(|&secs| secs > 0)
+    }
+}
+
+/// Non-empty list of configured pairs.
+#[derive(Debug, Clone)]
+pub struct PairList(pub Vec<TradingPair>);
+
+/// Parse a comma-separated pair list, rejecting anything that is not `BASE-QUOTE`
+/// (also accepting '/' or '_' as the separator).
+fn parse_pairs(s: &str) -> Result<PairList, String> {
+    let pairs = TradingPair::parse_list(s)?;
+    if pairs.is_empty() {
+        return Err("expected at least one trading pair, e.g. BTC-USDT".to_string());
+    }
+    Ok(PairList(pairs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pairs_and_duration() {
+        let cli = Cli::try_parse_from([
+            "agg",
+            "--pair",
+            "BTC-USDT,eth/usdt",
+            "--depth",
+            "5",
+            "--duration",
+            "30",
+        ])
+        .unwrap();
+        let pairs: Vec<&str> = cli.pair.0.iter().map(|p| p.as_str()).collect();
+        assert_eq!(pairs, vec!["BTC-USDT", "eth/usdt"]);
+        assert_eq!(cli.depth, 5);
+        assert_eq!(cli.run_duration(), Some(30));
+        assert_eq!(cli.exchanges, Exchange::ALL);
+        assert_eq!(cli.channel_capacity, 10_000);
+    }
+
+    #[test]
+    fn selects_exchanges() {
+        // Kept as listed; `api::subscriptions` drops the repeat at spawn time.
+        let cli = Cli::try_parse_from(["agg", "--exchanges", "okx,Binance,okx"]).unwrap();
+        assert_eq!(
+            cli.exchanges,
+            vec![Exchange::Okx, Exchange::Binance, Exchange::Okx]
+        );
+
+        let err = Cli::try_parse_from(["agg", "--exchanges", "binance,ftx"]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("valid: binance, bitstamp, kraken, okx, coinbase, bybit")
+        );
+    }
+
+    #[test]
+    fn run_forever_overrides_duration() {
+        let cli = Cli::try_parse_from(["agg", "--duration", "30", "--run-forever"]).unwrap();
+        assert_eq!(cli.run_duration(), None);
+    }
+
+    #[test]
+    fn channel_capacity_must_be_positive() {
+        let cli = Cli::try_parse_from(["agg", "--channel-capacity", "50000"]).unwrap();
+        assert_eq!(cli.channel_capacity, 50_000);
+        assert!(Cli::try_parse_from(["agg", "--channel-capacity", "0"]).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_pairs() {
+        assert!(Cli::try_parse_from(["agg", "--pair", "BTCUSDT"]).is_err());
+        let err = Cli::try_parse_from(["agg", "--pair", "BTC-USDT,btc/usd/t"]).unwrap_err();
+        assert!(err.to_string().contains("invalid trading pair 'btc/usd/t'"));
+        assert!(Cli::try_parse_from(["agg", "--pair", " , "]).is_err());
+    }
+}
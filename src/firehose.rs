@@ -0,0 +1,95 @@
+//! The raw update firehose: every `ExchangePrice` the aggregator takes off its channel,
+//! re-published on a broadcast channel for consumers that want the ticks themselves rather
+//! than book snapshots (e.g. a tick database). A consumer loop calls `forward` on each batch
+//! before `orderbook::apply_updates`; `main` does so when `FIREHOSE_CAPACITY` is set.
+//!
+//! Forwarding never waits. A subscriber more than the channel's capacity behind gets
+//! `RecvError::Lagged` and skips the oldest updates, while ingest carries on.
+
+use tokio::sync::broadcast;
+
+use crate::api::ExchangePrice;
+
+/// Updates buffered per subscriber before the oldest are skipped.
+pub const FIREHOSE_CAPACITY: usize = 4096;
+
+/// Tee for the raw update stream; clones share one channel.
+#[derive(Debug, Clone)]
+pub struct Firehose {
+    tx: broadcast::Sender<ExchangePrice>,
+}
+
+impl Default for Firehose {
+    fn default() -> Self {
+        Firehose::new(FIREHOSE_CAPACITY)
+    }
+}
+
+impl Firehose {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        Firehose { tx }
+    }
+
+    /// A receiver for every update forwarded from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ExchangePrice> {
+        self.tx.subscribe()
+    }
+
+    /// Publish `updates` to every subscriber; with none, nothing is cloned.
+    /// Returns how many updates were sent.
+    pub fn forward(&self, updates: &[ExchangePrice]) -> usize {
+        if self.tx.receiver_count() == 0 {
+            return 0;
+        }
+        for update in updates {
+            // Only fails once every receiver is gone.
+            if self.tx.send(update.clone()).is_err() {
+                return 0;
+            }
+        }
+        updates.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Exchange, Side};
+
+    fn update(price: u64) -> ExchangePrice {
+        ExchangePrice::new(Exchange::Kraken, price, 1, Side::Buy).with_pair("BTC-USDT".into())
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_forwarded_updates() {
+        let firehose = Firehose::default();
+        // Nobody listening yet: nothing is sent.
+        assert_eq!(firehose.forward(&[update(99)]), 0);
+
+        let mut first = firehose.subscribe();
+        let mut second = firehose.clone().subscribe();
+        assert_eq!(firehose.forward(&[update(100), update(101)]), 2);
+        for rx in [&mut first, &mut second] {
+            let received = [rx.recv().await.unwrap(), rx.recv().await.unwrap()];
+            assert_eq!(received.each_ref().map(|u| u.level().0), [100, 101]);
+            assert_eq!(received[0].pair(), "BTC-USDT");
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_lag_instead_of_blocking() {
+        let firehose = Firehose::new(2);
+        let mut rx = firehose.subscribe();
+        // More than the capacity, with nobody reading: forwarding still returns at once.
+        let updates: Vec<_> = (100..105).map(update).collect();
+        assert_eq!(firehose.forward(&updates), 5);
+
+        assert!(matches!(
+            rx.recv().await,
+            Err(broadcast::error::RecvError::Lagged(3))
+        ));
+        assert_eq!(rx.recv().await.unwrap().level().0, 103);
+        assert_eq!(rx.recv().await.unwrap().level().0, 104);
+    }
+}
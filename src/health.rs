@@ -0,0 +1,203 @@
+//! Per-exchange feed status for readiness probes: each client reports connection attempts,
+//! messages and disconnects, and `GET /health` (on the metrics server) answers 200 only
+//! while at least one feed is live.
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use dashmap::DashMap;
+use serde::Serialize;
+
+use crate::api::Exchange;
+use crate::util::current_timestamp_ms;
+
+/// Process-wide feed status, updated by the exchange clients.
+pub static FEEDS: LazyLock<FeedStatus> = LazyLock::new(FeedStatus::default);
+
+/// Where one exchange feed stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedState {
+    /// Opening the WebSocket (and subscribing); no message on this connection yet.
+    Connecting,
+    /// Messages are arriving.
+    Live,
+    /// Connected, but nothing arrived within the stale window.
+    Stale,
+    /// The connection dropped or the client stopped.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Feed {
+    /// `Connecting`, `Live` or `Disconnected`; `Stale` is derived when reporting.
+    state: FeedState,
+    /// When the last message arrived (epoch ms), on this or an earlier connection.
+    last_message_ms: Option<u64>,
+}
+
+/// One exchange's line in the health report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeedReport {
+    pub exchange: &'static str,
+    pub state: FeedState,
+    pub last_message_ms: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct FeedStatus {
+    feeds: DashMap<Exchange, Feed>,
+}
+
+impl FeedStatus {
+    fn set_state(&self, exchange: Exchange, state: FeedState) {
+        self.feeds
+            .entry(exchange)
+            .and_modify(|feed| feed.state = state)
+            .or_insert(Feed {
+                state,
+                last_message_ms: None,
+            });
+    }
+
+    /// A connection attempt to `exchange` is starting.
+    pub fn record_connecting(&self, exchange: Exchange) {
+        self.set_state(exchange, FeedState::Connecting);
+    }
+
+    /// `exchange`'s connection ended; it stays down until the next attempt.
+    pub fn record_disconnected(&self, exchange: Exchange) {
+        self.set_state(exchange, FeedState::Disconnected);
+    }
+
+    /// A message from `exchange` arrived at `at_ms` (epoch ms).
+    pub fn record_message(&self, exchange: Exchange, at_ms: u64) {
+        // Called per message: skip the shard write lock `entry` takes once the feed exists.
+        if let Some(mut feed) = self.feeds.get_mut(&exchange) {
+            feed.state = FeedState::Live;
+            feed.last_message_ms = Some(at_ms);
+            return;
+        }
+        self.feeds.insert(
+            exchange,
+            Feed {
+                state: FeedState::Live,
+                last_message_ms: Some(at_ms),
+            },
+        );
+    }
+
+    /// Every feed seen so far as of `now_ms`, sorted by exchange. A live feed whose last
+    /// message is older than `stale_after` is reported as `Stale`.
+    pub fn report(&self, now_ms: u64, stale_after: Duration) -> Vec<FeedReport> {
+        let stale_after_ms = stale_after.as_millis() as u64;
+        let mut reports: Vec<FeedReport> = self
+            .feeds
+            .iter()
+            .map(|entry| {
+                let feed = *entry.value();
+                let quiet = feed
+                    .last_message_ms
+                    .is_none_or(|at| now_ms.saturating_sub(at) > stale_after_ms);
+                let state = match feed.state {
+                    FeedState::Live if quiet => FeedState::Stale,
+                    state => state,
+                };
+                FeedReport {
+                    exchange: entry.key().as_str(),
+                    state,
+                    last_message_ms: feed.last_message_ms,
+                }
+            })
+            .collect();
+        reports.sort_unstable_by_key(|report| report.exchange);
+        reports
+    }
+}
+
+/// What the `/health` handler needs.
+#[derive(Debug, Clone, Copy)]
+pub struct HealthState {
+    /// How long a live feed may go without a message before it counts as stale.
+    pub stale_after: Duration,
+}
+
+/// The `/health` response for `reports`: 200 when at least one feed is live, else 503.
+pub fn health_response(reports: &[FeedReport]) -> Response {
+    let status = if reports.iter().any(|r| r.state == FeedState::Live) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::json!({ "feeds": reports }).to_string();
+    (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+pub async fn health_handler(State(state): State<HealthState>) -> Response {
+    health_response(&FEEDS.report(current_timestamp_ms(), state.stale_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STALE: Duration = Duration::from_secs(10);
+
+    fn states(status: &FeedStatus, now_ms: u64) -> Vec<(&'static str, FeedState)> {
+        status
+            .report(now_ms, STALE)
+            .into_iter()
+            .map(|r| (r.exchange, r.state))
+            .collect()
+    }
+
+    #[test]
+    fn feeds_go_live_on_messages_and_stale_when_quiet() {
+        let status = FeedStatus::default();
+        status.record_connecting(Exchange::Kraken);
+        status.record_connecting(Exchange::Binance);
+        status.record_message(Exchange::Binance, 1_000);
+        assert_eq!(
+            states(&status, 5_000),
+            [
+                ("binance", FeedState::Live),
+                ("kraken", FeedState::Connecting)
+            ]
+        );
+
+        // Nothing for longer than the window: stale, until the next message.
+        assert_eq!(states(&status, 11_001)[0], ("binance", FeedState::Stale));
+        status.record_message(Exchange::Binance, 11_001);
+        assert_eq!(states(&status, 11_001)[0], ("binance", FeedState::Live));
+
+        // A reconnect keeps the last message time but is not live until data arrives again.
+        status.record_disconnected(Exchange::Binance);
+        status.record_connecting(Exchange::Binance);
+        let report = status.report(12_000, STALE);
+        assert_eq!(report[0].state, FeedState::Connecting);
+        assert_eq!(report[0].last_message_ms, Some(11_001));
+    }
+
+    #[test]
+    fn healthy_only_while_a_feed_is_live() {
+        let status = FeedStatus::default();
+        assert_eq!(
+            health_response(&status.report(0, STALE)).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        status.record_message(Exchange::Okx, 1_000);
+        status.record_disconnected(Exchange::Coinbase);
+        assert_eq!(
+            health_response(&status.report(2_000, STALE)).status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            health_response(&status.report(20_000, STALE)).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
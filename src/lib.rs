@@ -0,0 +1,18 @@
+//! Order book aggregation across crypto exchanges, as a library.
+//!
+//! `api` holds the venue clients and their pure `parse_<venue>` functions, `orderbook` the
+//! combined `OrderBook` they feed, and `firehose` re-publishes the raw updates. The
+//! `websocket_agg_orders` binary wires these to the command line; embed them directly to run
+//! the aggregator inside another service.
+
+pub mod api;
+pub mod firehose;
+pub mod health;
+pub mod metrics;
+pub mod orderbook;
+pub mod recorder;
+pub mod replay;
+pub mod util;
+
+pub use api::{ExchangePrice, TradingPair};
+pub use orderbook::OrderBook;
@@ -1,65 +1,212 @@
-mod api;
-mod orderbook;
-mod util;
+mod cli;
 
-use orderbook::OrderBook;
-use std::env;
+use clap::Parser;
+use std::collections::{BTreeMap, HashMap};
+use std::process::ExitCode;
 use std::sync::Arc;
 use tokio::signal;
 use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant, sleep};
+use websocket_agg_orders::orderbook::OrderBook;
+use websocket_agg_orders::{api, firehose, health, metrics, orderbook, recorder, replay, util};
 
+/// Most updates taken off the channel and applied in one go.
+const UPDATE_BATCH: usize = 512;
+
+/// Upper bound on applying buffered updates at shutdown.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Failures return an `ExitCode` instead of calling `process::exit`, so the tracing guard
+// is dropped (and the flame graph flushed) on the way out.
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    // Flags fall back to env vars; invalid values (e.g. an unknown pair) exit non-zero here.
+    let cli = cli::Cli::parse();
     let _flame_guard = util::setup_config();
 
-    // Read trading pair from env, defaulting to a common pair when missing/invalid.
-    let pair = match env::var("TRADING_PAIR") {
-        Ok(s) => match api::TradingPair::from_str(&s) {
-            Some(p) => p,
-            None => {
-                eprintln!(
-                    "TRADING_PAIR is invalid or empty (got '{}'); defaulting to BTC-USDT.",
-                    s
-                );
-                api::TradingPair::default_pair()
+    // ASSET_ALIASES / ASSET_NAMES_<EXCHANGE> normalize asset codes (XBT = BTC), then
+    // SYMBOL_OVERRIDE_<EXCHANGE> renames a pair on venues that list it differently.
+    let asset_aliases = Arc::new(api::AssetAliases::from_env());
+    let symbol_overrides = api::SymbolOverrides::from_env();
+    let mut pairs: Vec<api::TradingPair> = cli
+        .pair
+        .0
+        .iter()
+        .map(|pair| {
+            pair.clone()
+                .with_price_decimals(cli.price_decimals)
+                .with_asset_aliases(asset_aliases.clone())
+                .with_symbol_overrides(&symbol_overrides)
+        })
+        .collect();
+    // Configured aliases can make two listed pairs the same one.
+    api::TradingPair::dedup(&mut pairs);
+
+    // One book per pair, keyed by the pair as configured.
+    let min_exchanges = util::env_or("MIN_EXCHANGES", 1);
+    // STALE_LEVEL_MS > 0 drops levels not updated for that long, for venues that go quiet.
+    let stale_after = Duration::from_millis(util::env_or("STALE_LEVEL_MS", 0u64));
+    // VALIDATE_BOOKS=1 checks each venue's own book after every batch of updates.
+    let validate_books = util::env_flag("VALIDATE_BOOKS", false);
+    // MIN_LEVEL_QUANTITY hides levels smaller than this many smallest units (dust) from reads.
+    let min_quantity = util::env_or("MIN_LEVEL_QUANTITY", 0u64);
+    // MAX_DEVIATION_BPS > 0 hides levels further than that from the mid (bad venue prices).
+    let max_deviation_bps = util::env_or("MAX_DEVIATION_BPS", 0u32);
+    let mut books: HashMap<String, Arc<OrderBook>> = HashMap::new();
+    for pair in &pairs {
+        let book = OrderBook::builder()
+            .symbol(pair.as_str())
+            .decimals(pair.base_decimals(), pair.price_decimals())
+            // Require quotes from this many venues on each side before reporting a spread.
+            .min_exchanges(min_exchanges)
+            .output_format(cli.output_format)
+            .exchange_format(cli.exchange_format)
+            .stale_after((!stale_after.is_zero()).then_some(stale_after))
+            .validate_updates(validate_books)
+            .min_quantity(min_quantity)
+            .max_deviation_bps((max_deviation_bps > 0).then_some(max_deviation_bps))
+            .top_of_book_only(cli.top_of_book)
+            .build();
+        books.insert(pair.as_str().to_string(), Arc::new(book));
+    }
+
+    // Levels per side in snapshots and gRPC summaries.
+    let depth = cli.depth;
+
+    // With ENABLE_GRPC=1, stream summaries of the first pair's in-memory order book over gRPC.
+    #[cfg(feature = "grpc")]
+    let mut grpc_handle = util::env_flag("ENABLE_GRPC", false).then(|| {
+        let grpc_ob = books[pairs[0].as_str()].clone();
+        let mut grpc_config = api::grpc::GrpcConfig::from_env();
+        if let Some(addr) = cli.grpc_addr {
+            grpc_config.addr = addr;
+        }
+        tokio::spawn(async move {
+            if let Err(e) = api::grpc::run_grpc_server(grpc_ob, depth, grpc_config).await {
+                tracing::error!(error = %e, "gRPC server error");
             }
-        },
-        Err(_) => {
-            eprintln!("TRADING_PAIR not set; defaulting to BTC-USDT.");
-            api::TradingPair::default_pair()
+        })
+    });
+    #[cfg(not(feature = "grpc"))]
+    let mut grpc_handle: Option<tokio::task::JoinHandle<()>> = None;
+
+    // Shared channel; each update carries the pair it belongs to.
+    let (tx, mut rx) = mpsc::channel::<api::ExchangePrice>(cli.channel_capacity as usize);
+
+    // EXCHANGES as listed; a venue or feed named twice is logged and subscribed once.
+    let subscriptions = api::subscriptions(&cli.exchanges, &pairs);
+    let channels = FeedChannels::from_env(cli.top_of_book);
+    let mut clients = JoinSet::new();
+    // --replay feeds a recorded update file (RECORD_UPDATES_PATH) through the same pipeline
+    // instead of connecting to the exchanges; the run ends once the file is exhausted.
+    let replay_handle = match &cli.replay {
+        Some(path) => {
+            let updates = match replay::read_updates(path) {
+                Ok(updates) => updates,
+                Err(e) => {
+                    tracing::error!(path = %path.display(), error = %e, "could not read replay file");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let tx = tx.clone();
+            let speed = cli.replay_speed;
+            Some(tokio::spawn(async move {
+                let sent = replay::replay_updates(updates, speed, &tx).await;
+                tracing::info!(sent, "replay finished");
+            }))
+        }
+        None => {
+            spawn_clients(
+                &mut clients,
+                &subscriptions,
+                api::UpdateSender::new(tx.clone(), cli.on_channel_full),
+                channels,
+            );
+            None
         }
     };
 
-    let orderbook = Arc::new(OrderBook::new(pair.as_str().to_string()));
+    let eviction_handle = (!stale_after.is_zero()).then(|| {
+        let books: Vec<Arc<OrderBook>> = books.values().cloned().collect();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(stale_after / 2);
+            loop {
+                ticker.tick().await;
+                for book in &books {
+                    let evicted = book.evict_expired();
+                    if evicted > 0 {
+                        tracing::debug!(symbol = %book.symbol, evicted, "evicted stale levels");
+                    }
+                }
+            }
+        })
+    });
 
-    // Start gRPC server that streams summaries from the same in-memory order book.
-    let grpc_ob = orderbook.clone();
-    let mut grpc_handle = tokio::spawn(async move {
-        if let Err(e) = api::grpc::run_grpc_server(grpc_ob).await {
-            eprintln!("gRPC server error: {e}");
-        }
+    // With HTTP_ADDR set, serve JSON snapshots of any pair at `GET /book`.
+    #[cfg(feature = "http")]
+    let http_handle = cli.http_addr.map(|addr| {
+        let state = api::http::HttpState {
+            books: Arc::new(books.clone()),
+            default_pair: pairs[0].as_str().to_string(),
+            default_depth: depth,
+            stream_interval: Duration::from_millis(util::env_or("HTTP_STREAM_MS", 1_000u64)),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = api::http::run_http_server(addr, state).await {
+                tracing::error!(error = %e, "HTTP server error");
+            }
+        })
     });
+    #[cfg(not(feature = "http"))]
+    let http_handle: Option<tokio::task::JoinHandle<()>> = None;
 
-    // Create a channel to receive price updates from exchanges
-    let (tx, mut rx) = mpsc::channel::<api::ExchangePrice>(1000);
+    // With WS_SERVER_ADDR set, push snapshots of every pair to WebSocket clients.
+    #[cfg(feature = "ws-server")]
+    let ws_server_handle = cli.ws_server_addr.map(|addr| {
+        let state = api::ws_server::WsServerState {
+            books: pairs
+                .iter()
+                .map(|pair| books[pair.as_str()].clone())
+                .collect(),
+            depth,
+            interval: Duration::from_millis(util::env_or("WS_SERVER_MS", 1_000u64)),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = api::ws_server::run_ws_server(addr, state).await {
+                tracing::error!(error = %e, "WebSocket server error");
+            }
+        })
+    });
+    #[cfg(not(feature = "ws-server"))]
+    let ws_server_handle: Option<tokio::task::JoinHandle<()>> = None;
 
-    // Spawn Binance listener (with small sync delay so both exchanges start together)
-    let binance_tx = tx.clone();
-    let binance_pair = pair.clone();
-    let mut binance_handle = tokio::spawn(async move {
-        sleep(Duration::from_millis(200)).await;
-        let client = api::binance::BinanceClient::new(binance_tx);
-        client.listen_pair(binance_pair).await;
+    // Log level parse counts per exchange every PARSE_REPORT_SECS (0 = off), warning when
+    // more than PARSE_FAILURE_WARN_PCT percent of an exchange's levels failed to parse.
+    let parse_report_every = util::env_or("PARSE_REPORT_SECS", 60u64);
+    let parse_report_handle = (parse_report_every > 0).then(|| {
+        let warn_above = util::env_or("PARSE_FAILURE_WARN_PCT", 5.0f64) / 100.0;
+        tokio::spawn(metrics::report_parse_rates(
+            Duration::from_secs(parse_report_every),
+            warn_above,
+        ))
     });
 
-    // Spawn Bitstamp listener (same delay as Binance)
-    let bitstamp_tx = tx.clone();
-    let bitstamp_pair = pair;
-    let mut bitstamp_handle = tokio::spawn(async move {
-        sleep(Duration::from_millis(200)).await;
-        let client = api::bitstamp::BitstampClient::new(bitstamp_tx);
-        client.listen_pair(bitstamp_pair).await;
+    // Serve Prometheus metrics; the weak sender lets the channel still close on shutdown.
+    // `/health` on the same server counts a feed as stale after FEED_STALE_SECS without data.
+    let metrics_addr = cli.metrics_addr;
+    let metrics_state = metrics::MetricsState {
+        books: Arc::new(books.clone()),
+        updates: tx.downgrade(),
+    };
+    let health_state = health::HealthState {
+        stale_after: Duration::from_secs(util::env_or("FEED_STALE_SECS", 30u64)),
+    };
+    let metrics_handle = tokio::spawn(async move {
+        if let Err(e) = metrics::run_metrics_server(metrics_addr, metrics_state, health_state).await
+        {
+            tracing::error!(error = %e, "metrics server error");
+        }
     });
 
     // We no longer need our own sender handle in main.
@@ -68,31 +215,375 @@ async fn main() {
     let ctrl_c = signal::ctrl_c();
     tokio::pin!(ctrl_c);
 
+    // --duration stops after that long (handy for tests); otherwise run until Ctrl+C.
+    let run_duration = cli.run_duration();
+    let run_window = async move {
+        match run_duration {
+            Some(secs) => sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending::<()>().await,
+        }
+    };
+    tokio::pin!(run_window);
+
+    // With RECORD_PATH set, append a snapshot of every book each RECORD_INTERVAL_MS to that
+    // file as NDJSON; with RECORD_UPDATES_PATH set, append every update (for --replay).
+    // Writer tasks do the file I/O; this loop only publishes what to record.
+    let Ok(snapshot_recorder) = start_recorder(cli.record_path.as_deref()).await else {
+        return ExitCode::FAILURE;
+    };
+    let Ok(update_recorder) = start_recorder(cli.record_updates_path.as_deref()).await else {
+        return ExitCode::FAILURE;
+    };
+    let mut record_ticker = snapshot_recorder.as_ref().map(|_| {
+        let period = Duration::from_millis(util::env_or("RECORD_INTERVAL_MS", 1_000u64).max(1));
+        tokio::time::interval_at(Instant::now() + period, period)
+    });
+
+    // SNAPSHOT_INTERVAL_SECS > 0 also prints snapshots periodically, not just at shutdown.
+    let snapshot_every = util::env_or("SNAPSHOT_INTERVAL_SECS", 0u64);
+    let mut snapshot_ticker = (snapshot_every > 0).then(|| {
+        let period = Duration::from_secs(snapshot_every);
+        tokio::time::interval_at(Instant::now() + period, period)
+    });
+
+    // FIREHOSE_CAPACITY > 0 tees every update taken off the channel to a broadcast of that
+    // capacity before it reaches its book. Forwarding with no subscriber is a no-op.
+    let firehose_capacity = util::env_or("FIREHOSE_CAPACITY", 0usize);
+    let firehose = (firehose_capacity > 0).then(|| firehose::Firehose::new(firehose_capacity));
+
+    let mut all_clients_stopped = false;
+    let mut batch = Vec::with_capacity(UPDATE_BATCH);
     loop {
         tokio::select! {
-            maybe_price = rx.recv() => {
-                match maybe_price {
-                    Some(price) => {
-                        let _span = tracing::info_span!("update_book", exchange = %price.exchange_name()).entered();
-                        orderbook.update_price_level(price);
+            // Take whatever is queued (a depth message's levels arrive back to back) and apply
+            // it in runs, so each book locks an exchange's side once per run, not per level.
+            received = rx.recv_many(&mut batch, UPDATE_BATCH) => {
+                if received == 0 {
+                    // All senders closed; nothing more to aggregate.
+                    break;
+                }
+                let _span = tracing::info_span!("update_book", updates = received).entered();
+                if let Some(recorder) = &update_recorder {
+                    recorder.record(batch.iter().map(recorder::RecordedUpdate::from).collect::<Vec<_>>());
+                }
+                if let Some(firehose) = &firehose {
+                    firehose.forward(&batch);
+                }
+                orderbook::apply_updates(&books, &batch);
+                batch.clear();
+            }
+            _ = async {
+                match snapshot_ticker.as_mut() {
+                    Some(ticker) => {
+                        ticker.tick().await;
                     }
-                    None => {
-                        // All senders closed; nothing more to aggregate.
-                        break;
+                    None => std::future::pending().await,
+                }
+            } => print_snapshots(&books, depth),
+            _ = async {
+                match record_ticker.as_mut() {
+                    Some(ticker) => {
+                        ticker.tick().await;
                     }
+                    None => std::future::pending().await,
+                }
+            } => {
+                if let Some(recorder) = &snapshot_recorder {
+                    record_snapshots(&books, depth, recorder);
                 }
             }
             _ = &mut ctrl_c => break,
-            _ = &mut binance_handle => break,
-            _ = &mut bitstamp_handle => break,
-            _ = &mut grpc_handle => break,
+            _ = &mut run_window => break,
+            // A client stops only once it gives up; keep serving the others' data.
+            Some(joined) = clients.join_next(), if !clients.is_empty() => {
+                match joined {
+                    Ok((exchange, Err(e))) => {
+                        tracing::error!(exchange = exchange.as_str(), error = %e, "exchange client stopped");
+                    }
+                    Ok((exchange, Ok(()))) => {
+                        tracing::warn!(exchange = exchange.as_str(), "exchange client stopped");
+                    }
+                    Err(e) => tracing::error!(error = %e, "exchange client task failed"),
+                }
+                if clients.is_empty() {
+                    all_clients_stopped = true;
+                    break;
+                }
+            }
+            _ = async {
+                match grpc_handle.as_mut() {
+                    Some(handle) => {
+                        let _ = handle.await;
+                    }
+                    None => std::future::pending().await,
+                }
+            } => break,
         }
     }
 
     // Graceful-ish shutdown: stop exchange tasks.
-    binance_handle.abort();
-    bitstamp_handle.abort();
+    clients.abort_all();
+    metrics_handle.abort();
+    if let Some(handle) = &eviction_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &parse_report_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &replay_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &grpc_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &http_handle {
+        handle.abort();
+    }
+    if let Some(handle) = &ws_server_handle {
+        handle.abort();
+    }
+
+    // Apply updates still buffered in the channel so the snapshot reflects everything received.
+    // Closing first stops further sends, so the drain ends once the buffer is empty.
+    rx.close();
+    // They are applied as one batch, chunked by pair like the updates above.
+    let deadline = Instant::now() + SHUTDOWN_DRAIN_TIMEOUT;
+    batch.clear();
+    while Instant::now() < deadline {
+        let Ok(price) = rx.try_recv() else {
+            break;
+        };
+        batch.push(price);
+    }
+    if let Some(firehose) = &firehose {
+        firehose.forward(&batch);
+    }
+    orderbook::apply_updates(&books, &batch);
+    tracing::info!(
+        drained = batch.len(),
+        "applied buffered updates before snapshot"
+    );
+
+    // Take and print a final snapshot of every combined book.
+    print_snapshots(&books, depth);
+
+    // Record the final snapshot too, then close the recordings so they flush.
+    if let Some(recorder) = snapshot_recorder {
+        record_snapshots(&books, depth, &recorder);
+        recorder.finish(SHUTDOWN_DRAIN_TIMEOUT).await;
+    }
+    if let Some(recorder) = update_recorder {
+        recorder.record(
+            batch
+                .iter()
+                .map(recorder::RecordedUpdate::from)
+                .collect::<Vec<_>>(),
+        );
+        recorder.finish(SHUTDOWN_DRAIN_TIMEOUT).await;
+    }
+
+    // With every exchange gone there is nothing left to aggregate: report failure.
+    if all_clients_stopped {
+        tracing::error!("every exchange client stopped");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print one JSON object with a snapshot of every book, keyed by pair.
+fn print_snapshots(books: &HashMap<String, Arc<OrderBook>>, depth: usize) {
+    let snapshot: BTreeMap<&str, orderbook::BookSnapshot> = books
+        .iter()
+        .map(|(symbol, book)| (symbol.as_str(), book.snapshot(depth)))
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&snapshot).unwrap());
+}
 
-    // Take and print a final snapshot of the combined book.
-    orderbook.print_snapshot_json();
+/// What an exchange client task returns when it stops.
+type ClientExit = (api::Exchange, Result<(), api::ClientError>);
+
+/// Spawn the listeners for every subscription (see `api::subscriptions`), all sending into
+/// `tx`: one per exchange and pair, except Binance, which carries all pairs on one
+/// combined-stream connection.
+fn spawn_clients(
+    clients: &mut JoinSet<ClientExit>,
+    subscriptions: &[(api::Exchange, Vec<api::TradingPair>)],
+    tx: api::UpdateSender,
+    channels: FeedChannels,
+) {
+    for (exchange, pairs) in subscriptions {
+        let exchange = *exchange;
+        // <EXCHANGE>_WS_URL points a client at a sandbox or mock server.
+        let ws_url = exchange.ws_url_override();
+        match exchange {
+            api::Exchange::Binance => {
+                let mut client = api::binance::BinanceClient::new(tx.clone());
+                client.ws_url = ws_url.unwrap_or(client.ws_url);
+                // BINANCE_STREAM picks a depth and update speed, or bookTicker.
+                client.stream = channels.binance;
+                // BINANCE_REST_URL is where the diff stream's snapshot comes from.
+                client.rest_url = util::env_or("BINANCE_REST_URL", client.rest_url);
+                let pairs = pairs.to_vec();
+                spawn_listener(clients, exchange, async move {
+                    client.listen_pairs(pairs).await
+                });
+            }
+            api::Exchange::Bitstamp => {
+                for pair in pairs.iter().cloned() {
+                    let mut client = api::bitstamp::BitstampClient::new(tx.clone());
+                    client.ws_url = ws_url.clone().unwrap_or(client.ws_url);
+                    spawn_listener(
+                        clients,
+                        exchange,
+                        async move { client.listen_pair(pair).await },
+                    );
+                }
+            }
+            api::Exchange::Kraken => {
+                for pair in pairs.iter().cloned() {
+                    let mut client = api::kraken::KrakenClient::new(tx.clone());
+                    client.ws_url = ws_url.clone().unwrap_or(client.ws_url);
+                    client.channel = channels.kraken;
+                    spawn_listener(
+                        clients,
+                        exchange,
+                        async move { client.listen_pair(pair).await },
+                    );
+                }
+            }
+            // OKX_DEPTH_CHANNEL picks the book channel.
+            api::Exchange::Okx => {
+                for pair in pairs.iter().cloned() {
+                    let mut client = api::okx::OkxClient::new(tx.clone());
+                    client.ws_url = ws_url.clone().unwrap_or(client.ws_url);
+                    client.depth_channel = channels.okx;
+                    spawn_listener(
+                        clients,
+                        exchange,
+                        async move { client.listen_pair(pair).await },
+                    );
+                }
+            }
+            api::Exchange::Coinbase => {
+                for pair in pairs.iter().cloned() {
+                    let mut client = api::coinbase::CoinbaseClient::new(tx.clone());
+                    client.ws_url = ws_url.clone().unwrap_or(client.ws_url);
+                    client.channel = channels.coinbase;
+                    spawn_listener(
+                        clients,
+                        exchange,
+                        async move { client.listen_pair(pair).await },
+                    );
+                }
+            }
+            api::Exchange::Bybit => {
+                for pair in pairs.iter().cloned() {
+                    let mut client = api::bybit::BybitClient::new(tx.clone());
+                    client.ws_url = ws_url.clone().unwrap_or(client.ws_url);
+                    client.depth = channels.bybit_depth;
+                    spawn_listener(
+                        clients,
+                        exchange,
+                        async move { client.listen_pair(pair).await },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Spawn one of `exchange`'s listeners. Each waits briefly so the exchanges start together.
+fn spawn_listener(
+    clients: &mut JoinSet<ClientExit>,
+    exchange: api::Exchange,
+    listen: impl Future<Output = Result<(), api::ClientError>> + Send + 'static,
+) {
+    clients.spawn(async move {
+        sleep(Duration::from_millis(200)).await;
+        (exchange, listen.await)
+    });
+}
+
+/// The channel each venue's client subscribes to.
+#[derive(Debug, Clone, Copy)]
+struct FeedChannels {
+    binance: api::binance::BinanceStream,
+    okx: api::okx::OkxDepthChannel,
+    coinbase: api::coinbase::CoinbaseChannel,
+    kraken: api::kraken::KrakenChannel,
+    bybit_depth: u32,
+}
+
+impl FeedChannels {
+    /// `<EXCHANGE>_*` variables pick each channel. With `top_of_book` the defaults are each
+    /// venue's lightest feed that still carries its best bid and ask; Bitstamp has none
+    /// lighter than its book snapshots, which the book trims to the best level itself.
+    fn from_env(top_of_book: bool) -> Self {
+        use api::{binance, bybit, coinbase, kraken, okx};
+        let (binance, okx, coinbase, kraken, bybit_depth) = if top_of_book {
+            (
+                binance::BinanceStream::BookTicker,
+                okx::OkxDepthChannel::BboTbt,
+                coinbase::CoinbaseChannel::Ticker,
+                kraken::KrakenChannel::Spread,
+                1,
+            )
+        } else {
+            (
+                binance::BinanceStream::default(),
+                okx::OkxDepthChannel::Books,
+                coinbase::CoinbaseChannel::default(),
+                kraken::KrakenChannel::default(),
+                bybit::BYBIT_BOOK_DEPTH,
+            )
+        };
+        FeedChannels {
+            // BINANCE_DEPTH_STREAM is the variable's name from before bookTicker was offered.
+            binance: util::env_or(
+                "BINANCE_STREAM",
+                util::env_or("BINANCE_DEPTH_STREAM", binance),
+            ),
+            okx: util::env_or("OKX_DEPTH_CHANNEL", okx),
+            coinbase: util::env_or("COINBASE_CHANNEL", coinbase),
+            kraken: util::env_or("KRAKEN_CHANNEL", kraken),
+            bybit_depth: util::env_or("BYBIT_BOOK_DEPTH", bybit_depth),
+        }
+    }
+}
+
+/// Publish a timestamped snapshot of every book, in pair order, to the recorder.
+fn record_snapshots(
+    books: &HashMap<String, Arc<OrderBook>>,
+    depth: usize,
+    recorder: &recorder::Recorder<recorder::RecordedSnapshot>,
+) {
+    let captured_at = util::current_timestamp_ms();
+    let books: BTreeMap<&String, &Arc<OrderBook>> = books.iter().collect();
+    let snapshots: Vec<_> = books
+        .values()
+        .map(|book| recorder::RecordedSnapshot {
+            captured_at,
+            snapshot: book.snapshot(depth),
+        })
+        .collect();
+    recorder.record(snapshots);
+}
+
+/// Start recording to `path` when one is configured; logs and fails if the file cannot be
+/// opened.
+async fn start_recorder<T>(
+    path: Option<&std::path::Path>,
+) -> std::io::Result<Option<recorder::Recorder<T>>>
+where
+    T: serde::Serialize + Send + Sync + 'static,
+{
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    recorder::Recorder::start(path)
+        .await
+        .inspect_err(|e| {
+            tracing::error!(path = %path.display(), error = %e, "could not open recording");
+        })
+        .map(Some)
 }
@@ -0,0 +1,447 @@
+//! Prometheus metrics: per-exchange message counters plus book gauges, served on `/metrics`
+//! (next to `/health`, see `health`).
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock};
+
+use axum::{Router, extract::State, routing::get};
+use dashmap::DashMap;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+use crate::api::{ClientError, ExchangePrice};
+use crate::health::{HealthState, health_handler};
+use crate::orderbook::OrderBook;
+use crate::util::ParseError;
+
+/// Reads one per-pair gauge from a book; `None` skips the sample.
+type BookGauge = fn(&OrderBook) -> Option<f64>;
+
+/// Process-wide counters, incremented by the exchange clients.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Messages handled successfully, by exchange.
+    messages_received: DashMap<&'static str, AtomicU64>,
+    /// Messages that failed to parse or apply, by exchange.
+    parse_failures: DashMap<&'static str, AtomicU64>,
+    /// Price levels parsed and forwarded, by exchange.
+    levels_parsed: DashMap<&'static str, AtomicU64>,
+    /// Price levels dropped because a number failed to parse, by exchange and reason.
+    level_parse_errors: DashMap<(&'static str, &'static str), AtomicU64>,
+    /// Updates that found the aggregator channel full, by exchange and whether the client
+    /// waited for room or dropped the update.
+    channel_full: DashMap<(&'static str, &'static str), AtomicU64>,
+    /// Client errors, by exchange and `ClientError::kind`.
+    client_errors: DashMap<(&'static str, &'static str), AtomicU64>,
+}
+
+impl Metrics {
+    /// Count one inbound message from `exchange`, as handled or failed.
+    pub fn record_message(&self, exchange: &'static str, ok: bool) {
+        let counters = if ok {
+            &self.messages_received
+        } else {
+            &self.parse_failures
+        };
+        counters
+            .entry(exchange)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count one price level dropped by `exchange` because a number failed to parse.
+    pub fn record_parse_error(&self, exchange: &'static str, error: ParseError) {
+        self.level_parse_errors
+            .entry((exchange, error.reason()))
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count one error from `exchange`'s client.
+    pub fn record_client_error(&self, exchange: &'static str, error: &ClientError) {
+        self.client_errors
+            .entry((exchange, error.kind()))
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count one price level from `exchange` parsed successfully.
+    pub fn record_level_parsed(&self, exchange: &'static str) {
+        // Called per level: skip the shard write lock `entry` takes once the counter exists.
+        if let Some(count) = self.levels_parsed.get(exchange) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.levels_parsed
+            .entry(exchange)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Levels parsed and levels dropped so far, per exchange, summed over failure reasons.
+    fn level_parse_totals(&self) -> HashMap<&'static str, (u64, u64)> {
+        let mut totals: HashMap<&'static str, (u64, u64)> = HashMap::new();
+        for entry in self.levels_parsed.iter() {
+            totals.entry(entry.key()).or_default().0 += entry.value().load(Ordering::Relaxed);
+        }
+        for entry in self.level_parse_errors.iter() {
+            totals.entry(entry.key().0).or_default().1 += entry.value().load(Ordering::Relaxed);
+        }
+        totals
+    }
+
+    /// Count one update from `exchange` that found the channel full; returns the new total
+    /// for that exchange and `action`.
+    pub fn record_channel_full(&self, exchange: &'static str, action: &'static str) -> u64 {
+        self.channel_full
+            .entry((exchange, action))
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed)
+            + 1
+    }
+
+    #[cfg(test)]
+    pub fn channel_full_count(&self, exchange: &'static str, action: &'static str) -> u64 {
+        self.channel_full
+            .get(&(exchange, action))
+            .map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+
+    /// Counter values sorted by exchange, so the output is stable between scrapes.
+    fn sorted(counters: &DashMap<&'static str, AtomicU64>) -> Vec<(&'static str, u64)> {
+        let mut values: Vec<_> = counters
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().load(Ordering::Relaxed)))
+            .collect();
+        values.sort_unstable();
+        values
+    }
+}
+
+/// What the `/metrics` handler reads gauges from.
+#[derive(Clone)]
+pub struct MetricsState {
+    pub books: Arc<HashMap<String, Arc<OrderBook>>>,
+    /// The update channel, held weakly so it still closes when the clients stop.
+    pub updates: mpsc::WeakSender<ExchangePrice>,
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+pub fn render(metrics: &Metrics, state: &MetricsState) -> String {
+    let mut out = String::new();
+
+    let counters = [
+        (
+            "agg_messages_received_total",
+            "Exchange messages handled successfully.",
+            &metrics.messages_received,
+        ),
+        (
+            "agg_parse_failures_total",
+            "Exchange messages that failed to parse.",
+            &metrics.parse_failures,
+        ),
+        (
+            "agg_levels_parsed_total",
+            "Price levels parsed and forwarded to the aggregator.",
+            &metrics.levels_parsed,
+        ),
+    ];
+    for (name, help, values) in counters {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (exchange, value) in Metrics::sorted(values) {
+            let _ = writeln!(out, "{name}{{exchange=\"{exchange}\"}} {value}");
+        }
+    }
+
+    let labeled_counters = [
+        (
+            "agg_level_parse_errors_total",
+            "Price levels dropped because a price or quantity failed to parse.",
+            "reason",
+            &metrics.level_parse_errors,
+        ),
+        (
+            "agg_channel_full_total",
+            "Updates that found the aggregator channel full (waited for room or dropped).",
+            "action",
+            &metrics.channel_full,
+        ),
+        (
+            "agg_client_errors_total",
+            "Exchange client errors: failed messages and sessions ended by an error.",
+            "kind",
+            &metrics.client_errors,
+        ),
+    ];
+    for (name, help, label, values) in labeled_counters {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let mut values: Vec<_> = values
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().load(Ordering::Relaxed)))
+            .collect();
+        values.sort_unstable();
+        for ((exchange, value_label), value) in values {
+            let _ = writeln!(
+                out,
+                "{name}{{exchange=\"{exchange}\",{label}=\"{value_label}\"}} {value}"
+            );
+        }
+    }
+
+    let mut pairs: Vec<_> = state.books.iter().collect();
+    pairs.sort_unstable_by_key(|(pair, _)| pair.as_str());
+    let gauges: [(&str, &str, BookGauge); 3] = [
+        (
+            "agg_spread_cents",
+            "Best ask minus best bid across exchanges (negative when crossed).",
+            |book| book.spread().map(|s| cents(book, s.value as f64)),
+        ),
+        (
+            "agg_best_bid_cents",
+            "Highest bid across exchanges.",
+            |book| {
+                book.best_bid()
+                    .map(|(_, price, _)| cents(book, price as f64))
+            },
+        ),
+        (
+            "agg_best_ask_cents",
+            "Lowest ask across exchanges.",
+            |book| {
+                book.best_ask()
+                    .map(|(_, price, _)| cents(book, price as f64))
+            },
+        ),
+    ];
+    for (name, help, read) in gauges {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for (pair, book) in &pairs {
+            if let Some(value) = read(book) {
+                let _ = writeln!(out, "{name}{{pair=\"{pair}\"}} {value}");
+            }
+        }
+    }
+
+    let backlog = state
+        .updates
+        .upgrade()
+        .map(|tx| tx.max_capacity() - tx.capacity())
+        .unwrap_or(0);
+    let _ = writeln!(
+        out,
+        "# HELP agg_channel_backlog Updates queued but not yet applied."
+    );
+    let _ = writeln!(out, "# TYPE agg_channel_backlog gauge");
+    let _ = writeln!(out, "agg_channel_backlog {backlog}");
+
+    out
+}
+
+/// One exchange's level parsing over a reporting period.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseRate {
+    pub exchange: &'static str,
+    pub parsed: u64,
+    pub failed: u64,
+}
+
+impl ParseRate {
+    /// Share of levels that failed to parse, from 0 to 1.
+    pub fn failure_rate(&self) -> f64 {
+        let total = self.parsed + self.failed;
+        if total == 0 {
+            0.0
+        } else {
+            self.failed as f64 / total as f64
+        }
+    }
+}
+
+/// Fewest levels in a period before a high failure rate is warned about, so a couple of bad
+/// levels on a quiet feed don't read as a format change.
+const MIN_LEVELS_TO_WARN: u64 = 100;
+
+/// Turns the running parse counters into per-period rates.
+#[derive(Debug, Default)]
+pub struct ParseRateReporter {
+    last: HashMap<&'static str, (u64, u64)>,
+}
+
+impl ParseRateReporter {
+    /// Levels parsed and dropped per exchange since the previous call, sorted by exchange.
+    /// Exchanges with nothing new are left out.
+    pub fn take(&mut self, metrics: &Metrics) -> Vec<ParseRate> {
+        let mut rates: Vec<ParseRate> = metrics
+            .level_parse_totals()
+            .into_iter()
+            .filter_map(|(exchange, (parsed, failed))| {
+                let (last_parsed, last_failed) = self
+                    .last
+                    .insert(exchange, (parsed, failed))
+                    .unwrap_or_default();
+                let rate = ParseRate {
+                    exchange,
+                    parsed: parsed - last_parsed,
+                    failed: failed - last_failed,
+                };
+                (rate.parsed + rate.failed > 0).then_some(rate)
+            })
+            .collect();
+        rates.sort_unstable_by_key(|rate| rate.exchange);
+        rates
+    }
+}
+
+/// Every `period`, log each exchange's level parse counts, and warn when more than
+/// `warn_above` (0 to 1) of a busy exchange's levels failed to parse.
+pub async fn report_parse_rates(period: std::time::Duration, warn_above: f64) {
+    let mut reporter = ParseRateReporter::default();
+    let mut ticker = tokio::time::interval(period);
+    // The first tick is immediate; start counting from there.
+    ticker.tick().await;
+    reporter.take(&METRICS);
+    loop {
+        ticker.tick().await;
+        for rate in reporter.take(&METRICS) {
+            let failure_rate = rate.failure_rate();
+            if failure_rate > warn_above && rate.parsed + rate.failed >= MIN_LEVELS_TO_WARN {
+                tracing::warn!(
+                    exchange = rate.exchange,
+                    parsed = rate.parsed,
+                    failed = rate.failed,
+                    failure_rate,
+                    "high price level parse failure rate"
+                );
+            } else {
+                tracing::info!(
+                    exchange = rate.exchange,
+                    parsed = rate.parsed,
+                    failed = rate.failed,
+                    "price levels parsed"
+                );
+            }
+        }
+    }
+}
+
+/// Convert a price in the book's price units to cents, so gauges don't depend on the scale.
+fn cents(book: &OrderBook, price_units: f64) -> f64 {
+    price_units / book.price_scale() * 100.0
+}
+
+async fn metrics_handler(State(state): State<MetricsState>) -> String {
+    render(&METRICS, &state)
+}
+
+/// Serve `/metrics` and `/health` on `addr` until the task is dropped.
+pub async fn run_metrics_server(
+    addr: SocketAddr,
+    state: MetricsState,
+    health: HealthState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(state)
+        .merge(
+            Router::new()
+                .route("/health", get(health_handler))
+                .with_state(health),
+        );
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Exchange, Side};
+
+    #[test]
+    fn renders_counters_and_book_gauges() {
+        let metrics = Metrics::default();
+        metrics.record_message("binance", true);
+        metrics.record_message("binance", true);
+        metrics.record_message("okx", false);
+        metrics.record_parse_error("kraken", ParseError::Overflow);
+        metrics.record_level_parsed("kraken");
+        metrics.record_client_error("coinbase", &ClientError::Parse(ParseError::InvalidFormat));
+
+        let book = OrderBook::new("BTC-USDT".to_string());
+        book.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        book.update_price_level(ExchangePrice::new(Exchange::Kraken, 105, 1, Side::Sell));
+
+        let (tx, _rx) = mpsc::channel(8);
+        tx.try_send(ExchangePrice::new(Exchange::Okx, 1, 1, Side::Buy))
+            .unwrap();
+        let state = MetricsState {
+            books: Arc::new(HashMap::from([("BTC-USDT".to_string(), Arc::new(book))])),
+            updates: tx.downgrade(),
+        };
+
+        let text = render(&metrics, &state);
+        assert!(text.contains("agg_messages_received_total{exchange=\"binance\"} 2\n"));
+        assert!(text.contains("agg_parse_failures_total{exchange=\"okx\"} 1\n"));
+        assert!(
+            text.contains(
+                "agg_level_parse_errors_total{exchange=\"kraken\",reason=\"overflow\"} 1\n"
+            )
+        );
+        assert!(text.contains("agg_levels_parsed_total{exchange=\"kraken\"} 1\n"));
+        assert!(text.contains("agg_client_errors_total{exchange=\"coinbase\",kind=\"parse\"} 1\n"));
+        assert!(text.contains("agg_spread_cents{pair=\"BTC-USDT\"} 5\n"));
+        assert!(text.contains("agg_best_bid_cents{pair=\"BTC-USDT\"} 100\n"));
+        assert!(text.contains("agg_best_ask_cents{pair=\"BTC-USDT\"} 105\n"));
+        assert!(text.contains("agg_channel_backlog 1\n"));
+    }
+
+    #[test]
+    fn parse_rates_cover_each_period() {
+        let metrics = Metrics::default();
+        let mut reporter = ParseRateReporter::default();
+        for _ in 0..9 {
+            metrics.record_level_parsed("okx");
+        }
+        metrics.record_parse_error("okx", ParseError::Overflow);
+        metrics.record_parse_error("kraken", ParseError::InvalidFormat);
+
+        let rates = reporter.take(&metrics);
+        assert_eq!(
+            rates,
+            vec![
+                ParseRate {
+                    exchange: "kraken",
+                    parsed: 0,
+                    failed: 1
+                },
+                ParseRate {
+                    exchange: "okx",
+                    parsed: 9,
+                    failed: 1
+                },
+            ]
+        );
+        assert_eq!(rates[0].failure_rate(), 1.0);
+        assert_eq!(rates[1].failure_rate(), 0.1);
+
+        // The next period only counts what happened since, and skips idle exchanges.
+        metrics.record_level_parsed("okx");
+        assert_eq!(
+            reporter.take(&metrics),
+            vec![ParseRate {
+                exchange: "okx",
+                parsed: 1,
+                failed: 0
+            }]
+        );
+    }
+}
@@ -1,241 +1,1951 @@
 use std::{
-    collections::BTreeMap,
-    sync::{Arc, RwLock},
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    iter::{Peekable, Rev},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
 };
 
+use arc_swap::{ArcSwap, Guard};
 use dashmap::DashMap;
-use serde_json::json;
+use imbl::OrdMap;
+use serde::Serialize;
 use tracing::instrument;
 
-use crate::api::{Exchange, ExchangePrice, Side};
+use crate::api::{Exchange, ExchangePrice, Side, UpdateMode};
+use crate::util::current_timestamp_ms;
+
+/// Number of levels per side returned when no depth is requested explicitly.
+pub const DEFAULT_DEPTH: usize = 10;
+
+/// Number of recent samples in the rolling latency mean.
+const LATENCY_WINDOW: usize = 100;
+
+/// Percentage band around mid used for the liquidity score in snapshots.
+const SNAPSHOT_LIQUIDITY_PCT: f64 = 1.0;
+
+/// Weights for `OrderBook::liquidity_score`.
+#[derive(Debug, Clone, Copy)]
+pub struct LiquidityWeights {
+    /// Multiplier on `ln(1 + notional within the band)`.
+    pub depth: f64,
+    /// Multiplier on `ln(1 + spread in bps)`.
+    pub spread: f64,
+}
+
+impl Default for LiquidityWeights {
+    fn default() -> Self {
+        LiquidityWeights {
+            depth: 1.0,
+            spread: 1.0,
+        }
+    }
+}
+
+/// Feed latency for one exchange: `received_at - exchange_timestamp`, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencyStats {
+    /// Latency of the most recent update.
+    #[serde(rename = "last")]
+    pub last_ms: u64,
+    /// Mean over the last `LATENCY_WINDOW` updates.
+    #[serde(rename = "mean")]
+    pub mean_ms: f64,
+    /// Largest latency seen since startup.
+    #[serde(rename = "max")]
+    pub max_ms: u64,
+}
+
+/// Rolling window backing `LatencyStats`.
+#[derive(Debug, Default)]
+struct LatencyTracker {
+    window: VecDeque<u64>,
+    sum: u64,
+    max: u64,
+}
+
+impl LatencyTracker {
+    fn record(&mut self, latency_ms: u64) {
+        if self.window.len() == LATENCY_WINDOW
+            && let Some(oldest) = self.window.pop_front()
+        {
+            self.sum -= oldest;
+        }
+        self.window.push_back(latency_ms);
+        self.sum += latency_ms;
+        self.max = self.max.max(latency_ms);
+    }
+
+    fn stats(&self) -> Option<LatencyStats> {
+        let last_ms = *self.window.back()?;
+        Some(LatencyStats {
+            last_ms,
+            mean_ms: self.sum as f64 / self.window.len() as f64,
+            max_ms: self.max,
+        })
+    }
+}
+
+/// What an update's quantity means for the level at its price.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuantityMode {
+    /// The level's new total size, as every supported venue sends it.
+    #[default]
+    Absolute,
+    /// Size added to whatever rests at the price, for sources that report fills or
+    /// additions rather than totals. A zero quantity still removes the level.
+    Additive,
+}
+
+/// A venue's own book in a state no real exchange publishes, so parsing or sequencing has
+/// gone wrong for it. Prices are in price units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum BookError {
+    #[error("{} book is locked: best bid {bid} equals best ask {ask}", .exchange.as_str())]
+    Locked {
+        exchange: Exchange,
+        bid: u64,
+        ask: u64,
+    },
+    #[error("{} book is crossed: best bid {bid} above best ask {ask}", .exchange.as_str())]
+    Crossed {
+        exchange: Exchange,
+        bid: u64,
+        ask: u64,
+    },
+}
+
+/// Signed top-of-book spread, so crossed books stay distinguishable from tight ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spread {
+    /// Best ask minus best bid, in price units. Negative when the book is crossed.
+    pub value: i64,
+    /// Best ask below best bid, usually a stale level on one venue.
+    pub crossed: bool,
+}
+
+/// Result of walking the combined book for a hypothetical market order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FillEstimate {
+    /// Quantity that could be filled, in smallest units (at most the target).
+    pub filled_qty: u64,
+    /// Total cost of the filled quantity, in price units (rounded).
+    pub total_cost: u64,
+    /// Average fill price in price units (rounded).
+    pub avg_price: u64,
+    /// Whether the book had enough depth for the full target.
+    pub complete: bool,
+}
+
+/// How `BookSnapshot` prices and amounts are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// JSON numbers in whole units (`43250.5`). Convenient, but rounds past ~15 digits.
+    #[default]
+    Float,
+    /// The stored integers (`4325050` cents), with their decimals in the snapshot's `scale`.
+    Integer,
+    /// Exact whole-unit strings carrying every stored decimal (`"43250.50"`).
+    Decimal,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "float" => Ok(OutputFormat::Float),
+            "integer" => Ok(OutputFormat::Integer),
+            "decimal" => Ok(OutputFormat::Decimal),
+            other => Err(format!(
+                "unknown output format '{other}' (valid: float, integer, decimal)"
+            )),
+        }
+    }
+}
+
+/// How `BookSnapshot` names exchanges: on levels and as `latency_ms` keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ExchangeFormat {
+    /// Lowercase name (`"okx"`), `Exchange::as_str`.
+    #[default]
+    Name,
+    /// The Rust variant name (`"Okx"`), `Exchange::enum_name`.
+    Enum,
+    /// The stable numeric id (`4`), `Exchange::id`. JSON map keys are strings, so
+    /// `latency_ms` keys read `"4"`.
+    Id,
+}
+
+impl std::str::FromStr for ExchangeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(ExchangeFormat::Name),
+            "enum" => Ok(ExchangeFormat::Enum),
+            "id" => Ok(ExchangeFormat::Id),
+            other => Err(format!(
+                "unknown exchange format '{other}' (valid: name, enum, id)"
+            )),
+        }
+    }
+}
+
+/// An exchange in a snapshot, serialized per `format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExchangeOut {
+    pub venue: Exchange,
+    pub format: ExchangeFormat,
+}
+
+impl Serialize for ExchangeOut {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.format {
+            ExchangeFormat::Name => serializer.serialize_str(self.venue.as_str()),
+            ExchangeFormat::Enum => serializer.serialize_str(self.venue.enum_name()),
+            ExchangeFormat::Id => serializer.serialize_u8(self.venue.id()),
+        }
+    }
+}
+
+/// A stored price or quantity with its decimal places, serialized per `format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Units {
+    /// Value in `10^-decimals` units, as stored in the book.
+    pub raw: u64,
+    pub decimals: u32,
+    pub format: OutputFormat,
+}
+
+impl Units {
+    /// Value in whole units, as `OutputFormat::Float` writes it.
+    pub fn to_f64(self) -> f64 {
+        self.raw as f64 / 10f64.powi(self.decimals as i32)
+    }
+
+    /// Exact value in whole units with `decimals` fractional digits, as
+    /// `OutputFormat::Decimal` writes it.
+    pub fn to_decimal_string(self) -> String {
+        let decimals = self.decimals as usize;
+        let digits = format!("{:0>width$}", self.raw, width = decimals + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - decimals);
+        if fraction.is_empty() {
+            whole.to_string()
+        } else {
+            format!("{whole}.{fraction}")
+        }
+    }
+}
+
+impl Serialize for Units {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.format {
+            OutputFormat::Float => serializer.serialize_f64(self.to_f64()),
+            OutputFormat::Integer => serializer.serialize_u64(self.raw),
+            OutputFormat::Decimal => serializer.serialize_str(&self.to_decimal_string()),
+        }
+    }
+}
+
+/// Decimals of `OutputFormat::Integer` values, so consumers can scale them back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Scale {
+    pub price_decimals: u32,
+    pub base_decimals: u32,
+}
+
+/// One combined-book level in a `BookSnapshot`, written per the book's `OutputFormat`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LevelOut {
+    pub exchange: ExchangeOut,
+    pub price: Units,
+    pub amount: Units,
+    /// Exchange timestamp (epoch ms) of the update that set the level, when the venue sent
+    /// one; compare with `generated_at` to spot stale levels.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exchange_timestamp: Option<u64>,
+}
+
+/// Point-in-time view of the combined book, as printed in the JSON snapshots.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BookSnapshot {
+    pub symbol: String,
+    /// When the snapshot was taken, epoch milliseconds.
+    pub generated_at: u64,
+    /// Best ask minus best bid, clamped at 0; `None` without enough venues.
+    pub spread: Option<Units>,
+    /// Whether the best ask is below the best bid (`spread` then reads 0).
+    pub crossed: bool,
+    /// `OrderBook::liquidity_score` within 1% of mid.
+    pub liquidity_score: Option<f64>,
+    /// Feed latency by exchange, for exchanges that sent timestamps.
+    pub latency_ms: BTreeMap<ExchangeOut, LatencyStats>,
+    /// Best first.
+    pub asks: Vec<LevelOut>,
+    /// Best first.
+    pub bids: Vec<LevelOut>,
+    /// Only with `OutputFormat::Integer`, whose values need it to mean anything.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<Scale>,
+    /// Levels per side requested, so a later `OrderBook::diff` compares like with like.
+    #[serde(skip)]
+    pub depth: usize,
+}
+
+impl BookSnapshot {
+    /// Levels added, changed or removed on each side going from `previous` to `self`.
+    /// A level is one exchange's quote at one price; a changed level kept its price but
+    /// not its amount, a removed one is no longer in the top `depth`.
+    pub fn changes_since(&self, previous: &BookSnapshot) -> BookDiff {
+        BookDiff {
+            symbol: self.symbol.clone(),
+            asks: SideDiff::between(&previous.asks, &self.asks),
+            bids: SideDiff::between(&previous.bids, &self.bids),
+        }
+    }
+}
+
+/// What changed between two snapshots of one book; see `OrderBook::diff`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BookDiff {
+    pub symbol: String,
+    pub asks: SideDiff,
+    pub bids: SideDiff,
+}
+
+impl BookDiff {
+    pub fn is_empty(&self) -> bool {
+        self.asks.is_empty() && self.bids.is_empty()
+    }
+}
+
+/// Level changes on one side. `added` and `changed` hold the new levels, best first;
+/// `removed` holds the levels as they were.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SideDiff {
+    pub added: Vec<LevelOut>,
+    pub changed: Vec<LevelOut>,
+    pub removed: Vec<LevelOut>,
+}
+
+impl SideDiff {
+    fn between(previous: &[LevelOut], current: &[LevelOut]) -> Self {
+        let key = |level: &LevelOut| (level.exchange, level.price.raw);
+        let before: HashMap<_, u64> = previous
+            .iter()
+            .map(|level| (key(level), level.amount.raw))
+            .collect();
+        let after: HashSet<_> = current.iter().map(key).collect();
+
+        let mut diff = SideDiff::default();
+        for level in current {
+            match before.get(&key(level)) {
+                None => diff.added.push(level.clone()),
+                Some(&amount) if amount != level.amount.raw => diff.changed.push(level.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = previous
+            .iter()
+            .filter(|level| !after.contains(&key(level)))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// One exchange's own best bid and ask as (price, quantity), in book units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExchangeTopOfBook {
+    pub exchange: Exchange,
+    pub bid: Option<(u64, u64)>,
+    pub ask: Option<(u64, u64)>,
+}
+
+/// One level as published: (price, quantity, exchange timestamp of the update that set it).
+type PublishedLevel = (u64, u64, Option<u64>);
+
+/// Combined best bid and best ask, each as (exchange, price, quantity).
+type BestBidAsk = ((Exchange, u64, u64), (Exchange, u64, u64));
+
+/// One venue's levels on one side, keyed by price. A persistent map: a clone shares the
+/// tree, so publishing a side is a pointer copy and the writer's next change copies only the
+/// nodes on its path.
+pub type SideLevels = OrdMap<u64, PriceLevel>;
+
+/// Read side of one side of the book: each venue's levels as last published, indexed by
+/// `Exchange as usize`. A write run swaps only its own venue's slot.
+type PublishedSide = [ArcSwap<SideLevels>; Exchange::ALL.len()];
+
+/// Every venue's published levels as one read sees them. Each side is loaded the first time
+/// the read asks for it and then kept, so a query's steps agree with each other; loading is
+/// an atomic load per venue, and writers never wait for readers.
+struct BookView<'a> {
+    book: &'a OrderBook,
+    bids: OnceCell<SideView>,
+    asks: OnceCell<SideView>,
+}
+
+impl BookView<'_> {
+    fn side(&self, side: Side) -> &SideView {
+        let (loaded, published) = match side {
+            Side::Buy => (&self.bids, &self.book.published_bids),
+            Side::Sell => (&self.asks, &self.book.published_asks),
+        };
+        loaded.get_or_init(|| SideView {
+            side,
+            venues: published.each_ref().map(|levels| levels.load()),
+        })
+    }
+
+    fn bids(&self) -> &SideView {
+        self.side(Side::Buy)
+    }
+
+    fn asks(&self) -> &SideView {
+        self.side(Side::Sell)
+    }
+
+    /// Number of venues with at least one level on `side` that `shown` keeps. A venue's top
+    /// level usually is shown, so its map is only walked when that one is hidden.
+    fn exchanges_with_levels(&self, side: Side, shown: impl Fn(&PublishedLevel) -> bool) -> usize {
+        let levels = self.side(side);
+        Exchange::ALL
+            .into_iter()
+            .filter(|&exchange| match levels.top(exchange) {
+                Some(top) => shown(&top) || levels.levels(exchange).any(|level| shown(&level)),
+                None => false,
+            })
+            .count()
+    }
+}
+
+/// One side of a `BookView`.
+struct SideView {
+    side: Side,
+    venues: [Guard<Arc<SideLevels>>; Exchange::ALL.len()],
+}
+
+impl SideView {
+    /// `exchange`'s levels, best first; empty when it has none.
+    fn levels(&self, exchange: Exchange) -> BestFirst<'_> {
+        let levels = self.venues[exchange as usize].iter();
+        match self.side {
+            Side::Buy => BestFirst::Bids(levels.rev()),
+            Side::Sell => BestFirst::Asks(levels),
+        }
+    }
+
+    /// `exchange`'s best level, found without walking its map.
+    fn top(&self, exchange: Exchange) -> Option<PublishedLevel> {
+        let levels = &self.venues[exchange as usize];
+        let (price, level) = match self.side {
+            Side::Buy => levels.get_max(),
+            Side::Sell => levels.get_min(),
+        }?;
+        Some((*price, level.quantity, level.exchange_timestamp))
+    }
+
+    /// Each venue with levels on this side, with those levels best first.
+    fn venues(&self) -> impl Iterator<Item = (Exchange, BestFirst<'_>)> {
+        Exchange::ALL
+            .into_iter()
+            .filter(|&exchange| !self.venues[exchange as usize].is_empty())
+            .map(|exchange| (exchange, self.levels(exchange)))
+    }
+
+    /// Whether `a` is a better price than `b` on this side.
+    fn better_price(&self) -> fn(u64, u64) -> bool {
+        match self.side {
+            Side::Buy => |a, b| a > b,
+            Side::Sell => |a, b| a < b,
+        }
+    }
+}
+
+/// `SideLevels` in ascending price order.
+type LevelIter<'a> = imbl::ordmap::Iter<'a, u64, PriceLevel, imbl::shared_ptr::DefaultSharedPtr>;
+
+/// One venue's `SideLevels` as `PublishedLevel`s, highest bid or lowest ask first.
+#[derive(Clone)]
+enum BestFirst<'a> {
+    Bids(Rev<LevelIter<'a>>),
+    Asks(LevelIter<'a>),
+}
+
+impl Iterator for BestFirst<'_> {
+    type Item = PublishedLevel;
+
+    fn next(&mut self) -> Option<PublishedLevel> {
+        let (&price, level) = match self {
+            BestFirst::Bids(levels) => levels.next(),
+            BestFirst::Asks(levels) => levels.next(),
+        }?;
+        Some((price, level.quantity, level.exchange_timestamp))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            BestFirst::Bids(levels) => levels.size_hint(),
+            BestFirst::Asks(levels) => levels.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for BestFirst<'_> {}
+
+/// Resting quantity at one price on one exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceLevel {
+    /// Quantity in smallest units.
+    pub quantity: u64,
+    /// When the level last changed (epoch ms), used by `evict_stale`.
+    pub updated_at: u64,
+    /// Exchange timestamp (epoch ms) of the update that set it, if the venue sent one.
+    pub exchange_timestamp: Option<u64>,
+}
+
+/// Keeps every level, for reads that must see the book as sent.
+fn all(_: &PublishedLevel) -> bool {
+    true
+}
+
+/// `merge_top_n` output as (exchange, price, quantity).
+fn untimed(exchange: Exchange, (price, quantity, _): PublishedLevel) -> (Exchange, u64, u64) {
+    (exchange, price, quantity)
+}
+
+/// `merge_top_n` output with the level's exchange timestamp alongside.
+fn timed(
+    exchange: Exchange,
+    (price, quantity, exchange_timestamp): PublishedLevel,
+) -> ((Exchange, u64, u64), Option<u64>) {
+    ((exchange, price, quantity), exchange_timestamp)
+}
 
 pub struct OrderBook {
-    #[allow(dead_code)]
     /// The symbol or identifier for this order book
     pub symbol: String,
-    // BTreeMap keeps prices sorted (bids: highest first, asks: lowest first) and maps price → quantity.
-    pub exchange_bids_price_level: DashMap<Exchange, Arc<RwLock<BTreeMap<u64, u64>>>>,
-    // One BTreeMap per exchange, sorted by price,
-    pub exchange_asks_price_level: DashMap<Exchange, Arc<RwLock<BTreeMap<u64, u64>>>>,
+    // Write side. Each map keeps prices sorted (bids read highest first, asks lowest first) and maps price → level.
+    pub exchange_bids_price_level: DashMap<Exchange, Arc<RwLock<SideLevels>>>,
+    // One map per exchange, sorted by price,
+    pub exchange_asks_price_level: DashMap<Exchange, Arc<RwLock<SideLevels>>>,
+    /// Read side: what every query method reads. Each write run republishes only the venue
+    /// and side it changed.
+    published_bids: PublishedSide,
+    published_asks: PublishedSide,
+    /// Distinct exchanges that must have levels on each side before a spread is reported.
+    pub min_exchanges: usize,
+    /// Decimals of the stored base-asset quantities (8 = satoshis for BTC).
+    pub base_decimals: u32,
+    /// Decimals of the stored prices ("price units"; 2 = cents).
+    pub price_decimals: u32,
+    /// Weights combining depth and spread in `liquidity_score`.
+    pub liquidity_weights: LiquidityWeights,
+    /// How snapshots write prices and amounts.
+    pub output_format: OutputFormat,
+    /// How snapshots name exchanges.
+    pub exchange_format: ExchangeFormat,
+    /// Levels per venue and side kept; `None` keeps the whole book.
+    pub published_depth: Option<usize>,
+    /// How update quantities are applied.
+    pub quantity_mode: QuantityMode,
+    /// Age after which `evict_expired` drops a level; `None` keeps levels until removed.
+    pub stale_after: Option<Duration>,
+    /// Levels smaller than this (smallest units) are hidden from reads; 0 shows every level.
+    /// Atomic so a shared book can be retuned; see `set_min_quantity`.
+    min_quantity: AtomicU64,
+    /// Hide levels priced more than this many basis points from the reference mid (see
+    /// `price_band`), so one venue's bad prices stay out of the aggregate. `None` is off.
+    pub max_deviation_bps: Option<u32>,
+    /// Run `validate_exchange` on the venues each `update_price_levels` call touched and
+    /// log violations. A debugging aid: it costs a view read per venue per batch.
+    pub validate_updates: bool,
+    /// Keep only each venue's best bid and ask; see `OrderBookBuilder::top_of_book_only`.
+    pub top_of_book_only: bool,
+    /// Per-exchange feed latency, fed from update timestamps.
+    latency: DashMap<Exchange, LatencyTracker>,
+    /// Bumped on every applied update, so readers can tell whether anything changed.
+    version: AtomicU64,
 }
 
-impl OrderBook {
-    pub fn new(symbol: String) -> Self {
+/// Configures an `OrderBook`. Each setter documents its default, which is what
+/// `OrderBook::new` uses.
+#[derive(Debug, Clone)]
+pub struct OrderBookBuilder {
+    symbol: String,
+    published_depth: Option<usize>,
+    quantity_mode: QuantityMode,
+    stale_after: Option<Duration>,
+    validate_updates: bool,
+    top_of_book_only: bool,
+    min_quantity: u64,
+    max_deviation_bps: Option<u32>,
+    base_decimals: u32,
+    price_decimals: u32,
+    min_exchanges: usize,
+    liquidity_weights: LiquidityWeights,
+    output_format: OutputFormat,
+    exchange_format: ExchangeFormat,
+}
+
+impl Default for OrderBookBuilder {
+    fn default() -> Self {
+        OrderBookBuilder {
+            symbol: String::new(),
+            published_depth: None,
+            quantity_mode: QuantityMode::default(),
+            stale_after: None,
+            validate_updates: false,
+            top_of_book_only: false,
+            min_quantity: 0,
+            max_deviation_bps: None,
+            base_decimals: 8,
+            price_decimals: 2,
+            min_exchanges: 1,
+            liquidity_weights: LiquidityWeights::default(),
+            output_format: OutputFormat::default(),
+            exchange_format: ExchangeFormat::default(),
+        }
+    }
+}
+
+impl OrderBookBuilder {
+    /// Pair or identifier the book reports as `symbol` (default empty).
+    pub fn symbol(mut self, symbol: impl Into<String>) -> Self {
+        self.symbol = symbol.into();
+        self
+    }
+
+    /// Levels per venue and side to keep (default `None`, the whole book). Levels pushed past
+    /// the cap are dropped, so reads always see the book as stored; an incremental feed then
+    /// shows fewer levels after its best is removed, until updates refill the tail.
+    pub fn depth(mut self, depth: Option<usize>) -> Self {
+        self.published_depth = depth;
+        self
+    }
+
+    /// How update quantities are applied (default `QuantityMode::Absolute`).
+    pub fn update_mode(mut self, mode: QuantityMode) -> Self {
+        self.quantity_mode = mode;
+        self
+    }
+
+    /// Drop levels older than this in `OrderBook::evict_expired` (default `None`, never).
+    pub fn stale_after(mut self, max_age: Option<Duration>) -> Self {
+        self.stale_after = max_age;
+        self
+    }
+
+    /// Check each updated venue for a locked or crossed book after every batch (default off).
+    pub fn validate_updates(mut self, validate: bool) -> Self {
+        self.validate_updates = validate;
+        self
+    }
+
+    /// Store only each venue's best bid and ask (default off), for feeds that send their top
+    /// level in every message: tickers, full snapshots, or one-level books. Every depth query
+    /// then returns at most one level per venue. A deeper incremental feed loses the level
+    /// behind the best when the best is removed, so pair this with a top-of-book channel.
+    pub fn top_of_book_only(mut self, top_only: bool) -> Self {
+        self.top_of_book_only = top_only;
+        self
+    }
+
+    /// Hide levels smaller than this many smallest units from reads (default 0, none hidden).
+    pub fn min_quantity(mut self, quantity: u64) -> Self {
+        self.min_quantity = quantity;
+        self
+    }
+
+    /// Hide levels more than `bps` basis points from the mid from reads (default `None`, off).
+    pub fn max_deviation_bps(mut self, bps: Option<u32>) -> Self {
+        self.max_deviation_bps = bps;
+        self
+    }
+
+    /// Decimals of stored quantities and prices (default 8 and 2: satoshis and cents).
+    pub fn decimals(mut self, base_decimals: u32, price_decimals: u32) -> Self {
+        self.base_decimals = base_decimals;
+        self.price_decimals = price_decimals;
+        self
+    }
+
+    /// Venues required on each side before a spread is reported (default 1).
+    pub fn min_exchanges(mut self, min_exchanges: usize) -> Self {
+        self.min_exchanges = min_exchanges;
+        self
+    }
+
+    /// Weights for `OrderBook::liquidity_score` (default 1.0 each).
+    pub fn liquidity_weights(mut self, weights: LiquidityWeights) -> Self {
+        self.liquidity_weights = weights;
+        self
+    }
+
+    /// How snapshots write prices and amounts (default `OutputFormat::Float`).
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    /// How snapshots name exchanges (default `ExchangeFormat::Name`).
+    pub fn exchange_format(mut self, format: ExchangeFormat) -> Self {
+        self.exchange_format = format;
+        self
+    }
+
+    pub fn build(self) -> OrderBook {
         OrderBook {
-            symbol,
+            symbol: self.symbol,
             exchange_bids_price_level: DashMap::new(),
             exchange_asks_price_level: DashMap::new(),
+            published_bids: PublishedSide::default(),
+            published_asks: PublishedSide::default(),
+            min_exchanges: self.min_exchanges,
+            base_decimals: self.base_decimals,
+            price_decimals: self.price_decimals,
+            liquidity_weights: self.liquidity_weights,
+            output_format: self.output_format,
+            exchange_format: self.exchange_format,
+            published_depth: if self.top_of_book_only {
+                Some(1)
+            } else {
+                self.published_depth
+            },
+            quantity_mode: self.quantity_mode,
+            stale_after: self.stale_after,
+            validate_updates: self.validate_updates,
+            top_of_book_only: self.top_of_book_only,
+            min_quantity: AtomicU64::new(self.min_quantity),
+            max_deviation_bps: self.max_deviation_bps,
+            latency: DashMap::new(),
+            version: AtomicU64::new(0),
         }
     }
+}
+
+impl OrderBook {
+    /// A book for `symbol` with every other setting at its `OrderBookBuilder` default.
+    pub fn new(symbol: String) -> Self {
+        OrderBook::builder().symbol(symbol).build()
+    }
+
+    pub fn builder() -> OrderBookBuilder {
+        OrderBookBuilder::default()
+    }
 
     /// Update the per-exchange price levels from a single exchange-level price update.
-    #[instrument(level = "trace", skip(self, order))]
+    #[instrument(level = "trace", skip(self, order), fields(exchange = order.exchange_name()))]
     pub fn update_price_level(&self, order: ExchangePrice) {
-        tracing::Span::current().record("exchange", order.exchange_name());
-        match order {
-            ExchangePrice::Binance {
-                price,
-                quantity,
-                exchange_timestamp,
-                received_at,
-                side,
-            } => {
-                // Touch timestamps so the fields are considered used.
-                let _ = (exchange_timestamp, received_at);
-                self.update_price_level_for_exchange(Exchange::Binance, price, quantity, side);
+        self.update_price_levels(std::slice::from_ref(&order));
+    }
+
+    /// Apply `orders` in sequence, as `update_price_level` would one at a time, but take each
+    /// exchange/side write lock once per run of consecutive updates to it rather than once
+    /// per level. A depth message's levels arrive together, so a run is usually a whole side.
+    pub fn update_price_levels(&self, orders: &[ExchangePrice]) {
+        let runs =
+            orders.chunk_by(|a, b| a.exchange() == b.exchange() && a.level().2 == b.level().2);
+        for run in runs {
+            let exchange = run[0].exchange();
+            let side = run[0].level().2;
+            let _span = tracing::info_span!(
+                "write_levels",
+                exchange = exchange.as_str(),
+                ?side,
+                levels = run.len()
+            )
+            .entered();
+            let side_levels = match side {
+                Side::Buy => &self.exchange_bids_price_level,
+                Side::Sell => &self.exchange_asks_price_level,
+            };
+            let levels = side_levels
+                .entry(exchange)
+                .or_insert_with(|| Arc::new(RwLock::new(SideLevels::new())))
+                .clone();
+            let mut guard = match levels.write() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            for order in run {
+                let received_at = order.received_at();
+                self.record_latency(exchange, order.exchange_timestamp(), received_at);
+                let (price, quantity, _) = order.level();
+                // A snapshot starts from an empty side, dropping levels it no longer lists.
+                if order.mode() == UpdateMode::Replace {
+                    guard.clear();
+                }
+                let exchange_timestamp = Some(order.exchange_timestamp()).filter(|&ts| ts > 0);
+                let level = PriceLevel {
+                    quantity,
+                    updated_at: received_at,
+                    exchange_timestamp,
+                };
+                Self::apply_level(&mut guard, price, level, self.quantity_mode);
             }
-            ExchangePrice::Bitstamp {
-                price,
-                quantity,
-                exchange_timestamp,
-                received_at,
-                side,
-            } => {
-                let _ = (exchange_timestamp, received_at);
-                self.update_price_level_for_exchange(Exchange::Bitstamp, price, quantity, side);
+            if let Some(depth) = self.published_depth {
+                Self::trim(&mut guard, side, depth);
             }
+            self.publish(exchange, side, &guard);
+        }
+        self.version
+            .fetch_add(orders.len() as u64, Ordering::Release);
+        if self.validate_updates {
+            self.validate_updated(orders);
         }
     }
 
-    /// Helper to update the appropriate price level map for a given exchange and side.
-    fn update_price_level_for_exchange(
-        &self,
-        exchange: Exchange,
-        price: u64,
-        quantity: u64,
-        side: Side,
-    ) {
-        let _span = tracing::info_span!("write_level").entered();
-        match side {
-            Side::Buy => {
-                let price_level = self
-                    .exchange_bids_price_level
-                    .entry(exchange)
-                    .or_insert_with(|| Arc::new(RwLock::new(BTreeMap::new())));
-                let mut guard = match (*price_level.value()).write() {
+    /// `Ok` unless `exchange`'s own best bid is at or above its own best ask. Venues are
+    /// checked separately: one venue's bid above another's ask is an arbitrage, not an error.
+    pub fn validate_exchange(&self, exchange: Exchange) -> Result<(), BookError> {
+        let view = self.view();
+        // Checks the book as the venue sent it, dust included.
+        let top = |side| Self::exchange_levels(side, exchange, 1, all).pop();
+        let (Some((bid, _)), Some((ask, _))) = (top(view.bids()), top(view.asks())) else {
+            return Ok(());
+        };
+        match bid.cmp(&ask) {
+            std::cmp::Ordering::Less => Ok(()),
+            std::cmp::Ordering::Equal => Err(BookError::Locked { exchange, bid, ask }),
+            std::cmp::Ordering::Greater => Err(BookError::Crossed { exchange, bid, ask }),
+        }
+    }
+
+    /// Validate every venue in `orders`, logging violations. A batch that ends partway through
+    /// a venue's message can see one side updated before the other, so an isolated warning
+    /// may be transient; one that repeats is not.
+    fn validate_updated(&self, orders: &[ExchangePrice]) {
+        let mut exchanges: Vec<Exchange> = orders.iter().map(|order| order.exchange()).collect();
+        exchanges.sort_unstable();
+        exchanges.dedup();
+        for exchange in exchanges {
+            if let Err(e) = self.validate_exchange(exchange) {
+                tracing::warn!(symbol = %self.symbol, error = %e, "invalid exchange book");
+            }
+        }
+    }
+
+    /// Number of updates applied so far; unchanged means the book has not changed.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Record `received_at - exchange_timestamp` when both are known.
+    /// Clock skew that puts the exchange ahead of us counts as zero latency.
+    fn record_latency(&self, exchange: Exchange, exchange_timestamp: u64, received_at: u64) {
+        if exchange_timestamp == 0 || received_at == 0 {
+            return;
+        }
+        self.latency
+            .entry(exchange)
+            .or_default()
+            .record(received_at.saturating_sub(exchange_timestamp));
+    }
+
+    /// Latest, rolling-mean and max feed latency for `exchange`, if any update carried
+    /// both timestamps.
+    pub fn latency_stats(&self, exchange: Exchange) -> Option<LatencyStats> {
+        self.latency.get(&exchange)?.stats()
+    }
+
+    /// Publish `exchange`'s `side` as `levels` now stands. Called with the venue's write lock
+    /// held, so publishes for one venue and side never overtake each other; the other venues
+    /// and the other side are left alone.
+    fn publish(&self, exchange: Exchange, side: Side, levels: &SideLevels) {
+        let published = match side {
+            Side::Buy => &self.published_bids,
+            Side::Sell => &self.published_asks,
+        };
+        published[exchange as usize].store(Arc::new(levels.clone()));
+    }
+
+    /// The current published levels of every venue, loaded a side at a time as the read
+    /// needs them; cheap to take and never blocked by writers.
+    fn view(&self) -> BookView<'_> {
+        BookView {
+            book: self,
+            bids: OnceCell::new(),
+            asks: OnceCell::new(),
+        }
+    }
+
+    /// Set one level in an exchange's side map to `level`, whose quantity is its new size
+    /// (or, with `QuantityMode::Additive`, what to add to the resting size).
+    fn apply_level(levels: &mut SideLevels, price: u64, mut level: PriceLevel, mode: QuantityMode) {
+        // Updates built without timestamps (tests, replays) count as fresh.
+        if level.updated_at == 0 {
+            level.updated_at = current_timestamp_ms();
+        }
+        if level.quantity == 0 {
+            // A zero quantity means the exchange cleared this level.
+            levels.remove(&price);
+            return;
+        }
+        if mode == QuantityMode::Additive
+            && let Some(resting) = levels.get(&price)
+        {
+            level.quantity = level.quantity.saturating_add(resting.quantity);
+        }
+        levels.insert(price, level);
+    }
+
+    /// Drop the levels furthest from the best on `side` until at most `depth` remain. Between
+    /// runs a side holds at most `depth` levels, so this pops what the run itself pushed past
+    /// the cap rather than scanning the side.
+    fn trim(levels: &mut SideLevels, side: Side, depth: usize) {
+        while levels.len() > depth {
+            let worst = match side {
+                Side::Buy => levels.get_min(),
+                Side::Sell => levels.get_max(),
+            };
+            let Some(&(price, _)) = worst else {
+                break;
+            };
+            levels.remove(&price);
+        }
+    }
+
+    /// Empty `exchange`'s bids and asks on demand, e.g. to wipe a venue before resyncing it
+    /// from scratch, so nothing from before lingers. Its maps stay allocated for the levels
+    /// to come.
+    pub fn clear_exchange(&self, exchange: Exchange) {
+        self.clear_where(|venue| venue == exchange);
+    }
+
+    /// Empty every exchange's bids and asks, keeping the maps allocated.
+    pub fn clear_all(&self) {
+        self.clear_where(|_| true);
+    }
+
+    /// Clear both sides of each exchange `matches` picks and republish them.
+    fn clear_where(&self, matches: impl Fn(Exchange) -> bool) {
+        for (side, side_levels) in [
+            (Side::Buy, &self.exchange_bids_price_level),
+            (Side::Sell, &self.exchange_asks_price_level),
+        ] {
+            for entry in side_levels.iter().filter(|entry| matches(*entry.key())) {
+                let mut guard = match entry.value().write() {
                     Ok(guard) => guard,
                     Err(poisoned) => poisoned.into_inner(),
                 };
-                let entry = guard.entry(price).or_insert(0);
-                *entry += quantity;
-
-                // We can compute best bid on demand later by inspecting this BTreeMap.
+                guard.clear();
+                self.publish(*entry.key(), side, &guard);
             }
-            Side::Sell => {
-                let price_level = self
-                    .exchange_asks_price_level
-                    .entry(exchange)
-                    .or_insert_with(|| Arc::new(RwLock::new(BTreeMap::new())));
-                let mut guard = match (*price_level.value()).write() {
+        }
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    /// `evict_stale` with the book's `stale_after`; does nothing when that is `None`.
+    pub fn evict_expired(&self) -> usize {
+        self.stale_after
+            .map_or(0, |max_age| self.evict_stale(max_age))
+    }
+
+    /// Drop levels on any exchange and side that have not been updated within `max_age`,
+    /// for venues that go quiet without sending removals. Returns the number removed.
+    pub fn evict_stale(&self, max_age: Duration) -> usize {
+        let cutoff = current_timestamp_ms().saturating_sub(max_age.as_millis() as u64);
+        self.evict_updated_before(cutoff)
+    }
+
+    /// Remove every level whose `updated_at` is before `cutoff_ms`.
+    fn evict_updated_before(&self, cutoff_ms: u64) -> usize {
+        let mut removed = 0;
+        for (side, side_levels) in [
+            (Side::Buy, &self.exchange_bids_price_level),
+            (Side::Sell, &self.exchange_asks_price_level),
+        ] {
+            for entry in side_levels.iter() {
+                let mut guard = match entry.value().write() {
                     Ok(guard) => guard,
                     Err(poisoned) => poisoned.into_inner(),
                 };
-                let entry = guard.entry(price).or_insert(0);
-                *entry += quantity;
-
-                // We can compute best ask on demand later by inspecting this BTreeMap.
+                let stale: Vec<u64> = guard
+                    .iter()
+                    .filter(|(_, level)| level.updated_at < cutoff_ms)
+                    .map(|(&price, _)| price)
+                    .collect();
+                if !stale.is_empty() {
+                    for price in &stale {
+                        guard.remove(price);
+                    }
+                    removed += stale.len();
+                    self.publish(*entry.key(), side, &guard);
+                }
             }
         }
+        if removed > 0 {
+            self.version.fetch_add(1, Ordering::Release);
+        }
+        removed
     }
 
     /// Top 10 bid levels from the combined book (exchange, price, quantity), sorted best-first.
     pub fn top_bids_all_exchanges(&self) -> Vec<(Exchange, u64, u64)> {
-        let mut levels: Vec<(Exchange, u64, u64)> = Vec::new();
-
-        // Collect all bid levels from all exchanges.
-        for entry in self.exchange_bids_price_level.iter() {
-            let exchange = *entry.key();
-            let map_arc = entry.value();
-            if let Ok(guard) = map_arc.read() {
-                for (&price, &qty) in guard.iter() {
-                    if qty == 0 {
-                        continue;
-                    }
-                    levels.push((exchange, price, qty));
+        self.top_bids_n(DEFAULT_DEPTH)
+    }
+
+    /// Top 10 ask levels from the combined book (exchange, price, quantity), sorted best-first.
+    pub fn top_asks_all_exchanges(&self) -> Vec<(Exchange, u64, u64)> {
+        self.top_asks_n(DEFAULT_DEPTH)
+    }
+
+    /// Top `n` bid levels from the combined book (exchange, price, quantity), sorted best-first.
+    pub fn top_bids_n(&self, n: usize) -> Vec<(Exchange, u64, u64)> {
+        let view = self.view();
+        Self::merge_top_n(view.bids(), n, self.shown(&view), untimed)
+    }
+
+    /// Top `n` ask levels from the combined book (exchange, price, quantity), sorted best-first.
+    pub fn top_asks_n(&self, n: usize) -> Vec<(Exchange, u64, u64)> {
+        let view = self.view();
+        Self::merge_top_n(view.asks(), n, self.shown(&view), untimed)
+    }
+
+    /// `top_bids_n` with the exchange timestamp of the update that set each level.
+    pub fn top_bids_n_timed(&self, n: usize) -> Vec<((Exchange, u64, u64), Option<u64>)> {
+        let view = self.view();
+        Self::merge_top_n(view.bids(), n, self.shown(&view), timed)
+    }
+
+    /// `top_asks_n` with the exchange timestamp of the update that set each level.
+    pub fn top_asks_n_timed(&self, n: usize) -> Vec<((Exchange, u64, u64), Option<u64>)> {
+        let view = self.view();
+        Self::merge_top_n(view.asks(), n, self.shown(&view), timed)
+    }
+
+    /// K-way merge of the venues' best-first levels, stopping after `n`: each step takes the
+    /// best head among the venues, so only O(n · venues) levels are looked at however deep
+    /// the books are. Levels `shown` rejects are skipped, and `out` shapes each merged level.
+    fn merge_top_n<T>(
+        side: &SideView,
+        n: usize,
+        shown: impl Fn(&PublishedLevel) -> bool,
+        out: impl Fn(Exchange, PublishedLevel) -> T,
+    ) -> Vec<T> {
+        let better_price = side.better_price();
+        let mut heads: Vec<(Exchange, Peekable<BestFirst>)> = side
+            .venues()
+            .map(|(exchange, levels)| (exchange, levels.peekable()))
+            .collect();
+        let available: usize = heads.iter().map(|(_, levels)| levels.len()).sum();
+        let mut merged = Vec::with_capacity(n.min(available));
+
+        while merged.len() < n {
+            let mut best: Option<(usize, (Exchange, u64, u64))> = None;
+            for (i, (exchange, levels)) in heads.iter_mut().enumerate() {
+                while levels.next_if(|level| !shown(level)).is_some() {}
+                let Some(&(price, quantity, _)) = levels.peek() else {
+                    continue;
+                };
+                let candidate = (*exchange, price, quantity);
+                if best
+                    .is_none_or(|(_, current)| Self::ranks_before(candidate, current, better_price))
+                {
+                    best = Some((i, candidate));
                 }
             }
+            let Some((i, (exchange, _, _))) = best else {
+                break;
+            };
+            let (_, levels) = &mut heads[i];
+            merged.extend(levels.next().map(|level| out(exchange, level)));
         }
+        merged
+    }
 
-        // Sort by price descending and take up to 10.
-        levels.sort_by(|a, b| b.1.cmp(&a.1));
-        if levels.len() > 10 {
-            levels.truncate(10);
+    /// Whether level `a` comes before `b` on a side ordered by `better_price`. Same-price
+    /// levels go larger quantity first, then by exchange name, so identical books always
+    /// list their levels identically.
+    fn ranks_before(
+        a: (Exchange, u64, u64),
+        b: (Exchange, u64, u64),
+        better_price: impl Fn(u64, u64) -> bool,
+    ) -> bool {
+        better_price(a.1, b.1)
+            || (a.1 == b.1 && (a.2 > b.2 || (a.2 == b.2 && a.0.as_str() < b.0.as_str())))
+    }
+
+    /// Top `n` bid prices with quantity summed across exchanges, as (price, total quantity),
+    /// best-first. Unlike `top_bids_n`, venues quoting the same price share one row.
+    pub fn top_bids_merged(&self, n: usize) -> Vec<(u64, u64)> {
+        let view = self.view();
+        Self::merged_levels(view.bids(), self.shown(&view))
+            .into_iter()
+            .rev()
+            .take(n)
+            .collect()
+    }
+
+    /// Top `n` ask prices with quantity summed across exchanges, as (price, total quantity),
+    /// best-first.
+    pub fn top_asks_merged(&self, n: usize) -> Vec<(u64, u64)> {
+        let view = self.view();
+        Self::merged_levels(view.asks(), self.shown(&view))
+            .into_iter()
+            .take(n)
+            .collect()
+    }
+
+    /// Total quantity per price across all exchanges on one side, over the levels `shown` keeps.
+    fn merged_levels(
+        side: &SideView,
+        shown: impl Fn(&PublishedLevel) -> bool,
+    ) -> BTreeMap<u64, u64> {
+        let mut merged: BTreeMap<u64, u64> = BTreeMap::new();
+        for (_, levels) in side.venues() {
+            for level @ (price, quantity, _) in levels {
+                if quantity > 0 && shown(&level) {
+                    let total = merged.entry(price).or_default();
+                    *total = total.saturating_add(quantity);
+                }
+            }
         }
-        levels
+        merged
     }
 
-    /// Top 10 ask levels from the combined book (exchange, price, quantity), sorted best-first.
-    pub fn top_asks_all_exchanges(&self) -> Vec<(Exchange, u64, u64)> {
-        let mut levels: Vec<(Exchange, u64, u64)> = Vec::new();
-
-        // Collect all ask levels from all exchanges.
-        for entry in self.exchange_asks_price_level.iter() {
-            let exchange = *entry.key();
-            let map_arc = entry.value();
-            if let Ok(guard) = map_arc.read() {
-                for (&price, &qty) in guard.iter() {
-                    if qty == 0 {
-                        continue;
-                    }
-                    levels.push((exchange, price, qty));
+    /// Highest bid across all exchanges as (exchange, price, quantity).
+    /// Ties on price go to the larger quantity, then to the exchange name (as in `top_bids_n`).
+    pub fn best_bid(&self) -> Option<(Exchange, u64, u64)> {
+        self.best(&self.view(), Side::Buy)
+    }
+
+    /// Lowest ask across all exchanges as (exchange, price, quantity).
+    /// Ties on price go to the larger quantity, then to the exchange name (as in `top_asks_n`).
+    pub fn best_ask(&self) -> Option<(Exchange, u64, u64)> {
+        self.best(&self.view(), Side::Sell)
+    }
+
+    /// Combined best shown level on `side` of `view`: each venue's first shown level, compared.
+    /// That is usually the venue's top level, read without walking its map.
+    fn best(&self, view: &BookView, side: Side) -> Option<(Exchange, u64, u64)> {
+        let levels = view.side(side);
+        let shown = self.shown(view);
+        let better_price = levels.better_price();
+        Exchange::ALL
+            .into_iter()
+            .filter_map(|exchange| {
+                let top = levels.top(exchange)?;
+                let (price, quantity, _) = if shown(&top) {
+                    top
+                } else {
+                    levels.levels(exchange).find(|level| shown(level))?
+                };
+                Some((exchange, price, quantity))
+            })
+            .reduce(|best, candidate| {
+                if Self::ranks_before(candidate, best, better_price) {
+                    candidate
+                } else {
+                    best
                 }
+            })
+    }
+
+    /// Current dust threshold; see `set_min_quantity`.
+    pub fn min_quantity(&self) -> u64 {
+        self.min_quantity.load(Ordering::Relaxed)
+    }
+
+    /// Hide levels smaller than `quantity` smallest units from reads (`top_*`, best bid/ask,
+    /// spread and snapshots); 0 shows every level. The levels themselves are kept, so
+    /// lowering the threshold shows them again.
+    pub fn set_min_quantity(&self, quantity: u64) {
+        self.min_quantity.store(quantity, Ordering::Relaxed);
+    }
+
+    /// Filter for reads from `view`: whether a published level clears the dust threshold and
+    /// lies within the price band.
+    fn shown(&self, view: &BookView) -> impl Fn(&PublishedLevel) -> bool + use<> {
+        let min_quantity = self.min_quantity();
+        let (low, high) = self.price_band(view, min_quantity).unwrap_or((0, u64::MAX));
+        move |&(price, quantity, _)| quantity >= min_quantity && (low..=high).contains(&price)
+    }
+
+    /// Lowest and highest price within `max_deviation_bps` of the reference mid: the median
+    /// of the mids of venues quoting both sides, so one venue's bad best price moves it little.
+    /// `None` (no band) when the setting is off or no venue quotes both sides.
+    fn price_band(&self, view: &BookView, min_quantity: u64) -> Option<(u64, u64)> {
+        let bps = self.max_deviation_bps?;
+        let first = |side: &SideView, exchange| {
+            side.levels(exchange)
+                .find(|&(_, quantity, _)| quantity >= min_quantity)
+                .map(|(price, _, _)| price as f64)
+        };
+        let mut mids: Vec<f64> = Exchange::ALL
+            .into_iter()
+            .filter_map(|exchange| {
+                Some((first(view.bids(), exchange)? + first(view.asks(), exchange)?) / 2.0)
+            })
+            .collect();
+        if mids.is_empty() {
+            return None;
+        }
+        mids.sort_by(f64::total_cmp);
+        let half = mids.len() / 2;
+        let mid = if mids.len() % 2 == 1 {
+            mids[half]
+        } else {
+            (mids[half - 1] + mids[half]) / 2.0
+        };
+        let deviation = mid * bps as f64 / 10_000.0;
+        Some((
+            (mid - deviation).max(0.0).ceil() as u64,
+            (mid + deviation).floor() as u64,
+        ))
+    }
+
+    /// `exchange`'s own top `n` bids as (price, quantity), best first, leaving the other
+    /// venues out. Empty when the exchange has no bids.
+    pub fn top_bids_for_exchange(&self, exchange: Exchange, n: usize) -> Vec<(u64, u64)> {
+        let view = self.view();
+        Self::exchange_levels(view.bids(), exchange, n, self.shown(&view))
+    }
+
+    /// `exchange`'s own top `n` asks as (price, quantity), best first.
+    pub fn top_asks_for_exchange(&self, exchange: Exchange, n: usize) -> Vec<(u64, u64)> {
+        let view = self.view();
+        Self::exchange_levels(view.asks(), exchange, n, self.shown(&view))
+    }
+
+    /// The first `n` of one venue's published levels on one side that `shown` keeps.
+    fn exchange_levels(
+        side: &SideView,
+        exchange: Exchange,
+        n: usize,
+        shown: impl Fn(&PublishedLevel) -> bool,
+    ) -> Vec<(u64, u64)> {
+        side.levels(exchange)
+            .filter(|level| shown(level))
+            .take(n)
+            .map(|(price, quantity, _)| (price, quantity))
+            .collect()
+    }
+
+    /// Each exchange's own best bid and best ask, ordered by exchange.
+    /// Exchanges with no levels on either side are left out.
+    pub fn exchange_tops(&self) -> Vec<ExchangeTopOfBook> {
+        // One view for both sides, so a venue's bid and ask come from the same moment.
+        let view = self.view();
+        let shown = self.shown(&view);
+        let top = |side, exchange| Self::exchange_levels(side, exchange, 1, &shown).pop();
+        Exchange::ALL
+            .iter()
+            .filter_map(|&exchange| {
+                let bid = top(view.bids(), exchange);
+                let ask = top(view.asks(), exchange);
+                (bid.is_some() || ask.is_some()).then_some(ExchangeTopOfBook { exchange, bid, ask })
+            })
+            .collect()
+    }
+
+    /// Volume-weighted average price (price units, rounded) over the top `n` combined levels of `side`,
+    /// i.e. the average fill price of a market order sweeping those levels.
+    /// `Side::Buy` walks the bids, `Side::Sell` the asks. Returns `None` if the side is empty.
+    pub fn vwap(&self, side: Side, n: usize) -> Option<u64> {
+        let levels = match side {
+            Side::Buy => self.top_bids_n(n),
+            Side::Sell => self.top_asks_n(n),
+        };
+
+        let (notional, volume) =
+            levels
+                .iter()
+                .fold((0u128, 0u128), |(notional, volume), &(_, price, qty)| {
+                    (notional + price as u128 * qty as u128, volume + qty as u128)
+                });
+        if volume == 0 {
+            return None;
+        }
+        Some(((notional + volume / 2) / volume) as u64)
+    }
+
+    /// Quantity (smallest units) a limit order on `side` at `limit_price` could fill right away:
+    /// for `Side::Buy` every ask priced at or below the limit, for `Side::Sell` every bid at or
+    /// above it. The counterpart of `fill_cost`, which asks what a given quantity would cost.
+    /// 0 when nothing is inside the limit.
+    pub fn liquidity_within(&self, side: Side, limit_price: u64) -> u64 {
+        let view = self.view();
+        let shown = self.shown(&view);
+        let (levels, inside): (_, fn(u64, u64) -> bool) = match side {
+            Side::Buy => (view.asks(), |price, limit| price <= limit),
+            Side::Sell => (view.bids(), |price, limit| price >= limit),
+        };
+        // Each venue's levels are best first, so its walk stops at the first one past the limit.
+        let total: u128 = levels
+            .venues()
+            .flat_map(|(_, levels)| levels.take_while(|&(price, _, _)| inside(price, limit_price)))
+            .filter(|level| shown(level))
+            .map(|(_, quantity, _)| quantity as u128)
+            .sum();
+        total.min(u64::MAX as u128) as u64
+    }
+
+    /// Simulate a market order of `target_qty` (smallest units) against the combined book.
+    /// `Side::Buy` consumes asks from the lowest price up, `Side::Sell` consumes bids from the
+    /// highest down. If the book runs out, the estimate covers what was fillable and
+    /// `complete` is false. Returns `None` if nothing could be filled.
+    pub fn fill_cost(&self, side: Side, target_qty: u64) -> Option<FillEstimate> {
+        let levels = match side {
+            Side::Buy => self.top_asks_n(usize::MAX),
+            Side::Sell => self.top_bids_n(usize::MAX),
+        };
+
+        let mut remaining = target_qty;
+        let mut notional: u128 = 0;
+        for (_, price, qty) in levels {
+            if remaining == 0 {
+                break;
             }
+            let take = qty.min(remaining);
+            notional += price as u128 * take as u128;
+            remaining -= take;
         }
 
-        // Sort by price ascending and take up to 10.
-        levels.sort_by(|a, b| a.1.cmp(&b.1));
-        if levels.len() > 10 {
-            levels.truncate(10);
+        let filled_qty = target_qty - remaining;
+        if filled_qty == 0 {
+            return None;
         }
-        levels
+        let scale = 10u128.pow(self.base_decimals);
+        Some(FillEstimate {
+            filled_qty,
+            total_cost: ((notional + scale / 2) / scale) as u64,
+            avg_price: ((notional + filled_qty as u128 / 2) / filled_qty as u128) as u64,
+            complete: remaining == 0,
+        })
     }
 
-    /// Spread across all exchanges: best ask price - best bid price (in cents)
-    /// using the combined top-of-book from all exchanges.
-    /// Returns `None` only if either side is missing.
+    /// Volume imbalance over the top `depth` combined levels of each side:
+    /// `(bid_volume - ask_volume) / (bid_volume + ask_volume)`, in [-1, 1].
+    /// Positive values mean more resting bids. Returns `None` when both sides are empty.
+    pub fn imbalance(&self, depth: usize) -> Option<f64> {
+        let volume = |levels: Vec<(Exchange, u64, u64)>| {
+            levels.iter().map(|&(_, _, qty)| qty as f64).sum::<f64>()
+        };
+        let bid_volume = volume(self.top_bids_n(depth));
+        let ask_volume = volume(self.top_asks_n(depth));
+
+        let total = bid_volume + ask_volume;
+        if total == 0.0 {
+            return None;
+        }
+        Some((bid_volume - ask_volume) / total)
+    }
+
+    /// Spread across all exchanges: best ask price - best bid price (in price units)
+    /// using the combined top-of-book from all exchanges, clamped at 0.
+    /// Returns `None` if either side is missing or has fewer than `min_exchanges` venues.
+    /// Use `spread` to tell a crossed book apart from a zero spread.
     pub fn spread_all_exchanges(&self) -> Option<u64> {
-        let top_bids = self.top_bids_all_exchanges();
-        let top_asks = self.top_asks_all_exchanges();
+        self.spread().map(|spread| spread.value.max(0) as u64)
+    }
 
-        let (_, best_bid_price, _) = top_bids.first().copied()?;
-        let (_, best_ask_price, _) = top_asks.first().copied()?;
+    /// `spread_all_exchanges` with a per-call venue requirement in place of `min_exchanges`,
+    /// e.g. 2 for arbitrage checks on a book that otherwise reports single-venue spreads.
+    pub fn spread_with_min_exchanges(&self, min_exchanges: usize) -> Option<u64> {
+        self.spread_requiring(min_exchanges)
+            .map(|(_, _, spread)| spread.value.max(0) as u64)
+    }
 
-        // Always return a numeric spread when both sides exist, even if crossed/locked.
-        Some(best_ask_price.saturating_sub(best_bid_price))
+    /// Signed spread across all exchanges; same availability rules as `spread_all_exchanges`.
+    pub fn spread(&self) -> Option<Spread> {
+        self.spread_requiring(self.min_exchanges)
+            .map(|(_, _, spread)| spread)
     }
-    /// Print a JSON summary of the current combined book: spread, top 10 bids, top 10 asks.
-    pub fn print_snapshot_json(&self) {
-        let top_bids = self.top_bids_all_exchanges();
-        let top_asks = self.top_asks_all_exchanges();
-        let spread_cents = self.spread_all_exchanges();
 
-        let bids_json: Vec<_> = top_bids
-            .into_iter()
-            .map(|(exchange, price_cents, qty_smallest)| {
-                let exchange_str = match exchange {
-                    Exchange::Binance => "binance",
-                    Exchange::Bitstamp => "bitstamp",
-                };
-                json!({
-                    "exchange": exchange_str,
-                    "price": price_cents as f64 / 100.0,
-                    "amount": qty_smallest as f64 / 1e8,
+    /// `spread_all_exchanges` together with where each side's best price comes from:
+    /// (bid exchange, ask exchange, spread in price units). Different venues on the two
+    /// sides is the cross-exchange arbitrage case.
+    pub fn spread_by_exchange(&self) -> Option<(Exchange, Exchange, u64)> {
+        self.spread_requiring(self.min_exchanges)
+            .map(|(bid_exchange, ask_exchange, spread)| {
+                (bid_exchange, ask_exchange, spread.value.max(0) as u64)
+            })
+    }
+
+    /// Signed spread with the best bid's and best ask's exchanges, or `None` unless each
+    /// side has levels from `min_exchanges` venues. Both come from one published view.
+    fn spread_requiring(&self, min_exchanges: usize) -> Option<(Exchange, Exchange, Spread)> {
+        let ((bid_exchange, best_bid_price, _), (ask_exchange, best_ask_price, _)) =
+            self.best_requiring(min_exchanges)?;
+
+        let value = best_ask_price as i64 - best_bid_price as i64;
+        let spread = Spread {
+            value,
+            crossed: value < 0,
+        };
+        Some((bid_exchange, ask_exchange, spread))
+    }
+
+    /// Combined best bid and best ask from one published view, or `None` unless each side has
+    /// levels from `min_exchanges` venues.
+    fn best_requiring(&self, min_exchanges: usize) -> Option<BestBidAsk> {
+        let view = self.view();
+        let shown = self.shown(&view);
+        if view.exchanges_with_levels(Side::Buy, &shown) < min_exchanges
+            || view.exchanges_with_levels(Side::Sell, &shown) < min_exchanges
+        {
+            return None;
+        }
+        Some((self.best(&view, Side::Buy)?, self.best(&view, Side::Sell)?))
+    }
+
+    /// Whether the combined best ask is below the combined best bid.
+    pub fn is_crossed(&self) -> bool {
+        self.spread().is_some_and(|spread| spread.crossed)
+    }
+
+    /// Spread in basis points of the mid price. The spread (clamped at 0, as in
+    /// `spread_all_exchanges`) and the mid come from the same best bid and ask, so both are
+    /// `None` together when either side has fewer than `min_exchanges` venues.
+    pub fn spread_bps(&self) -> Option<f64> {
+        let ((_, bid, _), (_, ask, _)) = self.best_requiring(self.min_exchanges)?;
+        let mid = (bid as f64 + ask as f64) / 2.0;
+        if mid <= 0.0 {
+            return None;
+        }
+        Some(ask.saturating_sub(bid) as f64 / mid * 10_000.0)
+    }
+
+    /// Notional (quote units, e.g. USDT) resting within `pct` percent of the mid price,
+    /// summed over both sides and all exchanges. `pct = 1.0` means ±1%.
+    pub fn depth_within_pct(&self, pct: f64) -> Option<f64> {
+        let view = self.view();
+        let mid = self.mid_price_f64(&view)?;
+        let low = (mid * (1.0 - pct / 100.0)).max(0.0).ceil() as u64;
+        let high = (mid * (1.0 + pct / 100.0)).floor() as u64;
+
+        // Summed exactly in stored units and scaled once at the end.
+        let notional = |(price, qty, _): PublishedLevel| price as u128 * qty as u128;
+        // Only shown levels count, as in snapshots. Published levels are best first, so each
+        // venue's walk stops at the band edge.
+        let shown = self.shown(&view);
+        let mut total: u128 = 0;
+        for (_, levels) in view.bids().venues() {
+            total += levels
+                .take_while(|&(p, _, _)| p >= low)
+                .filter(&shown)
+                .map(notional)
+                .sum::<u128>();
+        }
+        for (_, levels) in view.asks().venues() {
+            total += levels
+                .take_while(|&(p, _, _)| p <= high)
+                .filter(&shown)
+                .map(notional)
+                .sum::<u128>();
+        }
+        Some(total as f64 / (self.price_scale() * self.quantity_scale()))
+    }
+
+    /// Single-number market quality: deeper books and tighter spreads score higher.
+    ///
+    /// `score = w_depth * ln(1 + depth_within_pct(pct)) - w_spread * ln(1 + spread_bps)`,
+    /// with weights from `liquidity_weights`. Logs keep both terms on a comparable scale.
+    /// Returns `None` when either input is unavailable.
+    pub fn liquidity_score(&self, pct: f64) -> Option<f64> {
+        let depth = self.depth_within_pct(pct)?;
+        let spread_bps = self.spread_bps()?;
+        let weights = self.liquidity_weights;
+        Some(weights.depth * depth.ln_1p() - weights.spread * spread_bps.ln_1p())
+    }
+
+    /// Midpoint of the combined best bid and best ask in price units, rounded half up.
+    /// `None` when either side is empty.
+    pub fn mid_price(&self) -> Option<u64> {
+        let (_, best_bid, _) = self.best_bid()?;
+        let (_, best_ask, _) = self.best_ask()?;
+        Some((best_bid as u128 + best_ask as u128).div_ceil(2) as u64)
+    }
+
+    /// Size-weighted mid, `(bid_px·ask_qty + ask_px·bid_qty) / (bid_qty + ask_qty)`, in price
+    /// units rounded half up: it leans towards the ask when the best bid is larger, and the
+    /// other way round. `None` when either side is empty.
+    pub fn micro_price(&self) -> Option<u64> {
+        let (_, bid_px, bid_qty) = self.best_bid()?;
+        let (_, ask_px, ask_qty) = self.best_ask()?;
+        let weighted = bid_px as u128 * ask_qty as u128 + ask_px as u128 * bid_qty as u128;
+        let total = bid_qty as u128 + ask_qty as u128;
+        Some(((weighted + total / 2) / total) as u64)
+    }
+
+    /// Midpoint of `view`'s combined best bid and best ask, in (fractional) price units.
+    fn mid_price_f64(&self, view: &BookView) -> Option<f64> {
+        let (_, best_bid, _) = self.best(view, Side::Buy)?;
+        let (_, best_ask, _) = self.best(view, Side::Sell)?;
+        Some((best_bid as f64 + best_ask as f64) / 2.0)
+    }
+
+    /// Divisor turning stored smallest-unit quantities into whole base-asset units.
+    pub fn quantity_scale(&self) -> f64 {
+        10f64.powi(self.base_decimals as i32)
+    }
+
+    /// Divisor turning stored prices into whole quote-asset units.
+    pub fn price_scale(&self) -> f64 {
+        10f64.powi(self.price_decimals as i32)
+    }
+
+    /// A stored price (or price difference) in whole quote-asset units.
+    pub fn price_value(&self, price: u64) -> f64 {
+        price as f64 / self.price_scale()
+    }
+
+    /// A stored quantity in whole base-asset units.
+    pub fn quantity_value(&self, quantity: u64) -> f64 {
+        quantity as f64 / self.quantity_scale()
+    }
+
+    /// A stored price (or price difference) tagged for `output_format`.
+    pub fn price_units(&self, price: u64) -> Units {
+        Units {
+            raw: price,
+            decimals: self.price_decimals,
+            format: self.output_format,
+        }
+    }
+
+    /// A stored quantity tagged for `output_format`.
+    pub fn quantity_units(&self, quantity: u64) -> Units {
+        Units {
+            raw: quantity,
+            decimals: self.base_decimals,
+            format: self.output_format,
+        }
+    }
+
+    /// `exchange` tagged for `exchange_format`.
+    pub fn exchange_out(&self, exchange: Exchange) -> ExchangeOut {
+        ExchangeOut {
+            venue: exchange,
+            format: self.exchange_format,
+        }
+    }
+
+    /// A `(exchange, price, quantity)` level from `top_bids_n`/`top_asks_n`, without an
+    /// exchange timestamp. Snapshots and gRPC summaries both convert through here.
+    pub fn level_out(&self, (exchange, price, quantity): (Exchange, u64, u64)) -> LevelOut {
+        LevelOut {
+            exchange: self.exchange_out(exchange),
+            price: self.price_units(price),
+            amount: self.quantity_units(quantity),
+            exchange_timestamp: None,
+        }
+    }
+
+    /// Print a JSON summary of the current combined book: spread, top `depth` bids and asks.
+    pub fn print_snapshot_json(&self, depth: usize) {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&self.snapshot(depth)).unwrap()
+        );
+    }
+
+    /// Typed summary of the current combined book with `depth` levels per side.
+    pub fn snapshot(&self, depth: usize) -> BookSnapshot {
+        let spread = self.spread();
+        let levels = |levels: Vec<((Exchange, u64, u64), Option<u64>)>| -> Vec<LevelOut> {
+            levels
+                .into_iter()
+                .map(|(level, exchange_timestamp)| LevelOut {
+                    exchange_timestamp,
+                    ..self.level_out(level)
                 })
+                .collect()
+        };
+
+        BookSnapshot {
+            symbol: self.symbol.clone(),
+            generated_at: current_timestamp_ms(),
+            spread: spread.map(|s| self.price_units(s.value.max(0) as u64)),
+            crossed: spread.is_some_and(|s| s.crossed),
+            liquidity_score: self.liquidity_score(SNAPSHOT_LIQUIDITY_PCT),
+            latency_ms: self
+                .latency
+                .iter()
+                .filter_map(|entry| Some((self.exchange_out(*entry.key()), entry.value().stats()?)))
+                .collect(),
+            asks: levels(self.top_asks_n_timed(depth)),
+            bids: levels(self.top_bids_n_timed(depth)),
+            scale: (self.output_format == OutputFormat::Integer).then_some(Scale {
+                price_decimals: self.price_decimals,
+                base_decimals: self.base_decimals,
+            }),
+            depth,
+        }
+    }
+
+    /// Changes since `previous`, comparing it with a fresh snapshot at the same depth.
+    /// Sending these instead of full snapshots keeps incremental consumers in sync.
+    pub fn diff(&self, previous: &BookSnapshot) -> BookDiff {
+        self.snapshot(previous.depth).changes_since(previous)
+    }
+}
+
+/// Apply a batch of updates to their books, keyed by pair, one `update_price_levels` call per
+/// run of updates for the same pair. Updates for a pair without a book are ignored.
+pub fn apply_updates(books: &HashMap<String, Arc<OrderBook>>, updates: &[ExchangePrice]) {
+    for run in updates.chunk_by(|a, b| a.pair() == b.pair()) {
+        if let Some(book) = books.get(run[0].pair()) {
+            book.update_price_levels(run);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ob() -> OrderBook {
+        OrderBook::new("TEST".to_string())
+    }
+
+    #[test]
+    fn builder_configures_every_knob() {
+        let ob = OrderBook::builder()
+            .symbol("ETH-USDC")
+            .depth(Some(2))
+            .update_mode(QuantityMode::Additive)
+            .stale_after(Some(Duration::from_secs(60)))
+            .decimals(9, 4)
+            .min_exchanges(2)
+            .liquidity_weights(LiquidityWeights {
+                depth: 2.0,
+                spread: 0.5,
             })
+            .output_format(OutputFormat::Decimal)
+            .build();
+        assert_eq!(ob.symbol, "ETH-USDC");
+        assert_eq!((ob.base_decimals, ob.price_decimals), (9, 4));
+        assert_eq!(ob.min_exchanges, 2);
+        assert_eq!(ob.liquidity_weights.depth, 2.0);
+        assert_eq!(ob.output_format, OutputFormat::Decimal);
+        assert_eq!(ob.published_depth, Some(2));
+        assert_eq!(ob.quantity_mode, QuantityMode::Additive);
+        assert_eq!(
+            OrderBook::new("BTC".into()).quantity_mode,
+            QuantityMode::Absolute
+        );
+
+        // Additive sizes accumulate at a price.
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 2, Side::Buy));
+        // Only two levels per venue are kept.
+        for price in [99, 98] {
+            ob.update_price_level(ExchangePrice::new(Exchange::Binance, price, 1, Side::Buy));
+        }
+        assert_eq!(
+            ob.top_bids_n(10),
+            [(Exchange::Binance, 100, 3), (Exchange::Binance, 99, 1)]
+        );
+
+        // A level last received two minutes ago is past the 60s window.
+        let now = current_timestamp_ms();
+        ob.update_price_level(
+            ExchangePrice::new(Exchange::Kraken, 101, 1, Side::Buy)
+                .with_timestamps(0, now - 120_000),
+        );
+        assert_eq!(ob.evict_expired(), 1);
+        assert_eq!(ob.best_bid(), Some((Exchange::Binance, 100, 3)));
+        assert_eq!(OrderBook::new("BTC-USDT".to_string()).evict_expired(), 0);
+    }
+
+    #[test]
+    fn apply_updates_routes_each_run_to_its_pair() {
+        let books: HashMap<String, Arc<OrderBook>> = ["BTC-USDT", "ETH-USDT"]
+            .into_iter()
+            .map(|pair| (pair.to_string(), Arc::new(OrderBook::new(pair.to_string()))))
             .collect();
+        let level = |pair: &str, price| {
+            ExchangePrice::new(Exchange::Okx, price, 1, Side::Buy).with_pair(pair.into())
+        };
+        apply_updates(
+            &books,
+            &[
+                level("BTC-USDT", 100),
+                level("BTC-USDT", 99),
+                level("ETH-USDT", 10),
+                level("SOL-USDT", 1),
+                level("BTC-USDT", 98),
+            ],
+        );
+        assert_eq!(books["BTC-USDT"].top_bids_n(10).len(), 3);
+        assert_eq!(books["ETH-USDT"].top_bids_n(10), [(Exchange::Okx, 10, 1)]);
+    }
+
+    #[test]
+    fn clearing_one_exchange_keeps_the_others() {
+        let ob = ob();
+        for exchange in [Exchange::Binance, Exchange::Kraken] {
+            ob.update_price_level(ExchangePrice::new(exchange, 100, 1, Side::Buy));
+            ob.update_price_level(ExchangePrice::new(exchange, 101, 1, Side::Sell));
+        }
+        let version = ob.version();
+
+        ob.clear_exchange(Exchange::Binance);
+        assert_eq!(ob.top_bids_n(10), [(Exchange::Kraken, 100, 1)]);
+        assert_eq!(ob.top_asks_n(10), [(Exchange::Kraken, 101, 1)]);
+        assert!(ob.version() > version);
+        // The venue's maps are kept, empty, for its next levels.
+        assert!(
+            ob.exchange_bids_price_level
+                .get(&Exchange::Binance)
+                .unwrap()
+                .read()
+                .unwrap()
+                .is_empty()
+        );
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 99, 2, Side::Buy));
+        assert_eq!(
+            ob.top_bids_n(10),
+            [(Exchange::Kraken, 100, 1), (Exchange::Binance, 99, 2)]
+        );
+
+        ob.clear_all();
+        assert!(ob.top_bids_n(10).is_empty() && ob.top_asks_n(10).is_empty());
+        assert_eq!(ob.exchange_bids_price_level.len(), 2);
+    }
+
+    #[test]
+    fn per_exchange_levels_leave_other_venues_out() {
+        let ob = ob();
+        for (exchange, offset) in [(Exchange::Binance, 0), (Exchange::Okx, 5)] {
+            for price in [100, 99, 98] {
+                ob.update_price_level(ExchangePrice::new(exchange, price + offset, 1, Side::Buy));
+            }
+            ob.update_price_level(ExchangePrice::new(exchange, 110 + offset, 2, Side::Sell));
+        }
+
+        assert_eq!(
+            ob.top_bids_for_exchange(Exchange::Binance, 2),
+            [(100, 1), (99, 1)]
+        );
+        assert_eq!(
+            ob.top_bids_for_exchange(Exchange::Okx, 10),
+            [(105, 1), (104, 1), (103, 1)]
+        );
+        assert_eq!(ob.top_asks_for_exchange(Exchange::Binance, 10), [(110, 2)]);
+        assert_eq!(ob.top_asks_for_exchange(Exchange::Okx, 10), [(115, 2)]);
+        assert!(ob.top_bids_for_exchange(Exchange::Kraken, 10).is_empty());
+
+        // Clearing one venue does not touch the other's view.
+        ob.clear_exchange(Exchange::Okx);
+        assert!(ob.top_asks_for_exchange(Exchange::Okx, 10).is_empty());
+        assert_eq!(ob.top_asks_for_exchange(Exchange::Binance, 10), [(110, 2)]);
+    }
+
+    #[test]
+    fn validate_exchange_flags_locked_and_crossed_venues() {
+        let ob = ob();
+        assert_eq!(ob.validate_exchange(Exchange::Binance), Ok(()));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        assert_eq!(ob.validate_exchange(Exchange::Binance), Ok(()));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 1, Side::Sell));
+        assert_eq!(ob.validate_exchange(Exchange::Binance), Ok(()));
+
+        // Another venue's ask below Binance's bid crosses the combined book, not Binance's.
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 99, 1, Side::Sell));
+        assert!(ob.is_crossed());
+        assert_eq!(ob.validate_exchange(Exchange::Binance), Ok(()));
+        assert_eq!(ob.validate_exchange(Exchange::Kraken), Ok(()));
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 1, Side::Buy));
+        assert_eq!(
+            ob.validate_exchange(Exchange::Binance),
+            Err(BookError::Locked {
+                exchange: Exchange::Binance,
+                bid: 101,
+                ask: 101,
+            })
+        );
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 98, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 97, 1, Side::Sell));
+        let err = ob.validate_exchange(Exchange::Kraken).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "kraken book is crossed: best bid 98 above best ask 97"
+        );
+    }
+
+    #[test]
+    fn top_of_book_only_keeps_each_venues_best_level() {
+        let ob = OrderBook::builder().top_of_book_only(true).build();
+        // A snapshot with depth: only the best level of each side is stored.
+        ob.update_price_levels(&[
+            ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy).with_mode(UpdateMode::Replace),
+            ExchangePrice::new(Exchange::Binance, 99, 2, Side::Buy),
+            ExchangePrice::new(Exchange::Binance, 101, 3, Side::Sell)
+                .with_mode(UpdateMode::Replace),
+            ExchangePrice::new(Exchange::Binance, 102, 4, Side::Sell),
+        ]);
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 98, 5, Side::Buy));
+        assert_eq!(
+            ob.top_bids_n(10),
+            [(Exchange::Binance, 100, 1), (Exchange::Okx, 98, 5)]
+        );
+        assert_eq!(ob.top_asks_n(10), [(Exchange::Binance, 101, 3)]);
+        assert_eq!(
+            ob.exchange_bids_price_level
+                .get(&Exchange::Binance)
+                .unwrap()
+                .read()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // A ticker moving its best down replaces the old best.
+        ob.update_price_level(
+            ExchangePrice::new(Exchange::Binance, 97, 6, Side::Buy).with_mode(UpdateMode::Replace),
+        );
+        assert_eq!(ob.best_bid(), Some((Exchange::Okx, 98, 5)));
+        assert_eq!(ob.top_bids_for_exchange(Exchange::Binance, 10), [(97, 6)]);
+    }
+
+    #[test]
+    fn dust_levels_are_hidden_from_reads() {
+        let ob = OrderBook::builder().min_quantity(1_000).build();
+        // Dust at the best prices on both sides and alongside a real level.
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 102, 5, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 2_000, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 101, 999, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 100, 1_000, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 103, 1, Side::Sell));
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 105, 5_000, Side::Sell));
+
+        assert_eq!(
+            ob.top_bids_n(10),
+            [
+                (Exchange::Binance, 101, 2_000),
+                (Exchange::Kraken, 100, 1_000)
+            ]
+        );
+        assert_eq!(ob.top_asks_n(10), [(Exchange::Okx, 105, 5_000)]);
+        assert_eq!(ob.best_bid(), Some((Exchange::Binance, 101, 2_000)));
+        assert_eq!(ob.spread_all_exchanges(), Some(4));
+        assert_eq!(ob.top_bids_merged(10), [(101, 2_000), (100, 1_000)]);
+        assert_eq!(
+            ob.top_bids_for_exchange(Exchange::Kraken, 10),
+            [(100, 1_000)]
+        );
+
+        // The threshold applies at read time, so lowering it brings the dust back.
+        ob.set_min_quantity(0);
+        assert_eq!(ob.best_bid(), Some((Exchange::Binance, 102, 5)));
+        assert_eq!(ob.top_bids_n(10).len(), 4);
+        assert_eq!(ob.spread_all_exchanges(), Some(1));
+    }
+
+    /// Binance, Kraken and OKX quote around 10_000/10_010; OKX also has a bid at 15_000
+    /// and an ask at 20_000 from a bad message.
+    fn book_with_outliers(max_deviation_bps: Option<u32>) -> OrderBook {
+        let ob = OrderBook::builder()
+            .max_deviation_bps(max_deviation_bps)
+            .build();
+        for (exchange, offset) in [
+            (Exchange::Binance, 0),
+            (Exchange::Kraken, 2),
+            (Exchange::Okx, 4),
+        ] {
+            ob.update_price_level(ExchangePrice::new(exchange, 10_000 - offset, 1, Side::Buy));
+            ob.update_price_level(ExchangePrice::new(exchange, 10_010 + offset, 1, Side::Sell));
+        }
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 15_000, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 20_000, 1, Side::Sell));
+        ob
+    }
+
+    #[test]
+    fn price_band_drops_levels_far_from_mid() {
+        // 5% around the median venue mid (10_005): 9_504.75..=10_505.25.
+        let ob = book_with_outliers(Some(500));
+        assert_eq!(ob.best_bid(), Some((Exchange::Binance, 10_000, 1)));
+        assert!(!ob.is_crossed());
+        assert_eq!(ob.spread_all_exchanges(), Some(10));
+        let bids: Vec<u64> = ob.top_bids_n(10).iter().map(|level| level.1).collect();
+        assert_eq!(bids, [10_000, 9_998, 9_996]);
+        let asks: Vec<u64> = ob.top_asks_n(10).iter().map(|level| level.1).collect();
+        assert_eq!(asks, [10_010, 10_012, 10_014]);
+        assert_eq!(ob.top_bids_for_exchange(Exchange::Okx, 10), [(9_996, 1)]);
+        // Validation still sees what OKX sent: its bad bid crosses its own ask.
+        assert!(matches!(
+            ob.validate_exchange(Exchange::Okx),
+            Err(BookError::Crossed { bid: 15_000, .. })
+        ));
+        assert_eq!(ob.top_bids_merged(10)[0], (10_000, 1));
+    }
+
+    #[test]
+    fn price_band_is_opt_in() {
+        let ob = book_with_outliers(None);
+        assert_eq!(ob.best_bid(), Some((Exchange::Okx, 15_000, 1)));
+        assert!(ob.is_crossed());
+        assert_eq!(ob.top_asks_n(10).len(), 4);
+    }
 
-        let asks_json: Vec<_> = top_asks
-            .into_iter()
-            .map(|(exchange, price_cents, qty_smallest)| {
-                let exchange_str = match exchange {
-                    Exchange::Binance => "binance",
-                    Exchange::Bitstamp => "bitstamp",
-                };
-                json!({
-                    "exchange": exchange_str,
-                    "price": price_cents as f64 / 100.0,
-                    "amount": qty_smallest as f64 / 1e8,
-                })
-            })
-            .collect();
+    #[test]
+    fn mid_and_micro_price() {
+        let ob = ob();
+        assert_eq!(ob.mid_price(), None);
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 10_000, 3, Side::Buy));
+        assert_eq!(ob.mid_price(), None);
+        assert_eq!(ob.micro_price(), None);
 
-        let snapshot = json!({
-            "spread": spread_cents.map(|c| c as f64 / 100.0),
-            "symbol": self.symbol,
-            "asks": asks_json,
-            "bids": bids_json,
-        });
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 10_010, 1, Side::Sell));
+        assert_eq!(ob.mid_price(), Some(10_005));
+        // (10_000·1 + 10_010·3) / 4 = 10_007.5: three times the size bid pulls it up.
+        assert_eq!(ob.micro_price(), Some(10_008));
 
-        println!("{}", serde_json::to_string_pretty(&snapshot).unwrap());
+        // Equal sizes make the micro-price the mid; an odd spread rounds half up.
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 10_001, 3, Side::Sell));
+        assert_eq!(ob.mid_price(), Some(10_001));
+        assert_eq!(ob.micro_price(), Some(10_001));
     }
-}
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::api::{ExchangePrice, Side};
 
-    fn ob() -> OrderBook {
-        OrderBook::new("TEST".to_string())
+    #[test]
+    fn liquidity_within_includes_the_limit_price() {
+        let ob = ob();
+        assert_eq!(ob.liquidity_within(Side::Buy, u64::MAX), 0);
+        assert_eq!(ob.liquidity_within(Side::Sell, 0), 0);
+
+        for (exchange, price, qty) in [
+            (Exchange::Binance, 101, 1),
+            (Exchange::Kraken, 102, 2),
+            (Exchange::Binance, 103, 4),
+        ] {
+            ob.update_price_level(ExchangePrice::new(exchange, price, qty, Side::Sell));
+        }
+        for (exchange, price, qty) in [(Exchange::Okx, 99, 5), (Exchange::Kraken, 98, 7)] {
+            ob.update_price_level(ExchangePrice::new(exchange, price, qty, Side::Buy));
+        }
+
+        // A buy walks the asks up to the limit, inclusive.
+        assert_eq!(ob.liquidity_within(Side::Buy, 100), 0);
+        assert_eq!(ob.liquidity_within(Side::Buy, 101), 1);
+        assert_eq!(ob.liquidity_within(Side::Buy, 102), 3);
+        assert_eq!(ob.liquidity_within(Side::Buy, 1_000), 7);
+        // A sell walks the bids down to the limit.
+        assert_eq!(ob.liquidity_within(Side::Sell, 99), 5);
+        assert_eq!(ob.liquidity_within(Side::Sell, 98), 12);
+        assert_eq!(ob.liquidity_within(Side::Sell, 100), 0);
     }
 
     #[test]
     fn aggregates_bids_across_exchanges() {
         let ob = ob();
 
-        ob.update_price_level(ExchangePrice::Binance {
-            price: 100,
-            quantity: 1,
-            exchange_timestamp: 0,
-            received_at: 0,
-            side: Side::Buy,
-        });
-        ob.update_price_level(ExchangePrice::Bitstamp {
-            price: 100,
-            quantity: 2,
-            exchange_timestamp: 0,
-            received_at: 0,
-            side: Side::Buy,
-        });
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 100, 2, Side::Buy));
 
         let bids = ob.top_bids_all_exchanges();
         assert_eq!(bids.len(), 2);
@@ -247,19 +1957,34 @@ mod tests {
         assert_eq!(total_qty, 3);
     }
 
+    #[test]
+    fn zero_quantity_removes_level() {
+        let ob = ob();
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 0, Side::Buy));
+
+        let bids = ob.top_bids_all_exchanges();
+        assert_eq!(bids.len(), 1);
+        assert_eq!(bids[0].1, 100);
+        let still_present = ob
+            .exchange_bids_price_level
+            .get(&Exchange::Binance)
+            .unwrap()
+            .read()
+            .unwrap()
+            .contains_key(&101);
+        assert!(!still_present);
+    }
+
     #[test]
     fn respects_less_than_ten_levels() {
         let ob = ob();
 
         // Insert 5 distinct bid levels
         for i in 0..5 {
-            ob.update_price_level(ExchangePrice::Binance {
-                price: 100 + i,
-                quantity: 1,
-                exchange_timestamp: 0,
-                received_at: 0,
-                side: Side::Buy,
-            });
+            ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100 + i, 1, Side::Buy));
         }
 
         let bids = ob.top_bids_all_exchanges();
@@ -274,13 +1999,12 @@ mod tests {
         let ob = ob();
 
         for i in 0..3 {
-            ob.update_price_level(ExchangePrice::Binance {
-                price: 200 + i * 10,
-                quantity: 1,
-                exchange_timestamp: 0,
-                received_at: 0,
-                side: Side::Sell,
-            });
+            ob.update_price_level(ExchangePrice::new(
+                Exchange::Binance,
+                200 + i * 10,
+                1,
+                Side::Sell,
+            ));
         }
 
         let asks = ob.top_asks_all_exchanges();
@@ -294,20 +2018,8 @@ mod tests {
         let ob = ob();
 
         // Best bid: 100, best ask: 110
-        ob.update_price_level(ExchangePrice::Binance {
-            price: 100,
-            quantity: 1,
-            exchange_timestamp: 0,
-            received_at: 0,
-            side: Side::Buy,
-        });
-        ob.update_price_level(ExchangePrice::Binance {
-            price: 110,
-            quantity: 1,
-            exchange_timestamp: 0,
-            received_at: 0,
-            side: Side::Sell,
-        });
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 110, 1, Side::Sell));
 
         let spread = ob.spread_all_exchanges();
         assert_eq!(spread, Some(10));
@@ -318,23 +2030,966 @@ mod tests {
         let ob = ob();
 
         // Best bid above best ask: 110 bid, 100 ask.
-        ob.update_price_level(ExchangePrice::Binance {
-            price: 110,
-            quantity: 1,
-            exchange_timestamp: 0,
-            received_at: 0,
-            side: Side::Buy,
-        });
-        ob.update_price_level(ExchangePrice::Binance {
-            price: 100,
-            quantity: 1,
-            exchange_timestamp: 0,
-            received_at: 0,
-            side: Side::Sell,
-        });
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 110, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Sell));
 
         // We never expose a negative spread; crossed books are reported as 0.
         let spread = ob.spread_all_exchanges();
         assert_eq!(spread, Some(0));
     }
+
+    #[test]
+    fn signed_spread_flags_crossed_book() {
+        let ob = ob();
+        assert_eq!(ob.spread(), None);
+        assert!(!ob.is_crossed());
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 110, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 100, 1, Side::Sell));
+        assert_eq!(
+            ob.spread(),
+            Some(Spread {
+                value: -10,
+                crossed: true
+            })
+        );
+        assert!(ob.is_crossed());
+
+        // Locked (ask == bid) is tight, not crossed.
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 100, 0, Side::Sell));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 110, 1, Side::Sell));
+        assert_eq!(
+            ob.spread(),
+            Some(Spread {
+                value: 0,
+                crossed: false
+            })
+        );
+        assert!(!ob.is_crossed());
+    }
+
+    #[test]
+    fn spread_suppressed_below_min_exchanges() {
+        let mut ob = ob();
+        ob.min_exchanges = 2;
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 110, 1, Side::Sell));
+        assert_eq!(ob.spread_all_exchanges(), None);
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 99, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 111, 1, Side::Sell));
+        assert_eq!(ob.spread_all_exchanges(), Some(10));
+    }
+
+    #[test]
+    fn spread_by_exchange_attributes_each_side() {
+        let ob = ob();
+        assert_eq!(ob.spread_by_exchange(), None);
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 10_000, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 10_020, 1, Side::Sell));
+        assert_eq!(
+            ob.spread_by_exchange(),
+            Some((Exchange::Binance, Exchange::Binance, 20))
+        );
+
+        // Bitstamp undercuts on the ask: bid from Binance, ask from Bitstamp.
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 9_990, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Bitstamp,
+            10_005,
+            1,
+            Side::Sell,
+        ));
+        assert_eq!(
+            ob.spread_by_exchange(),
+            Some((Exchange::Binance, Exchange::Bitstamp, 5))
+        );
+        assert_eq!(ob.spread_all_exchanges(), Some(5));
+    }
+
+    #[test]
+    fn spread_with_min_exchanges_needs_that_many_venues() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 110, 1, Side::Sell));
+        // One venue: the book's own setting (1) reports it, an arbitrage check does not.
+        assert_eq!(ob.spread_all_exchanges(), Some(10));
+        assert_eq!(ob.spread_with_min_exchanges(1), Some(10));
+        assert_eq!(ob.spread_with_min_exchanges(2), None);
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 101, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 108, 1, Side::Sell));
+        assert_eq!(ob.spread_with_min_exchanges(2), Some(7));
+        assert_eq!(ob.spread_with_min_exchanges(3), None);
+    }
+
+    #[test]
+    fn spread_bps_is_gated_like_the_spread() {
+        let ob = OrderBook::builder().min_exchanges(2).build();
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 9_950, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 10_050, 1, Side::Sell));
+        // One venue per side: neither the spread nor its mid is reported.
+        assert_eq!(ob.spread_all_exchanges(), None);
+        assert_eq!(ob.spread_bps(), None);
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 9_900, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 10_100, 1, Side::Sell));
+        let bps = ob.spread_bps().unwrap();
+        assert!((bps - 100.0 / 10_000.0 * 10_000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn liquidity_score_from_known_depth_and_spread() {
+        let ob = ob();
+        // 1 BTC bid at 100.00 and ask at 101.00: mid 100.50, spread 100 cents.
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_000,
+            100_000_000,
+            Side::Buy,
+        ));
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_100,
+            100_000_000,
+            Side::Sell,
+        ));
+        // Far from mid, excluded from the 1% band.
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            9_000,
+            100_000_000,
+            Side::Buy,
+        ));
+
+        let depth = ob.depth_within_pct(1.0).unwrap();
+        assert!((depth - 201.0).abs() < 1e-9);
+        let bps = ob.spread_bps().unwrap();
+        assert!((bps - 100.0 / 10_050.0 * 10_000.0).abs() < 1e-9);
+
+        let score = ob.liquidity_score(1.0).unwrap();
+        assert!((score - (201.0f64.ln_1p() - bps.ln_1p())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn depth_within_pct_skips_hidden_levels() {
+        let ob = OrderBook::builder()
+            .min_quantity(1_000)
+            .max_deviation_bps(Some(50))
+            .build();
+        for (price, side) in [(10_000, Side::Buy), (10_100, Side::Sell)] {
+            ob.update_price_level(ExchangePrice::new(
+                Exchange::Binance,
+                price,
+                100_000_000,
+                side,
+            ));
+        }
+        // Dust inside the spread, and a bid 1.5% below the 100.50 mid: both within 5% of it,
+        // but the snapshot hides them.
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 10_050, 10, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Kraken,
+            9_900,
+            100_000_000,
+            Side::Buy,
+        ));
+        assert_eq!(
+            ob.top_bids_n(10),
+            [(Exchange::Binance, 10_000, 100_000_000)]
+        );
+
+        let depth = ob.depth_within_pct(5.0).unwrap();
+        assert!((depth - 201.0).abs() < 1e-9, "{depth}");
+    }
+
+    #[test]
+    fn tighter_and_deeper_book_scores_higher() {
+        let wide = ob();
+        wide.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_000,
+            100_000_000,
+            Side::Buy,
+        ));
+        wide.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_100,
+            100_000_000,
+            Side::Sell,
+        ));
+
+        let tight = ob();
+        tight.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_040,
+            500_000_000,
+            Side::Buy,
+        ));
+        tight.update_price_level(ExchangePrice::new(
+            Exchange::Bitstamp,
+            10_050,
+            500_000_000,
+            Side::Sell,
+        ));
+
+        assert!(tight.liquidity_score(1.0).unwrap() > wide.liquidity_score(1.0).unwrap());
+        assert_eq!(ob().liquidity_score(1.0), None);
+    }
+
+    #[test]
+    fn top_n_respects_requested_depth() {
+        let ob = ob();
+        for i in 0..15 {
+            ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100 + i, 1, Side::Buy));
+            ob.update_price_level(ExchangePrice::new(
+                Exchange::Bitstamp,
+                200 + i,
+                1,
+                Side::Sell,
+            ));
+        }
+
+        assert_eq!(ob.top_bids_all_exchanges().len(), DEFAULT_DEPTH);
+        assert_eq!(ob.top_bids_n(12).len(), 12);
+        assert_eq!(
+            ob.top_asks_n(3).iter().map(|l| l.1).collect::<Vec<_>>(),
+            vec![200, 201, 202]
+        );
+        assert_eq!(ob.top_asks_n(50).len(), 15);
+    }
+
+    #[test]
+    fn best_bid_and_ask_empty_book() {
+        let ob = ob();
+        assert_eq!(ob.best_bid(), None);
+        assert_eq!(ob.best_ask(), None);
+
+        // A venue whose levels were all cleared counts as empty.
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 0, Side::Buy));
+        assert_eq!(ob.best_bid(), None);
+    }
+
+    #[test]
+    fn best_bid_and_ask_across_exchanges() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 102, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 110, 1, Side::Sell));
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 108, 3, Side::Sell));
+
+        assert_eq!(ob.best_bid(), Some((Exchange::Kraken, 102, 1)));
+        assert_eq!(ob.best_ask(), Some((Exchange::Okx, 108, 3)));
+        assert_eq!(ob.best_bid(), ob.top_bids_n(1).first().copied());
+    }
+
+    #[test]
+    fn best_level_ties_prefer_larger_quantity_then_exchange_order() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 100, 5, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 100, 2, Side::Buy));
+        assert_eq!(ob.best_bid(), Some((Exchange::Okx, 100, 5)));
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 110, 4, Side::Sell));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 110, 4, Side::Sell));
+        assert_eq!(ob.best_ask(), Some((Exchange::Bitstamp, 110, 4)));
+    }
+
+    #[test]
+    fn vwap_weights_levels_by_quantity() {
+        let ob = ob();
+        assert_eq!(ob.vwap(Side::Buy, 5), None);
+
+        // Same price on two venues counts as two levels of the combined book.
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 100, 3, Side::Buy));
+        assert_eq!(ob.vwap(Side::Buy, 2), Some(100));
+
+        // (100*1 + 100*3 + 90*4) / 8 = 95
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 90, 4, Side::Buy));
+        assert_eq!(ob.vwap(Side::Buy, 3), Some(95));
+        // Deeper levels beyond n are ignored.
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 10, 100, Side::Buy));
+        assert_eq!(ob.vwap(Side::Buy, 3), Some(95));
+    }
+
+    #[test]
+    fn vwap_on_asks_rounds_to_nearest_cent() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 200, 2, Side::Sell));
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 201, 1, Side::Sell));
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 250, 1, Side::Sell));
+
+        // (200*2 + 201*1) / 3 = 200.33
+        assert_eq!(ob.vwap(Side::Sell, 2), Some(200));
+        // (200*2 + 201 + 250) / 4 = 212.75
+        assert_eq!(ob.vwap(Side::Sell, 10), Some(213));
+        assert_eq!(ob.vwap(Side::Buy, 10), None);
+    }
+
+    #[test]
+    fn fill_cost_walks_liquidity_across_exchanges() {
+        let ob = ob();
+        assert_eq!(ob.fill_cost(Side::Buy, 100_000_000), None);
+
+        // 0.5 BTC at 100.00 on Binance, 1 BTC at 101.00 on Bitstamp.
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_000,
+            50_000_000,
+            Side::Sell,
+        ));
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Bitstamp,
+            10_100,
+            100_000_000,
+            Side::Sell,
+        ));
+
+        // Buy 1 BTC: 0.5 @ 100.00 + 0.5 @ 101.00 = 100.50.
+        assert_eq!(
+            ob.fill_cost(Side::Buy, 100_000_000),
+            Some(FillEstimate {
+                filled_qty: 100_000_000,
+                total_cost: 10_050,
+                avg_price: 10_050,
+                complete: true,
+            })
+        );
+        // Bids are empty, so a sell cannot fill at all.
+        assert_eq!(ob.fill_cost(Side::Sell, 1), None);
+    }
+
+    #[test]
+    fn fill_cost_reports_partial_fill() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_000,
+            100_000_000,
+            Side::Buy,
+        ));
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Bitstamp,
+            9_900,
+            50_000_000,
+            Side::Buy,
+        ));
+
+        // Sell 2 BTC into 1.5 BTC of bids: 100.00 + 0.5 * 99.00 = 149.50 for 1.5 BTC.
+        let fill = ob.fill_cost(Side::Sell, 200_000_000).unwrap();
+        assert!(!fill.complete);
+        assert_eq!(fill.filled_qty, 150_000_000);
+        assert_eq!(fill.total_cost, 14_950);
+        assert_eq!(fill.avg_price, 9_967);
+    }
+
+    #[test]
+    fn imbalance_from_known_quantities() {
+        let ob = ob();
+        assert_eq!(ob.imbalance(5), None);
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 3, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 99, 3, Side::Buy));
+        // Only bids: fully bid-heavy.
+        assert_eq!(ob.imbalance(5), Some(1.0));
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 2, Side::Sell));
+        // (6 - 2) / 8
+        assert_eq!(ob.imbalance(5), Some(0.5));
+        // Top level only: (3 - 2) / 5
+        assert_eq!(ob.imbalance(1), Some(0.2));
+    }
+
+    #[test]
+    fn imbalance_negative_when_asks_dominate() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 101, 3, Side::Sell));
+        assert_eq!(ob.imbalance(10), Some(-0.5));
+    }
+
+    #[test]
+    fn latency_stats_track_last_mean_and_max() {
+        let ob = ob();
+        let update = |exchange_ts, received_at| {
+            ExchangePrice::new(Exchange::Bitstamp, 100, 1, Side::Buy)
+                .with_timestamps(exchange_ts, received_at)
+        };
+
+        ob.update_price_level(update(1_000, 1_040));
+        ob.update_price_level(update(2_000, 2_100));
+        ob.update_price_level(update(3_000, 3_010));
+        // Missing exchange timestamp is skipped.
+        ob.update_price_level(update(0, 4_000));
+
+        let stats = ob.latency_stats(Exchange::Bitstamp).unwrap();
+        assert_eq!(stats.last_ms, 10);
+        assert_eq!(stats.max_ms, 100);
+        assert!((stats.mean_ms - 50.0).abs() < 1e-9);
+        assert_eq!(ob.latency_stats(Exchange::Binance), None);
+    }
+
+    #[test]
+    fn latency_mean_rolls_over_window() {
+        let ob = ob();
+        ob.update_price_level(
+            ExchangePrice::new(Exchange::Okx, 100, 1, Side::Sell).with_timestamps(1, 1_001),
+        );
+        for i in 0..LATENCY_WINDOW as u64 {
+            ob.update_price_level(
+                ExchangePrice::new(Exchange::Okx, 100, 1, Side::Sell)
+                    .with_timestamps(10 + i, 20 + i),
+            );
+        }
+
+        let stats = ob.latency_stats(Exchange::Okx).unwrap();
+        // The 1s outlier has left the window but is still the max.
+        assert!((stats.mean_ms - 10.0).abs() < 1e-9);
+        assert_eq!(stats.max_ms, 1_000);
+    }
+
+    #[test]
+    fn version_advances_on_every_update() {
+        let ob = ob();
+        assert_eq!(ob.version(), 0);
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 0, Side::Buy));
+        assert_eq!(ob.version(), 2);
+    }
+
+    #[test]
+    fn evicts_levels_not_updated_since_cutoff() {
+        let ob = ob();
+        let at = |exchange, price, side, received_at| {
+            ExchangePrice::new(exchange, price, 1, side).with_timestamps(0, received_at)
+        };
+        ob.update_price_level(at(Exchange::Binance, 100, Side::Buy, 1_000));
+        ob.update_price_level(at(Exchange::Kraken, 99, Side::Buy, 5_000));
+        ob.update_price_level(at(Exchange::Binance, 110, Side::Sell, 2_000));
+        // Refreshing a level moves its timestamp forward.
+        ob.update_price_level(at(Exchange::Binance, 110, Side::Sell, 6_000));
+
+        let version = ob.version();
+        assert_eq!(ob.evict_updated_before(4_000), 1);
+        assert!(ob.version() > version);
+        assert_eq!(ob.best_bid(), Some((Exchange::Kraken, 99, 1)));
+        assert_eq!(ob.best_ask(), Some((Exchange::Binance, 110, 1)));
+
+        // Nothing left to evict leaves the version alone.
+        let version = ob.version();
+        assert_eq!(ob.evict_updated_before(4_000), 0);
+        assert_eq!(ob.version(), version);
+    }
+
+    #[test]
+    fn evict_stale_keeps_fresh_levels() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 100, 1, Side::Buy));
+        assert_eq!(ob.evict_stale(Duration::from_secs(60)), 0);
+        assert!(ob.best_bid().is_some());
+    }
+
+    #[test]
+    fn exchange_tops_report_each_venue_separately() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 99, 4, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 102, 2, Side::Sell));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 103, 3, Side::Buy));
+
+        assert_eq!(
+            ob.exchange_tops(),
+            vec![
+                ExchangeTopOfBook {
+                    exchange: Exchange::Binance,
+                    bid: Some((100, 1)),
+                    ask: Some((102, 2)),
+                },
+                ExchangeTopOfBook {
+                    exchange: Exchange::Bitstamp,
+                    bid: Some((103, 3)),
+                    ask: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_names_every_exchange() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Coinbase, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 105, 1, Side::Sell));
+
+        let snapshot = ob.snapshot(10);
+        assert_eq!(snapshot.bids[0].exchange.venue, Exchange::Coinbase);
+        assert_eq!(snapshot.asks[0].exchange.venue, Exchange::Kraken);
+        assert!(snapshot.generated_at > 1_600_000_000_000);
+    }
+
+    #[test]
+    fn snapshot_divides_by_the_price_scale() {
+        let mut ob = ob();
+        ob.price_decimals = 8;
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 1_234, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 1_334, 1, Side::Sell));
+
+        let snapshot = ob.snapshot(10);
+        assert_eq!(snapshot.bids[0].price.to_f64(), 0.00001234);
+        assert_eq!(snapshot.spread.map(Units::to_f64), Some(0.000001));
+    }
+
+    #[test]
+    fn diff_tells_added_changed_and_removed_levels_apart() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_000,
+            100,
+            Side::Buy,
+        ));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 9_990, 100, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_010,
+            100,
+            Side::Sell,
+        ));
+        let previous = ob.snapshot(10);
+        assert!(ob.diff(&previous).is_empty());
+
+        // Quantity change at the same price (sizes are absolute), a removal (zero size)
+        // and a new level.
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            10_000,
+            150,
+            Side::Buy,
+        ));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 9_990, 0, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 10_020, 50, Side::Sell));
+
+        let diff = ob.diff(&previous);
+        let level = |exchange, price, quantity| ob.level_out((exchange, price, quantity));
+        assert_eq!(diff.bids.changed, [level(Exchange::Binance, 10_000, 150)]);
+        assert_eq!(diff.bids.removed, [level(Exchange::Kraken, 9_990, 100)]);
+        assert!(diff.bids.added.is_empty());
+        assert_eq!(diff.asks.added, [level(Exchange::Okx, 10_020, 50)]);
+        assert!(diff.asks.changed.is_empty() && diff.asks.removed.is_empty());
+
+        // The same price on another venue is a different level.
+        let previous = ob.snapshot(10);
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Coinbase,
+            10_000,
+            250,
+            Side::Buy,
+        ));
+        let diff = ob.diff(&previous);
+        assert_eq!(diff.bids.added, [level(Exchange::Coinbase, 10_000, 250)]);
+        assert!(diff.bids.changed.is_empty());
+    }
+
+    #[test]
+    fn diff_compares_at_the_previous_depth() {
+        let ob = ob();
+        for price in [100, 99, 98] {
+            ob.update_price_level(ExchangePrice::new(Exchange::Binance, price, 1, Side::Buy));
+        }
+        let previous = ob.snapshot(2);
+
+        // A better bid pushes 99 out of the top two.
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 1, Side::Buy));
+        let diff = ob.diff(&previous);
+        assert_eq!(diff.bids.added, [ob.level_out((Exchange::Binance, 101, 1))]);
+        assert_eq!(
+            diff.bids.removed,
+            [ob.level_out((Exchange::Binance, 99, 1))]
+        );
+    }
+
+    #[test]
+    fn snapshot_levels_carry_their_exchange_timestamp() {
+        let ob = ob();
+        ob.update_price_level(
+            ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy)
+                .with_timestamps(1_700_000_000_000, 1_700_000_000_050),
+        );
+        ob.update_price_level(
+            ExchangePrice::new(Exchange::Binance, 100, 2, Side::Buy)
+                .with_timestamps(1_700_000_005_000, 1_700_000_005_020),
+        );
+        // No exchange timestamp: the level is reported without one.
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 99, 1, Side::Buy));
+
+        let snapshot = ob.snapshot(10);
+        assert_eq!(snapshot.bids[0].exchange_timestamp, Some(1_700_000_005_000));
+        assert_eq!(snapshot.bids[1].exchange_timestamp, None);
+        let json = serde_json::to_string(&snapshot.bids).unwrap();
+        assert_eq!(json.matches("exchange_timestamp").count(), 1);
+    }
+
+    #[test]
+    fn level_out_converts_to_whole_units() {
+        let mut ob = ob();
+        ob.base_decimals = 9;
+        ob.price_decimals = 6;
+        let level = ob.level_out((Exchange::Okx, 101_500_000, 2_500_000_000));
+        assert_eq!(level.exchange.venue, Exchange::Okx);
+        assert_eq!(level.price.to_f64(), 101.5);
+        assert_eq!(level.amount.to_f64(), 2.5);
+    }
+
+    /// A bid at 43250.50 for 0.75 BTC and an ask 1.00 above it, in `format`.
+    fn formatted_snapshot(format: OutputFormat) -> BookSnapshot {
+        let mut ob = ob();
+        ob.output_format = format;
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Binance,
+            4_325_050,
+            75_000_000,
+            Side::Buy,
+        ));
+        ob.update_price_level(ExchangePrice::new(
+            Exchange::Kraken,
+            4_325_150,
+            200_000_000,
+            Side::Sell,
+        ));
+        ob.snapshot(1)
+    }
+
+    #[test]
+    fn float_output_writes_whole_units_as_numbers() {
+        let snapshot = formatted_snapshot(OutputFormat::Float);
+        assert_eq!(serde_json::to_string(&snapshot.spread).unwrap(), "1.0");
+        assert_eq!(
+            serde_json::to_string(&snapshot.bids).unwrap(),
+            r#"[{"exchange":"binance","price":43250.5,"amount":0.75}]"#
+        );
+        assert!(!serde_json::to_string(&snapshot).unwrap().contains("scale"));
+    }
+
+    #[test]
+    fn integer_output_writes_stored_units_and_their_scale() {
+        let snapshot = formatted_snapshot(OutputFormat::Integer);
+        assert_eq!(serde_json::to_string(&snapshot.spread).unwrap(), "100");
+        assert_eq!(
+            serde_json::to_string(&snapshot.asks).unwrap(),
+            r#"[{"exchange":"kraken","price":4325150,"amount":200000000}]"#
+        );
+        assert!(
+            serde_json::to_string(&snapshot)
+                .unwrap()
+                .ends_with(r#""scale":{"price_decimals":2,"base_decimals":8}}"#)
+        );
+    }
+
+    #[test]
+    fn decimal_output_writes_exact_strings() {
+        let snapshot = formatted_snapshot(OutputFormat::Decimal);
+        assert_eq!(
+            serde_json::to_string(&snapshot.spread).unwrap(),
+            r#""1.00""#
+        );
+        assert_eq!(
+            serde_json::to_string(&snapshot.bids).unwrap(),
+            r#"[{"exchange":"binance","price":"43250.50","amount":"0.75000000"}]"#
+        );
+    }
+
+    #[test]
+    fn decimal_strings_keep_digits_a_float_would_round() {
+        let units = |raw, decimals| Units {
+            raw,
+            decimals,
+            format: OutputFormat::Decimal,
+        };
+        assert_eq!(
+            units(12_345_678_901_234_567_891, 8).to_decimal_string(),
+            "123456789012.34567891"
+        );
+        assert_eq!(units(5, 3).to_decimal_string(), "0.005");
+        assert_eq!(units(0, 2).to_decimal_string(), "0.00");
+        assert_eq!(units(42, 0).to_decimal_string(), "42");
+    }
+
+    #[test]
+    fn exchange_format_picks_name_enum_or_id() {
+        let written = |format| {
+            let mut ob = ob();
+            ob.exchange_format = format;
+            ob.update_price_level(
+                ExchangePrice::new(Exchange::Okx, 100, 1, Side::Buy).with_timestamps(1_000, 1_005),
+            );
+            let snapshot = ob.snapshot(1);
+            (
+                serde_json::to_value(snapshot.bids[0].exchange).unwrap(),
+                serde_json::to_value(&snapshot.latency_ms)
+                    .unwrap()
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(
+            written(ExchangeFormat::Name),
+            (serde_json::json!("okx"), vec!["okx".to_string()])
+        );
+        assert_eq!(
+            written(ExchangeFormat::Enum),
+            (serde_json::json!("Okx"), vec!["Okx".to_string()])
+        );
+        assert_eq!(
+            written(ExchangeFormat::Id),
+            (serde_json::json!(4), vec!["4".to_string()])
+        );
+        assert_eq!("enum".parse(), Ok(ExchangeFormat::Enum));
+        assert!("number".parse::<ExchangeFormat>().is_err());
+    }
+
+    #[test]
+    fn parses_output_formats() {
+        assert_eq!("integer".parse(), Ok(OutputFormat::Integer));
+        assert_eq!("decimal".parse(), Ok(OutputFormat::Decimal));
+        assert!("cents".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn merged_levels_sum_same_price_quotes() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 2, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 100, 3, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Bitstamp, 99, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 4, Side::Sell));
+        ob.update_price_level(ExchangePrice::new(Exchange::Kraken, 101, 1, Side::Sell));
+
+        assert_eq!(ob.top_bids_merged(10), vec![(100, 5), (99, 1)]);
+        assert_eq!(ob.top_bids_merged(1), vec![(100, 5)]);
+        assert_eq!(ob.top_asks_merged(10), vec![(101, 5)]);
+        // The per-exchange view keeps both rows.
+        assert_eq!(ob.top_bids_n(2).len(), 2);
+    }
+
+    #[test]
+    fn same_price_levels_have_a_stable_order() {
+        // Insert the same book in different orders; the listing must not change.
+        let quotes = [
+            (Exchange::Okx, 2),
+            (Exchange::Binance, 1),
+            (Exchange::Kraken, 2),
+            (Exchange::Coinbase, 1),
+        ];
+        let build = |order: &[usize]| {
+            let ob = ob();
+            for &i in order {
+                let (exchange, qty) = quotes[i];
+                ob.update_price_level(ExchangePrice::new(exchange, 100, qty, Side::Buy));
+                ob.update_price_level(ExchangePrice::new(exchange, 200, qty, Side::Sell));
+            }
+            ob
+        };
+        let expected = vec![
+            (Exchange::Kraken, 100, 2),
+            (Exchange::Okx, 100, 2),
+            (Exchange::Binance, 100, 1),
+            (Exchange::Coinbase, 100, 1),
+        ];
+        for order in [[0, 1, 2, 3], [3, 2, 1, 0], [1, 3, 0, 2]] {
+            let ob = build(&order);
+            assert_eq!(ob.top_bids_n(10), expected);
+            let asks: Vec<_> = ob.top_asks_n(10).iter().map(|l| (l.0, l.2)).collect();
+            let bids: Vec<_> = expected.iter().map(|l| (l.0, l.2)).collect();
+            assert_eq!(asks, bids);
+            assert_eq!(ob.best_bid(), Some(expected[0]));
+        }
+    }
+
+    #[test]
+    fn merged_top_levels_match_a_full_sort() {
+        let ob = ob();
+        // Deep, overlapping books with shared prices and equal quantities across venues.
+        for (n, exchange) in Exchange::ALL.into_iter().enumerate() {
+            for i in 0..300u64 {
+                let price = 10_000 + (i * 7 + n as u64 * 3) % 400;
+                let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+                ob.update_price_level(ExchangePrice::new(exchange, price, 1 + i % 4, side));
+            }
+        }
+
+        let view = ob.view();
+        let mut bids = Vec::new();
+        let mut asks = Vec::new();
+        for (side, out) in [(view.bids(), &mut bids), (view.asks(), &mut asks)] {
+            for (exchange, levels) in side.venues() {
+                out.extend(levels.map(|(price, qty, _)| (exchange, price, qty)));
+            }
+        }
+        let same_price = |a: &(Exchange, u64, u64), b: &(Exchange, u64, u64)| {
+            b.2.cmp(&a.2).then_with(|| a.0.as_str().cmp(b.0.as_str()))
+        };
+        bids.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| same_price(a, b)));
+        asks.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| same_price(a, b)));
+
+        for n in [1, 10, 100, usize::MAX] {
+            assert_eq!(ob.top_bids_n(n), bids[..n.min(bids.len())]);
+            assert_eq!(ob.top_asks_n(n), asks[..n.min(asks.len())]);
+        }
+    }
+
+    /// Best bid/ask recomputed from the write-side maps, ignoring the published view.
+    fn recomputed_best(ob: &OrderBook, side: Side) -> Option<(Exchange, u64, u64)> {
+        let side_levels = match side {
+            Side::Buy => &ob.exchange_bids_price_level,
+            Side::Sell => &ob.exchange_asks_price_level,
+        };
+        let mut best: Option<(Exchange, u64, u64)> = None;
+        for entry in side_levels.iter() {
+            let levels = entry.value().read().unwrap();
+            let head = match side {
+                Side::Buy => levels.iter().next_back(),
+                Side::Sell => levels.iter().next(),
+            };
+            let Some((&price, level)) = head else {
+                continue;
+            };
+            let candidate = (*entry.key(), price, level.quantity);
+            let better_price = |a: u64, b: u64| match side {
+                Side::Buy => a > b,
+                Side::Sell => a < b,
+            };
+            if best.is_none_or(|best| OrderBook::ranks_before(candidate, best, better_price)) {
+                best = Some(candidate);
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn published_best_levels_track_inserts_and_removals_at_the_top() {
+        let ob = ob();
+        // Deterministic pseudo-random walk that mostly touches the current best, so removals
+        // at the top keep moving the published best onto the next level.
+        let mut state = 0x2545_f491_u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            state >> 33
+        };
+        for step in 0..5_000 {
+            let exchange = Exchange::ALL[next() as usize % Exchange::ALL.len()];
+            let side = if next() % 2 == 0 {
+                Side::Buy
+            } else {
+                Side::Sell
+            };
+            let price = match (next() % 3, recomputed_best(&ob, side)) {
+                (0, _) | (_, None) => 9_900 + next() % 200,
+                (_, Some((_, best, _))) => best,
+            };
+            let quantity = if next() % 2 == 0 { 0 } else { 1 + next() % 3 };
+            ob.update_price_level(ExchangePrice::new(exchange, price, quantity, side));
+
+            assert_eq!(
+                ob.best_bid(),
+                recomputed_best(&ob, Side::Buy),
+                "step {step}"
+            );
+            assert_eq!(
+                ob.best_ask(),
+                recomputed_best(&ob, Side::Sell),
+                "step {step}"
+            );
+        }
+
+        // Stale eviction republishes too.
+        ob.evict_updated_before(u64::MAX);
+        assert_eq!(ob.best_bid(), None);
+        assert_eq!(ob.best_ask(), None);
+    }
+
+    #[test]
+    fn deep_books_stay_readable() {
+        let ob = ob();
+        let levels: Vec<_> = (0..5_000)
+            .map(|i| ExchangePrice::new(Exchange::Binance, 10_000 + i, 1, Side::Sell))
+            .collect();
+        ob.update_price_levels(&levels);
+
+        let asks = ob.top_asks_n(usize::MAX);
+        assert_eq!(asks.len(), 5_000);
+        assert_eq!(asks[4_999], (Exchange::Binance, 14_999, 1));
+        assert_eq!(ob.liquidity_within(Side::Buy, u64::MAX), 5_000);
+    }
+
+    #[test]
+    fn writes_republish_only_their_venue_and_side() {
+        let ob = ob();
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Okx, 99, 1, Side::Buy));
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 101, 1, Side::Sell));
+        let before = ob.view();
+        // Views load lazily; load both sides before the write.
+        before.bids();
+        before.asks();
+
+        ob.update_price_level(ExchangePrice::new(Exchange::Binance, 98, 1, Side::Buy));
+        let after = ob.view();
+        let slot =
+            |side: &SideView, exchange: Exchange| Arc::clone(&side.venues[exchange as usize]);
+        assert!(!Arc::ptr_eq(
+            &slot(before.bids(), Exchange::Binance),
+            &slot(after.bids(), Exchange::Binance)
+        ));
+        assert!(Arc::ptr_eq(
+            &slot(before.bids(), Exchange::Okx),
+            &slot(after.bids(), Exchange::Okx)
+        ));
+        assert!(Arc::ptr_eq(
+            &slot(before.asks(), Exchange::Binance),
+            &slot(after.asks(), Exchange::Binance)
+        ));
+        // A view taken earlier keeps seeing the book as it was.
+        assert_eq!(before.bids().levels(Exchange::Binance).count(), 1);
+        assert_eq!(after.bids().levels(Exchange::Binance).count(), 2);
+    }
+
+    #[test]
+    fn batched_updates_match_one_at_a_time() {
+        let updates = || {
+            vec![
+                ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy),
+                ExchangePrice::new(Exchange::Binance, 99, 2, Side::Buy),
+                ExchangePrice::new(Exchange::Binance, 101, 3, Side::Sell),
+                ExchangePrice::new(Exchange::Okx, 100, 4, Side::Buy),
+                // Back to Binance bids: a later run for the same side still applies in order.
+                ExchangePrice::new(Exchange::Binance, 100, 0, Side::Buy),
+                ExchangePrice::new(Exchange::Binance, 98, 5, Side::Buy),
+            ]
+        };
+        let single = ob();
+        for update in updates() {
+            single.update_price_level(update);
+        }
+        let batched = ob();
+        batched.update_price_levels(&updates());
+
+        assert_eq!(batched.top_bids_n(10), single.top_bids_n(10));
+        assert_eq!(batched.top_asks_n(10), single.top_asks_n(10));
+        assert_eq!(batched.version(), single.version());
+        assert_eq!(
+            batched.top_bids_n(10),
+            vec![
+                (Exchange::Okx, 100, 4),
+                (Exchange::Binance, 99, 2),
+                (Exchange::Binance, 98, 5)
+            ]
+        );
+    }
 }
@@ -0,0 +1,226 @@
+//! Recording to disk as newline-delimited JSON: periodic book snapshots for backtesting,
+//! and the raw updates themselves, which `replay` can feed back through the pipeline.
+//!
+//! The aggregator loop only publishes what to record on a broadcast channel; a separate
+//! task serializes and writes it, so file I/O never holds up ingest.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+use crate::api::{Exchange, ExchangePrice, Side, UpdateMode};
+use crate::orderbook::BookSnapshot;
+
+/// Batches buffered for the writer before the oldest are dropped (and logged).
+pub const RECORD_CHANNEL_CAPACITY: usize = 256;
+
+/// One line of a recording: a book snapshot and when it was taken.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordedSnapshot {
+    /// Capture time, epoch milliseconds.
+    pub captured_at: u64,
+    #[serde(flatten)]
+    pub snapshot: BookSnapshot,
+}
+
+/// One line of an update recording: an `ExchangePrice` in book units, enough to rebuild it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedUpdate {
+    /// Trading pair as configured, e.g. "BTC-USDT".
+    pub pair: String,
+    pub exchange: Exchange,
+    pub side: Side,
+    /// Price in `10^-price_decimals` units of the quote asset.
+    pub price: u64,
+    /// Quantity in the base asset's smallest unit; 0 removes the level.
+    pub quantity: u64,
+    /// Exchange's own timestamp (epoch ms, 0 if unknown).
+    pub exchange_timestamp: u64,
+    /// When the update was received (epoch ms); replay pacing follows these.
+    pub received_at: u64,
+    /// Whether the update opened a snapshot; recordings made before modes existed read as
+    /// `delta`, and so does the retired `add` mode.
+    #[serde(default)]
+    pub mode: UpdateMode,
+}
+
+impl From<&ExchangePrice> for RecordedUpdate {
+    fn from(update: &ExchangePrice) -> Self {
+        let (price, quantity, side) = update.level();
+        RecordedUpdate {
+            pair: update.pair().to_string(),
+            exchange: update.exchange(),
+            side,
+            price,
+            quantity,
+            exchange_timestamp: update.exchange_timestamp(),
+            received_at: update.received_at(),
+            mode: update.mode(),
+        }
+    }
+}
+
+impl From<RecordedUpdate> for ExchangePrice {
+    fn from(update: RecordedUpdate) -> Self {
+        ExchangePrice::new(update.exchange, update.price, update.quantity, update.side)
+            .with_timestamps(update.exchange_timestamp, update.received_at)
+            .with_pair(update.pair.into())
+            .with_mode(update.mode)
+    }
+}
+
+/// Open `path` for appending, creating it if needed, so restarts extend a recording.
+pub async fn open_recording(path: &Path) -> std::io::Result<tokio::fs::File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+/// A recording in progress: `record` publishes batches for the writer task, `finish` closes
+/// the channel and waits for the file to be flushed.
+pub struct Recorder<T> {
+    tx: broadcast::Sender<Arc<[T]>>,
+    writer: JoinHandle<()>,
+}
+
+impl<T> Recorder<T>
+where
+    T: Serialize + Send + Sync + 'static,
+{
+    /// Open (or create) `path` for appending and start the writer task.
+    pub async fn start(path: &Path) -> std::io::Result<Self> {
+        let file = open_recording(path).await?;
+        let (tx, rx) = broadcast::channel(RECORD_CHANNEL_CAPACITY);
+        let path = path.display().to_string();
+        let writer = tokio::spawn(async move {
+            match record_lines(file, rx).await {
+                Ok(lines) => tracing::info!(path, lines, "recording closed"),
+                Err(e) => tracing::error!(path, error = %e, "recording failed"),
+            }
+        });
+        Ok(Recorder { tx, writer })
+    }
+
+    /// Queue `batch` for writing without waiting; dropped (and logged) if the writer lags.
+    pub fn record(&self, batch: impl Into<Arc<[T]>>) {
+        // Only fails once the writer has stopped, which it logs itself.
+        let _ = self.tx.send(batch.into());
+    }
+
+    /// Stop accepting batches and wait up to `timeout` for the rest to be written and flushed.
+    pub async fn finish(self, timeout: Duration) {
+        drop(self.tx);
+        if tokio::time::timeout(timeout, self.writer).await.is_err() {
+            tracing::warn!("recording did not finish flushing in time");
+        }
+    }
+}
+
+/// Write every item of every batch received on `batches` to `out`, one JSON object per
+/// line, until all senders are dropped; then flush. Returns the number of lines written.
+///
+/// Writes go through a `BufWriter`, so a line may sit in memory until the buffer fills or
+/// the channel closes. If the writer falls behind, skipped batches are logged.
+pub async fn record_lines<T, W>(
+    out: W,
+    mut batches: broadcast::Receiver<Arc<[T]>>,
+) -> std::io::Result<u64>
+where
+    T: Serialize,
+    W: AsyncWrite + Unpin,
+{
+    let mut out = BufWriter::new(out);
+    let mut written = 0;
+    let mut line = Vec::new();
+    loop {
+        let batch = match batches.recv().await {
+            Ok(batch) => batch,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(skipped, "recorder fell behind; batches not recorded");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        for item in batch.iter() {
+            line.clear();
+            serde_json::to_writer(&mut line, item).map_err(std::io::Error::other)?;
+            line.push(b'\n');
+            out.write_all(&line).await?;
+            written += 1;
+        }
+    }
+    out.flush().await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderBook;
+
+    #[test]
+    fn legacy_add_mode_reads_as_delta() {
+        let line = r#"{"pair":"BTC-USDT","exchange":"kraken","side":"buy","price":100,"quantity":2,"exchange_timestamp":0,"received_at":5,"mode":"add"}"#;
+        let update: RecordedUpdate = serde_json::from_str(line).unwrap();
+        assert_eq!(update.mode, UpdateMode::Delta);
+        let written = serde_json::to_value(&update).unwrap();
+        assert_eq!(written["mode"], "delta");
+    }
+
+    #[tokio::test]
+    async fn appends_lines_to_the_file() {
+        let path = std::env::temp_dir().join(format!("agg-record-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let book = OrderBook::new("ETH-USDT".to_string());
+        book.update_price_level(ExchangePrice::new(Exchange::Okx, 200_000, 5, Side::Sell));
+
+        for captured_at in [10, 20] {
+            let (tx, rx) = broadcast::channel(8);
+            let file = open_recording(&path).await.unwrap();
+            let writer = tokio::spawn(record_lines(file, rx));
+            let batch: Arc<[RecordedSnapshot]> = Arc::new([RecordedSnapshot {
+                captured_at,
+                snapshot: book.snapshot(5),
+            }]);
+            tx.send(batch).unwrap();
+            drop(tx);
+            writer.await.unwrap().unwrap();
+        }
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["captured_at"], 10);
+        assert_eq!(lines[1]["captured_at"], 20);
+        assert_eq!(lines[1]["symbol"], "ETH-USDT");
+        assert_eq!(lines[1]["asks"][0]["exchange"], "okx");
+    }
+
+    #[test]
+    fn recorded_updates_rebuild_the_same_price() {
+        let original = ExchangePrice::new(Exchange::Bitstamp, 6_543_210, 25, Side::Sell)
+            .with_timestamps(1_700_000_000_000, 1_700_000_000_050)
+            .with_pair("ETH-USD".into());
+        let line = serde_json::to_string(&RecordedUpdate::from(&original)).unwrap();
+        assert!(line.contains(r#""exchange":"bitstamp","side":"sell""#));
+
+        let replayed = ExchangePrice::from(serde_json::from_str::<RecordedUpdate>(&line).unwrap());
+        assert!(matches!(replayed, ExchangePrice::Bitstamp { .. }));
+        assert_eq!(replayed.pair(), "ETH-USD");
+        assert_eq!(replayed.level(), (6_543_210, 25, Side::Sell));
+        assert_eq!(replayed.exchange_timestamp(), 1_700_000_000_000);
+        assert_eq!(replayed.received_at(), 1_700_000_000_050);
+    }
+}
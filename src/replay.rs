@@ -0,0 +1,177 @@
+//! Replay of recorded `ExchangePrice` updates into the aggregation pipeline.
+
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::{Instant, sleep_until};
+
+use crate::api::ExchangePrice;
+use crate::recorder::RecordedUpdate;
+
+/// How fast recorded updates are fed back, relative to their recorded `received_at` gaps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// No pacing: send every update as fast as the channel accepts it.
+    Max,
+    /// Reproduce the original inter-arrival timing.
+    Realtime,
+    /// Original timing sped up by this factor (2.0 replays twice as fast).
+    Multiplier(f64),
+}
+
+impl ReplaySpeed {
+    /// Wall-clock offset at which an update recorded `elapsed_ms` after the first should be sent.
+    fn offset(self, elapsed_ms: u64) -> Option<Duration> {
+        let elapsed = Duration::from_millis(elapsed_ms);
+        match self {
+            ReplaySpeed::Max => None,
+            ReplaySpeed::Realtime => Some(elapsed),
+            ReplaySpeed::Multiplier(factor) if factor > 0.0 => Some(elapsed.div_f64(factor)),
+            ReplaySpeed::Multiplier(_) => None,
+        }
+    }
+}
+
+impl std::str::FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(ReplaySpeed::Max),
+            "realtime" => Ok(ReplaySpeed::Realtime),
+            other => other
+                .trim_end_matches('x')
+                .parse::<f64>()
+                .ok()
+                .filter(|factor| *factor > 0.0 && factor.is_finite())
+                .map(ReplaySpeed::Multiplier)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown replay speed '{other}' (valid: max, realtime, or a factor such as 2x)"
+                    )
+                }),
+        }
+    }
+}
+
+/// Read an update recording (`RECORD_UPDATES_PATH`): one `RecordedUpdate` per line, blank
+/// lines ignored. Fails on the first line that does not parse, naming it.
+pub fn read_updates(path: &Path) -> std::io::Result<Vec<ExchangePrice>> {
+    let text = std::fs::read_to_string(path)?;
+    parse_updates(&text)
+}
+
+fn parse_updates(text: &str) -> std::io::Result<Vec<ExchangePrice>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str::<RecordedUpdate>(line)
+                .map(ExchangePrice::from)
+                .map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("line {}: {e}", index + 1),
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Send `updates` into `tx`, pacing them according to `speed`.
+///
+/// Send times are anchored to the first update so sleep overshoot doesn't accumulate.
+/// Returns how many updates were sent before the input ran out or the receiver closed.
+pub async fn replay_updates<I>(
+    updates: I,
+    speed: ReplaySpeed,
+    tx: &mpsc::Sender<ExchangePrice>,
+) -> usize
+where
+    I: IntoIterator<Item = ExchangePrice>,
+{
+    let started = Instant::now();
+    let mut first_received_at = None;
+    let mut sent = 0;
+
+    for update in updates {
+        let received_at = update.received_at();
+        let first = *first_received_at.get_or_insert(received_at);
+        if let Some(offset) = speed.offset(received_at.saturating_sub(first)) {
+            sleep_until(started + offset).await;
+        }
+
+        if tx.send(update).await.is_err() {
+            break;
+        }
+        sent += 1;
+    }
+
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Exchange, Side};
+
+    fn frames() -> Vec<ExchangePrice> {
+        vec![
+            ExchangePrice::new(Exchange::Binance, 100, 1, Side::Buy).with_timestamps(0, 10_000),
+            ExchangePrice::new(Exchange::Binance, 101, 1, Side::Buy).with_timestamps(0, 11_000),
+        ]
+    }
+
+    async fn replay_elapsed(speed: ReplaySpeed) -> Duration {
+        let (tx, mut rx) = mpsc::channel(8);
+        let started = Instant::now();
+        assert_eq!(replay_updates(frames(), speed, &tx).await, 2);
+        let elapsed = started.elapsed();
+        assert_eq!(rx.recv().await.unwrap().received_at(), 10_000);
+        assert_eq!(rx.recv().await.unwrap().received_at(), 11_000);
+        elapsed
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn realtime_replay_follows_recorded_gap() {
+        let elapsed = replay_elapsed(ReplaySpeed::Realtime).await;
+        assert!(elapsed >= Duration::from_millis(1_000));
+        assert!(elapsed < Duration::from_millis(1_050));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn multiplier_scales_recorded_gap() {
+        let elapsed = replay_elapsed(ReplaySpeed::Multiplier(4.0)).await;
+        assert!(elapsed >= Duration::from_millis(250));
+        assert!(elapsed < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn parses_speeds() {
+        assert_eq!("max".parse(), Ok(ReplaySpeed::Max));
+        assert_eq!("realtime".parse(), Ok(ReplaySpeed::Realtime));
+        assert_eq!("2x".parse(), Ok(ReplaySpeed::Multiplier(2.0)));
+        assert_eq!("0.5".parse(), Ok(ReplaySpeed::Multiplier(0.5)));
+        assert!("0".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[test]
+    fn parses_recorded_lines_and_names_the_bad_one() {
+        let line = r#"{"pair":"BTC-USDT","exchange":"kraken","side":"buy","price":100,"quantity":2,"exchange_timestamp":0,"received_at":5}"#;
+        let updates = parse_updates(&format!("{line}\n\n{line}\n")).unwrap();
+        assert_eq!(updates.len(), 2);
+        assert!(matches!(updates[0], ExchangePrice::Kraken { .. }));
+        assert_eq!(updates[1].pair(), "BTC-USDT");
+
+        let err = parse_updates(&format!("{line}\n{{\"pair\":1}}\n")).unwrap_err();
+        assert!(err.to_string().starts_with("line 2:"), "{err}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_speed_does_not_sleep() {
+        let elapsed = replay_elapsed(ReplaySpeed::Max).await;
+        assert!(elapsed < Duration::from_millis(1));
+    }
+}
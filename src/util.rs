@@ -5,12 +5,12 @@ use std::io::BufWriter;
 use std::time::Duration as StdDuration;
 use tracing_subscriber::prelude::*;
 
-/// Sets up tracing: tokio-console (with tokio/runtime at TRACE), fmt layer (stdout),
-/// and optionally a flame layer when `TRACING_FLAME=1`. Returns a guard that must be
-/// held until process exit so the flamegraph file is flushed; callers should
-/// `let _guard = util::setup_config();`.
+/// Sets up tracing: tokio-console (with tokio/runtime at TRACE), fmt layer (stderr, so stdout
+/// only carries the JSON snapshots), and optionally a flame layer when `TRACING_FLAME=1`.
+/// Returns a guard that must be held until process exit so the flamegraph file is flushed;
+/// callers should `let _guard = util::setup_config();`.
 pub fn setup_config() -> Option<tracing_flame::FlushGuard<BufWriter<std::fs::File>>> {
-    // Tokio/runtime at TRACE for the console; keep them off stdout.
+    // Tokio/runtime at TRACE for the console; keep them out of the log output.
     let filter = tracing_subscriber::EnvFilter::from_default_env()
         .add_directive("tokio=trace".parse().expect("tokio directive"))
         .add_directive("runtime=trace".parse().expect("runtime directive"));
@@ -33,15 +33,13 @@ pub fn setup_config() -> Option<tracing_flame::FlushGuard<BufWriter<std::fs::Fil
         .retention(StdDuration::from_secs(3600))
         .spawn();
 
-    let (flame_layer, flame_guard) = match env::var("TRACING_FLAME") {
+    // The subscriber is not installed yet, so a failure here is logged once it is.
+    let (flame_layer, flame_guard, flame_error) = match env::var("TRACING_FLAME") {
         Ok(_) => match tracing_flame::FlameLayer::with_file("./flamegraph.folded") {
-            Ok((layer, guard)) => (Some(layer), Some(guard)),
-            Err(e) => {
-                eprintln!("TRACING_FLAME: could not create flamegraph file: {e}");
-                (None, None)
-            }
+            Ok((layer, guard)) => (Some(layer), Some(guard), None),
+            Err(e) => (None, None, Some(e)),
         },
-        Err(_) => (None, None),
+        Err(_) => (None, None, None),
     };
 
     if let Some(flame) = flame_layer {
@@ -51,6 +49,7 @@ pub fn setup_config() -> Option<tracing_flame::FlushGuard<BufWriter<std::fs::Fil
             .with(flame)
             .with(
                 tracing_subscriber::fmt::layer()
+                    .with_writer(std::io::stderr)
                     .with_target(false)
                     .with_filter(fmt_filter),
             )
@@ -61,76 +60,464 @@ pub fn setup_config() -> Option<tracing_flame::FlushGuard<BufWriter<std::fs::Fil
             .with(console_layer)
             .with(
                 tracing_subscriber::fmt::layer()
+                    .with_writer(std::io::stderr)
                     .with_target(false)
                     .with_filter(fmt_filter),
             )
             .init();
     }
 
+    if let Some(e) = flame_error {
+        tracing::warn!(error = %e, "TRACING_FLAME: could not create flamegraph file");
+    }
     flame_guard
 }
 
-/// Parse a decimal price string into cents (2 decimal places).
-/// Returns `None` if the string cannot be parsed.
-pub fn parse_price_cents(s: &str) -> Option<u64> {
-    let mut parts = s.split('.');
-    let int_part = parts.next()?;
-    let frac_part = parts.next();
+/// Read `key` from the environment and parse it, falling back to `default` when unset.
+/// Values that fail to parse are reported and also fall back to `default`.
+pub fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(raw) => match raw.trim().parse() {
+            Ok(value) => value,
+            Err(_) => {
+                tracing::warn!(key, value = %raw, "invalid value; using the default");
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// Read the on/off flag `key` from the environment: `true`/`1` or `false`/`0` (any case),
+/// falling back to `default` when unset. Other values are reported and also fall back.
+pub fn env_flag(key: &str, default: bool) -> bool {
+    match env::var(key) {
+        Ok(raw) => parse_flag(&raw).unwrap_or_else(|| {
+            tracing::warn!(key, value = %raw, "invalid flag; using the default");
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// `true`/`1` or `false`/`0`, ignoring case and surrounding whitespace.
+fn parse_flag(raw: &str) -> Option<bool> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Current wall-clock time as milliseconds since the Unix epoch.
+/// Every client stamps `received_at` with this so latencies are comparable across venues.
+pub fn current_timestamp_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Parse an RFC 3339 timestamp such as "2019-08-14T20:42:27.265Z" into epoch milliseconds.
+/// Digits past milliseconds are dropped. Returns `None` for malformed or pre-1970 input.
+pub fn parse_rfc3339_ms(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (date, time) = s.split_once(['T', 't', ' '])?;
 
-    // More than one '.' is considered invalid
-    if parts.next().is_some() {
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = parse_digits(date_parts.next()?, 4)?;
+    let month: i64 = parse_digits(date_parts.next()?, 2)?;
+    let day: i64 = parse_digits(date_parts.next()?, 2)?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
         return None;
     }
 
-    let int_val: u64 = int_part.parse().ok()?;
+    // Split off the zone: "Z" or "+HH:MM" / "-HH:MM".
+    let (clock, offset_secs) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let idx = time.rfind(['+', '-'])?;
+        let (clock, zone) = time.split_at(idx);
+        let sign = if zone.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = zone[1..].split_once(':')?;
+        let offset = parse_digits(hours, 2)? * 3600 + parse_digits(minutes, 2)? * 60;
+        (clock, sign * offset)
+    };
 
-    let frac_val = if let Some(frac) = frac_part {
-        let mut frac = frac.to_string();
-        // We only care about 2 decimal places for "cents"
-        if frac.len() > 2 {
-            frac.truncate(2);
-        } else {
-            while frac.len() < 2 {
-                frac.push('0');
+    let (hms, frac) = match clock.split_once('.') {
+        Some((hms, frac)) => (hms, Some(frac)),
+        None => (clock, None),
+    };
+    let mut hms_parts = hms.splitn(3, ':');
+    let hour: i64 = parse_digits(hms_parts.next()?, 2)?;
+    let minute: i64 = parse_digits(hms_parts.next()?, 2)?;
+    let second: i64 = parse_digits(hms_parts.next()?, 2)?;
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let millis = match frac {
+        Some(frac) if !frac.is_empty() && frac.bytes().all(|b| b.is_ascii_digit()) => {
+            let mut ms = frac[..frac.len().min(3)].to_string();
+            while ms.len() < 3 {
+                ms.push('0');
             }
+            ms.parse::<i64>().ok()?
         }
-        frac.parse::<u64>().ok()?
-    } else {
-        0
+        Some(_) => return None,
+        None => 0,
     };
 
-    int_val.checked_mul(100)?.checked_add(frac_val)
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_secs;
+    u64::try_from(secs * 1000 + millis).ok()
+}
+
+/// Parse exactly `len` ASCII digits.
+fn parse_digits(s: &str, len: usize) -> Option<i64> {
+    if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Days in `month` (1-12) of `year`, with February's leap day in Gregorian leap years.
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Parse a decimal price string into an integer count of `10^-decimals` units,
+/// e.g. "0.00001234" with 8 decimals becomes 1234 and "101.5" with 2 becomes 10150.
+/// Digits beyond `decimals` are truncated.
+pub fn parse_price_scaled(s: &str, decimals: u32) -> Result<u64, ParseError> {
+    // Same fixed-point conversion as quantities, just at the quote asset's scale.
+    parse_quantity_smallest_unit(s, decimals)
 }
 
 /// Parse a decimal quantity string into the smallest unit given by `decimals`.
 /// For example, with `decimals = 8`, "0.00000001" becomes 1.
-pub fn parse_quantity_smallest_unit(s: &str, decimals: u32) -> Option<u64> {
-    let mut parts = s.split('.');
-    let int_part = parts.next()?;
-    let frac_part = parts.next();
+/// Digits beyond `decimals` are silently truncated; see `parse_quantity_exact` to detect that.
+pub fn parse_quantity_smallest_unit(s: &str, decimals: u32) -> Result<u64, ParseError> {
+    parse_fixed_point(s, decimals).map(|(value, _)| value)
+}
 
-    // More than one '.' is considered invalid
-    if parts.next().is_some() {
-        return None;
+/// Why a price or quantity string was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// Not a plain unsigned decimal number (surrounding whitespace is allowed).
+    InvalidFormat,
+    /// Valid, but non-zero digits beyond `decimals` would be dropped; `truncated` is what
+    /// the lenient parser returns. Only `parse_quantity_exact` reports this.
+    Truncated { truncated: u64 },
+    /// Does not fit in a `u64` at this scale.
+    Overflow,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidFormat => write!(f, "not a decimal number"),
+            ParseError::Truncated { truncated } => {
+                write!(
+                    f,
+                    "more precision than supported (truncates to {truncated})"
+                )
+            }
+            ParseError::Overflow => write!(f, "value out of range"),
+        }
     }
+}
 
-    let int_val: u64 = int_part.parse().ok()?;
+impl std::error::Error for ParseError {}
 
-    let scale = 10u64.checked_pow(decimals)?;
+impl ParseError {
+    /// Short label for metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ParseError::InvalidFormat => "invalid_format",
+            ParseError::Truncated { .. } => "truncated",
+            ParseError::Overflow => "overflow",
+        }
+    }
+}
 
-    let frac_val = if let Some(frac) = frac_part {
-        let mut frac = frac.to_string();
-        if frac.len() > decimals as usize {
-            frac.truncate(decimals as usize);
+/// Like `parse_quantity_smallest_unit`, but fails instead of dropping precision.
+/// Trailing zeros beyond `decimals` are not a loss and are accepted.
+pub fn parse_quantity_exact(s: &str, decimals: u32) -> Result<u64, ParseError> {
+    match parse_fixed_point(s, decimals)? {
+        (value, false) => Ok(value),
+        (truncated, true) => Err(ParseError::Truncated { truncated }),
+    }
+}
+
+/// Fixed-point conversion shared by the parsers: the value in `10^-decimals` units, and
+/// whether non-zero digits beyond `decimals` were dropped.
+///
+/// Accepts plain decimals ("0.00012") and scientific notation ("1.2e-4", "1E+3"), which
+/// some feeds use for very small sizes.
+fn parse_fixed_point(s: &str, decimals: u32) -> Result<(u64, bool), ParseError> {
+    let s = s.trim();
+    let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, parse_exponent(exponent)?),
+        None => (s, 0),
+    };
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    // Digits only: no signs, so "-1" and "+1" are both rejected.
+    if int_part.is_empty()
+        || !int_part.bytes().all(|b| b.is_ascii_digit())
+        || !frac_part.bytes().all(|b| b.is_ascii_digit())
+    {
+        return Err(ParseError::InvalidFormat);
+    }
+
+    // Shift the decimal point right by the exponent plus `decimals`; digits left of it
+    // form the result, digits right of it are dropped.
+    let digits = int_part.bytes().chain(frac_part.bytes());
+    let len = (int_part.len() + frac_part.len()) as i64;
+    let point = int_part.len() as i64 + exponent + decimals as i64;
+    let kept = point.clamp(0, len) as usize;
+
+    let mut value: u64 = 0;
+    let mut truncated = false;
+    for (i, digit) in digits.enumerate() {
+        let digit = u64::from(digit - b'0');
+        if i < kept {
+            value = value
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(digit))
+                .ok_or(ParseError::Overflow)?;
         } else {
-            while frac.len() < decimals as usize {
-                frac.push('0');
-            }
+            truncated |= digit != 0;
         }
-        frac.parse::<u64>().ok()?
+    }
+
+    // Pad with zeros when the point lands past the last digit.
+    let padding = (point - len).max(0);
+    if value != 0 && padding > 0 {
+        let scale = u32::try_from(padding)
+            .ok()
+            .and_then(|p| 10u64.checked_pow(p))
+            .ok_or(ParseError::Overflow)?;
+        value = value.checked_mul(scale).ok_or(ParseError::Overflow)?;
+    }
+    Ok((value, truncated))
+}
+
+/// Parse the exponent of scientific notation: optional sign, then at least one digit.
+fn parse_exponent(s: &str) -> Result<i64, ParseError> {
+    let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseError::InvalidFormat);
+    }
+    // Anything this large would overflow (or round to zero) anyway.
+    let magnitude: i64 = digits.parse().map_err(|_| ParseError::Overflow)?;
+    if magnitude > 1_000 {
+        return Err(ParseError::Overflow);
+    }
+    Ok(if s.starts_with('-') {
+        -magnitude
     } else {
-        0
-    };
+        magnitude
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_accept_words_and_digits() {
+        for raw in ["true", "TRUE", "1", " True "] {
+            assert_eq!(parse_flag(raw), Some(true), "{raw:?}");
+        }
+        for raw in ["false", "False", "0"] {
+            assert_eq!(parse_flag(raw), Some(false), "{raw:?}");
+        }
+        assert_eq!(parse_flag("yes"), None);
+        assert_eq!(parse_flag(""), None);
+    }
+
+    #[test]
+    fn parses_prices_at_any_scale() {
+        assert_eq!(parse_price_scaled("101.5", 2), Ok(10_150));
+        assert_eq!(parse_price_scaled("101.567", 2), Ok(10_156));
+        // Microcap prices survive instead of collapsing to 0 cents.
+        assert_eq!(parse_price_scaled("0.00001234", 2), Ok(0));
+        assert_eq!(parse_price_scaled("0.00001234", 8), Ok(1_234));
+        assert_eq!(
+            parse_price_scaled("1.2.3", 8),
+            Err(ParseError::InvalidFormat)
+        );
+        assert_eq!(
+            parse_price_scaled("99999999999999999999", 2),
+            Err(ParseError::Overflow)
+        );
+    }
+
+    #[test]
+    fn lenient_quantity_parsing_truncates() {
+        assert_eq!(parse_quantity_smallest_unit("0.00000001", 8), Ok(1));
+        assert_eq!(
+            parse_quantity_smallest_unit("0.123456789", 8),
+            Ok(12_345_678)
+        );
+        assert_eq!(
+            parse_quantity_smallest_unit("1.2.3", 8),
+            Err(ParseError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn quantity_parsing_edge_cases() {
+        let parse = |s| parse_quantity_smallest_unit(s, 8);
+        // Surrounding whitespace is tolerated, inner whitespace is not.
+        assert_eq!(parse(" 1.5 "), Ok(150_000_000));
+        assert_eq!(parse("1 .5"), Err(ParseError::InvalidFormat));
+        // Empty fractional part means zero; an empty integer part is rejected.
+        assert_eq!(parse("1."), Ok(100_000_000));
+        assert_eq!(parse(".5"), Err(ParseError::InvalidFormat));
+        assert_eq!(parse(""), Err(ParseError::InvalidFormat));
+        // Signs are rejected either way.
+        assert_eq!(parse("-1"), Err(ParseError::InvalidFormat));
+        assert_eq!(parse("+1"), Err(ParseError::InvalidFormat));
+        assert_eq!(parse("1.-5"), Err(ParseError::InvalidFormat));
+        // Overflow of the integer part or of the scaled value.
+        assert_eq!(parse("99999999999999999999"), Err(ParseError::Overflow));
+        assert_eq!(parse("200000000000"), Err(ParseError::Overflow));
+    }
+
+    #[test]
+    fn parses_scientific_notation() {
+        assert_eq!(parse_quantity_smallest_unit("1e-8", 8), Ok(1));
+        assert_eq!(parse_quantity_smallest_unit("1.5E-5", 8), Ok(1_500));
+        assert_eq!(
+            parse_quantity_smallest_unit("1.23e2", 8),
+            Ok(12_300_000_000)
+        );
+        assert_eq!(parse_quantity_smallest_unit("1E+3", 0), Ok(1_000));
+        assert_eq!(parse_price_scaled("2.5e-1", 2), Ok(25));
+        // Below one smallest unit: rounds down to zero, which strict parsing reports.
+        assert_eq!(parse_quantity_smallest_unit("1e-9", 8), Ok(0));
+        assert_eq!(
+            parse_quantity_exact("1e-9", 8),
+            Err(ParseError::Truncated { truncated: 0 })
+        );
+        assert_eq!(
+            parse_quantity_smallest_unit("1e30", 8),
+            Err(ParseError::Overflow)
+        );
+        for bad in ["1e", "e5", "1e+", "1e-x", "1ee5", "1e5.0", "-1e5", "1e 5"] {
+            assert_eq!(
+                parse_quantity_smallest_unit(bad, 8),
+                Err(ParseError::InvalidFormat),
+                "{bad}"
+            );
+        }
+    }
 
-    int_val.checked_mul(scale)?.checked_add(frac_val)
+    #[test]
+    fn exact_quantity_parsing_reports_loss() {
+        assert_eq!(parse_quantity_exact("0.12345678", 8), Ok(12_345_678));
+        // Trailing zeros beyond the scale lose nothing.
+        assert_eq!(parse_quantity_exact("0.1234567800", 8), Ok(12_345_678));
+        assert_eq!(
+            parse_quantity_exact("0.123456789", 8),
+            Err(ParseError::Truncated {
+                truncated: 12_345_678
+            })
+        );
+        assert_eq!(
+            parse_quantity_exact("abc", 8),
+            Err(ParseError::InvalidFormat)
+        );
+        assert_eq!(
+            parse_quantity_exact("1.x", 8),
+            Err(ParseError::InvalidFormat)
+        );
+        // u64::MAX / 10^8 is about 1.8e11 whole units.
+        assert_eq!(
+            parse_quantity_exact("200000000000", 8),
+            Err(ParseError::Overflow)
+        );
+        assert_eq!(
+            parse_quantity_exact("99999999999999999999", 0),
+            Err(ParseError::Overflow)
+        );
+    }
+
+    #[test]
+    fn parses_rfc3339_timestamps() {
+        assert_eq!(parse_rfc3339_ms("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_rfc3339_ms("2019-08-14T20:42:27.265Z"),
+            Some(1_565_815_347_265)
+        );
+        // Nanosecond precision is cut to milliseconds.
+        assert_eq!(
+            parse_rfc3339_ms("2023-02-09T20:32:50.714964855Z"),
+            Some(1_675_974_770_714)
+        );
+        assert_eq!(
+            parse_rfc3339_ms("2019-08-14T22:42:27.2+02:00"),
+            Some(1_565_815_347_200)
+        );
+        assert_eq!(
+            parse_rfc3339_ms("2024-02-29T00:00:00Z"),
+            Some(1_709_164_800_000)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_rfc3339() {
+        for bad in [
+            "",
+            "not a time",
+            "2019-08-14",
+            "2019-13-14T20:42:27Z",
+            "2019-08-14T20:42:27",
+            "2019-08-14T20:42:27.Z",
+            "2019-8-14T20:42:27Z",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert_eq!(parse_rfc3339_ms(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn rejects_days_past_the_end_of_the_month() {
+        for bad in [
+            "2024-02-31T00:00:00Z",
+            "2024-02-30T00:00:00Z",
+            "2023-02-29T00:00:00Z",
+            "1900-02-29T00:00:00Z",
+            "2024-04-31T00:00:00Z",
+            "2024-11-31T00:00:00Z",
+        ] {
+            assert_eq!(parse_rfc3339_ms(bad), None, "{bad}");
+        }
+        // Leap days in leap years, including a century divisible by 400, and month ends.
+        for good in [
+            "2024-02-29T00:00:00Z",
+            "2000-02-29T00:00:00Z",
+            "2024-04-30T00:00:00Z",
+            "2024-12-31T00:00:00Z",
+        ] {
+            assert!(parse_rfc3339_ms(good).is_some(), "{good}");
+        }
+    }
 }